@@ -0,0 +1,38 @@
+//! Second-order IIR filter section, the building block of every filter the
+//! engine runs on decoded audio.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Coefficients {
+    pub b0: f64,
+    pub b1: f64,
+    pub b2: f64,
+    pub a1: f64,
+    pub a2: f64
+}
+
+/// One filter section with its own history, so one instance per channel.
+#[derive(Clone, Copy)]
+pub struct Biquad {
+    pub coefficients: Coefficients,
+    z1: f64,
+    z2: f64
+}
+
+impl Biquad {
+    pub fn new(coefficients: Coefficients) -> Self {
+        Self {
+            coefficients,
+            z1: 0.0,
+            z2: 0.0
+        }
+    }
+
+    /// Filters one sample (transposed direct form II).
+    pub fn process(&mut self, x: f64) -> f64 {
+        let c = &self.coefficients;
+        let y = c.b0 * x + self.z1;
+        self.z1 = c.b1 * x - c.a1 * y + self.z2;
+        self.z2 = c.b2 * x - c.a2 * y;
+        y
+    }
+}
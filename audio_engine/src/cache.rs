@@ -0,0 +1,381 @@
+//! On-disk cache of finished downloads, shared by every mixer process.
+//!
+//! A track that streamed to its end is kept as the PCM the engine plays
+//! (s16le, 48 kHz stereo), so loading it again reads the file instead of
+//! running yt-dlp and the decoder. Entries are plain files named after the
+//! track, with what the source knew about it (title, chapters...) in a JSON
+//! sidecar of the same name: the least recently played ones are deleted,
+//! sidecar included, once the directory grows past its cap. A track is
+//! written under a temporary name and renamed when complete, so a reader
+//! never picks up half a song.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::{get_track_cache_dir, get_track_cache_max_mb};
+use crate::protocol::{send_log, TrackInfo};
+
+const ENTRY_EXTENSION: &str = "pcm";
+const INFO_EXTENSION: &str = "json";
+const PART_EXTENSION: &str = "part";
+
+/// A temporary file this old belongs to a process that died mid-download.
+const STALE_PART_AGE: Duration = Duration::from_secs(24 * 3600);
+
+/// Name a track is stored under: the video ID for YouTube links, so the same
+/// song shared through different URLs is one entry, a hash of the URL for
+/// everything else.
+pub fn key_for(url: &str) -> String {
+    match youtube_id(url) {
+        Some(id) => format!("yt-{}", id),
+        None => format!("url-{:016x}", fnv1a(url.as_bytes()))
+    }
+}
+
+fn youtube_id(url: &str) -> Option<&str> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))?;
+    let (host, path) = rest.split_once('/')?;
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    let candidate = match host {
+        "youtu.be" => path.split(['?', '#']).next(),
+        "youtube.com" | "m.youtube.com" | "music.youtube.com" => {
+            if let Some(id) = path.strip_prefix("shorts/") {
+                id.split(['?', '#', '/']).next()
+            } else {
+                let query = path.strip_prefix("watch?")?;
+                query
+                    .split(['&', '#'])
+                    .find_map(|pair| pair.strip_prefix("v="))
+            }
+        }
+        _ => None
+    }?;
+    let valid = candidate.len() == 11
+        && candidate
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    valid.then_some(candidate)
+}
+
+/// 64-bit FNV-1a: stable across builds, unlike the std hasher, so entries
+/// survive an engine upgrade.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[derive(Clone)]
+pub struct TrackCache {
+    dir: PathBuf,
+    max_bytes: u64
+}
+
+impl TrackCache {
+    /// The cache described by the environment, or None when it is turned off.
+    pub fn from_config() -> Option<Self> {
+        let max_mb = get_track_cache_max_mb();
+        if max_mb == 0 {
+            return None;
+        }
+        Some(Self {
+            dir: get_track_cache_dir(),
+            max_bytes: max_mb * 1024 * 1024
+        })
+    }
+
+    /// An unbounded cache in `dir`, for tests of the code storing into it.
+    #[cfg(test)]
+    pub fn at(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            max_bytes: u64::MAX
+        }
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", key, ENTRY_EXTENSION))
+    }
+
+    fn info_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", key, INFO_EXTENSION))
+    }
+
+    /// What was known about `url` when it was stored, if anything.
+    pub fn info(&self, url: &str) -> Option<TrackInfo> {
+        let raw = fs::read(self.info_path(&key_for(url))).ok()?;
+        serde_json::from_slice(&raw).ok()
+    }
+
+    /// Opens the stored PCM of `url`, marking it as recently played.
+    pub fn open(&self, url: &str) -> Option<File> {
+        let path = self.entry_path(&key_for(url));
+        let file = File::open(&path).ok()?;
+        // The modification time is the LRU clock: eviction reads it back
+        if let Ok(touch) = File::options().append(true).open(&path) {
+            let _ = touch.set_modified(SystemTime::now());
+        }
+        Some(file)
+    }
+
+    /// Starts storing a download of `url`. Returns None when the cache
+    /// directory cannot be written to.
+    pub fn writer(&self, url: &str) -> Option<CacheWriter> {
+        if let Err(e) = fs::create_dir_all(&self.dir) {
+            send_log(
+                "error",
+                &format!("Track cache: cannot create {}: {}", self.dir.display(), e)
+            );
+            return None;
+        }
+        let key = key_for(url);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or(0);
+        let part = self.dir.join(format!(
+            "{}.{}-{}.{}",
+            key,
+            std::process::id(),
+            nanos,
+            PART_EXTENSION
+        ));
+        let file = File::create(&part).ok()?;
+        Some(CacheWriter {
+            file: BufWriter::new(file),
+            path: self.entry_path(&key),
+            info_path: self.info_path(&key),
+            part,
+            cache: self.clone(),
+            failed: false
+        })
+    }
+
+    /// Deletes the least recently played entries until the cache fits its cap,
+    /// along with temporary files left behind by dead processes.
+    fn evict(&self) {
+        let Ok(dir) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let now = SystemTime::now();
+        let mut entries = Vec::new();
+        let mut infos = Vec::new();
+        let mut total = 0;
+        for entry in dir.flatten() {
+            let path = entry.path();
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            let modified = meta.modified().unwrap_or(UNIX_EPOCH);
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some(ENTRY_EXTENSION) => {
+                    total += meta.len();
+                    entries.push((modified, meta.len(), path));
+                }
+                Some(INFO_EXTENSION) => infos.push(path),
+                Some(PART_EXTENSION) => {
+                    let age = now.duration_since(modified).unwrap_or_default();
+                    if age > STALE_PART_AGE {
+                        let _ = fs::remove_file(&path);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        entries.sort_by_key(|(modified, _, _)| *modified);
+        for (_, len, path) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                total -= len;
+                let _ = fs::remove_file(path.with_extension(INFO_EXTENSION));
+                send_log("debug", &format!("Track cache: evicted {}", path.display()));
+            }
+        }
+        // Sidecars whose track is gone, e.g. deleted by hand
+        for info in infos {
+            if !info.with_extension(ENTRY_EXTENSION).exists() {
+                let _ = fs::remove_file(&info);
+            }
+        }
+    }
+}
+
+/// A track being stored while it downloads. Dropping it without `commit`
+/// throws the partial file away.
+pub struct CacheWriter {
+    file: BufWriter<File>,
+    part: PathBuf,
+    path: PathBuf,
+    info_path: PathBuf,
+    cache: TrackCache,
+    /// A write failed (e.g. disk full): the rest of the track is not stored.
+    failed: bool
+}
+
+impl CacheWriter {
+    pub fn push(&mut self, sample: f32) {
+        if self.failed {
+            return;
+        }
+        let pcm = (sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16;
+        if let Err(e) = self.file.write_all(&pcm.to_le_bytes()) {
+            send_log("error", &format!("Track cache: write failed: {}", e));
+            self.failed = true;
+        }
+    }
+
+    /// Publishes the complete track with `info` as its sidecar, then trims
+    /// the cache back under its cap. A track whose info cannot be written is
+    /// still stored: its length can be told from the file.
+    pub fn commit(mut self, info: Option<&TrackInfo>) -> io::Result<()> {
+        if self.failed {
+            return Err(io::Error::other("incomplete write"));
+        }
+        self.file.flush()?;
+        fs::rename(&self.part, &self.path)?;
+        if let Err(e) = self.store_info(info) {
+            send_log("error", &format!("Track cache: track info not stored: {}", e));
+        }
+        self.cache.evict();
+        Ok(())
+    }
+
+    fn store_info(&self, info: Option<&TrackInfo>) -> io::Result<()> {
+        let Some(info) = info else {
+            // What a previous copy of the track left must not outlive it
+            let _ = fs::remove_file(&self.info_path);
+            return Ok(());
+        };
+        let part = self
+            .part
+            .with_extension(format!("{}.{}", INFO_EXTENSION, PART_EXTENSION));
+        let stored = fs::write(&part, serde_json::to_vec(info)?)
+            .and_then(|_| fs::rename(&part, &self.info_path));
+        if stored.is_err() {
+            let _ = fs::remove_file(&part);
+        }
+        stored
+    }
+}
+
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        // Already gone after a successful commit
+        let _ = fs::remove_file(&self.part);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::PcmStream;
+    use crate::protocol::Chapter;
+    use std::io::BufReader;
+
+    fn temp_cache(name: &str, max_bytes: u64) -> TrackCache {
+        let dir = std::env::temp_dir().join(format!("mixer-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        TrackCache { dir, max_bytes }
+    }
+
+    #[test]
+    fn youtube_links_share_one_key() {
+        let key = key_for("https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=RD1");
+        assert_eq!(key, "yt-dQw4w9WgXcQ");
+        assert_eq!(key_for("https://youtu.be/dQw4w9WgXcQ?t=42"), key);
+        assert_eq!(key_for("https://music.youtube.com/watch?v=dQw4w9WgXcQ"), key);
+        assert_eq!(key_for("https://youtube.com/shorts/dQw4w9WgXcQ"), key);
+        assert!(key_for("https://example.com/song.mp3").starts_with("url-"));
+    }
+
+    #[test]
+    fn committed_tracks_read_back_as_played() {
+        let cache = temp_cache("roundtrip", u64::MAX);
+        let url = "https://example.com/a.mp3";
+        assert!(cache.open(url).is_none());
+
+        let mut writer = cache.writer(url).unwrap();
+        for sample in [0.5, -0.25, 1.0] {
+            writer.push(sample);
+        }
+        // Not visible until the download completes
+        assert!(cache.open(url).is_none());
+        writer.commit(None).unwrap();
+
+        let mut reader = BufReader::new(cache.open(url).unwrap());
+        assert_eq!(reader.next_sample().unwrap(), 0.5);
+        assert_eq!(reader.next_sample().unwrap(), -0.25);
+        assert!((reader.next_sample().unwrap() - 1.0).abs() < 1e-4);
+        let _ = fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn least_recently_played_tracks_are_evicted_first() {
+        let cache = temp_cache("lru", 8);
+        let store = |url: &str| {
+            let mut writer = cache.writer(url).unwrap();
+            writer.push(0.1);
+            writer.push(0.1);
+            let info = TrackInfo {
+                title: Some(url.to_string()),
+                ..TrackInfo::default()
+            };
+            writer.commit(Some(&info)).unwrap();
+        };
+        store("https://example.com/1.mp3");
+        store("https://example.com/2.mp3");
+        // Replaying 1 makes 2 the oldest
+        let old = SystemTime::now() - Duration::from_secs(60);
+        File::options()
+            .append(true)
+            .open(cache.entry_path(&key_for("https://example.com/2.mp3")))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        assert!(cache.open("https://example.com/1.mp3").is_some());
+
+        store("https://example.com/3.mp3");
+        assert!(cache.open("https://example.com/1.mp3").is_some());
+        assert!(cache.open("https://example.com/2.mp3").is_none());
+        assert!(cache.open("https://example.com/3.mp3").is_some());
+        // The sidecar goes with its track
+        assert!(cache.info("https://example.com/2.mp3").is_none());
+        assert!(cache.info("https://example.com/1.mp3").is_some());
+        let _ = fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn track_info_is_stored_next_to_the_track() {
+        let cache = temp_cache("info", u64::MAX);
+        let url = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+        let info = TrackInfo {
+            title: Some("Song".to_string()),
+            uploader: Some("Artist".to_string()),
+            duration_ms: Some(212_000),
+            thumbnail: Some("https://i.ytimg.com/vi/dQw4w9WgXcQ/hq.jpg".to_string()),
+            chapters: vec![Chapter {
+                title: "Intro".to_string(),
+                start_ms: 0,
+                end_ms: 15_000
+            }]
+        };
+        let mut writer = cache.writer(url).unwrap();
+        writer.push(0.5);
+        writer.commit(Some(&info)).unwrap();
+        assert_eq!(cache.info(url), Some(info));
+
+        // Stored again without info, the old info is not kept
+        let mut writer = cache.writer(url).unwrap();
+        writer.push(0.5);
+        writer.commit(None).unwrap();
+        assert!(cache.info(url).is_none());
+        let _ = fs::remove_dir_all(&cache.dir);
+    }
+}
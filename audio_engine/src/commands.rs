@@ -1,32 +1,106 @@
 //! Handling of the commands Node.js sends over stdin.
 
 use crate::config::{CHANNELS, SAMPLE_RATE};
-use crate::protocol::{send_log, InputCommand};
-use crate::state::{deck_name, Crossfade, MixerState, PendingTransition};
+use crate::deck::SeekOutcome;
+use crate::eq::{preset, Gains, BANDS};
+use crate::gain::MAX_VOLUME;
+use crate::overlay::MAX_CLIPS;
+use crate::protocol::{
+    emit, protocol_version, send_log, set_protocol_version, AckStatus, DeckChangeTrigger,
+    InputCommand, MalformedRequest, OutputEvent, RejectReason, Request
+};
+use crate::state::{Crossfade, MixerState, OpenDeckError, PendingTransition, FIXED_DECKS};
 
 /// Outcome of a command: the mixer loop stops when Node.js asks it to.
 pub enum CommandOutcome {
     Continue,
+    /// Accepted, but only carried out once a download catches up.
+    Deferred,
     Shutdown
 }
 
+/// Why a command was not carried out, reported back to Node.js.
+#[derive(Debug)]
+pub struct Rejection {
+    pub reason: RejectReason,
+    pub detail: String
+}
+
+impl Rejection {
+    fn new(reason: RejectReason, detail: impl Into<String>) -> Self {
+        Self {
+            reason,
+            detail: detail.into()
+        }
+    }
+}
+
+/// Resolves a deck name coming from Node.js, rejecting the command when the
+/// engine has no such deck.
+fn known_deck(state: &MixerState, raw: &str) -> Result<&'static str, Rejection> {
+    state.deck_name(raw).ok_or_else(|| {
+        Rejection::new(RejectReason::UnknownDeck, format!("no deck named '{}'", raw))
+    })
+}
+
+/// Refusal of a command that needs the copy of a track too long to keep.
+fn replay_dropped(deck: &str) -> Rejection {
+    Rejection::new(
+        RejectReason::InvalidState,
+        format!("deck {} no longer keeps its track for replay, reload it", deck)
+    )
+}
+
 /// Converts a fade duration into the number of stereo samples it spans.
 fn crossfade_samples(duration_ms: u64) -> usize {
     (duration_ms as usize * SAMPLE_RATE / 1000) * CHANNELS
 }
 
-/// Applies one command from Node.js. Commands naming an unknown deck are
-/// ignored: `deck_name` is the single place deck identity is validated.
-pub fn apply_command(state: &mut MixerState, cmd: InputCommand) -> CommandOutcome {
+/// Applies one command from Node.js. A command that cannot be carried out
+/// leaves the state untouched and comes back as a `Rejection`; deck identity
+/// is validated in one place, `known_deck` (or `open_deck` for `load`, the
+/// only command that may create a deck).
+pub fn apply_command(
+    state: &mut MixerState,
+    cmd: InputCommand
+) -> Result<CommandOutcome, Rejection> {
     match cmd {
+        InputCommand::Hello {
+            protocol_version: requested
+        } => {
+            let accepted = set_protocol_version(requested);
+            if !accepted {
+                send_log(
+                    "error",
+                    &format!(
+                        "Protocol version {} not supported, keeping {}",
+                        requested,
+                        protocol_version()
+                    )
+                );
+            }
+            emit(OutputEvent::ProtocolSelected {
+                protocol_version: protocol_version(),
+                accepted
+            });
+        }
+
         InputCommand::Load {
             url,
             deck,
-            autoplay
+            autoplay,
+            live
         } => {
-            let Some(deck) = deck_name(&deck) else {
-                return CommandOutcome::Continue;
-            };
+            let deck = state.open_deck(&deck).map_err(|e| match e {
+                OpenDeckError::InvalidName => Rejection::new(
+                    RejectReason::InvalidValue,
+                    format!("'{}' is not a valid deck name", deck)
+                ),
+                OpenDeckError::LimitReached(max) => Rejection::new(
+                    RejectReason::InvalidState,
+                    format!("cannot open deck '{}': {} decks already open", deck, max)
+                )
+            })?;
 
             // Loading over the deck a crossfade is fading OUT of would pull the
             // source audio away mid-fade: finish the fade instantly instead.
@@ -38,13 +112,13 @@ pub fn apply_command(state: &mut MixerState, cmd: InputCommand) -> CommandOutcom
                 );
                 state.crossfade = None;
                 state.switch_to(target);
-                send_log(
-                    "deck_changed",
-                    &format!("deck={}, triggered_by=crossfade_snap", target)
-                );
+                emit(OutputEvent::DeckChanged {
+                    deck: target,
+                    trigger: DeckChangeTrigger::CrossfadeSnap
+                });
             }
 
-            state.deck_mut(deck).load(url);
+            state.deck_mut(deck).load(url, live);
             send_log(
                 "info",
                 &format!(
@@ -56,24 +130,20 @@ pub fn apply_command(state: &mut MixerState, cmd: InputCommand) -> CommandOutcom
         }
 
         InputCommand::Play { deck } => {
-            let Some(deck) = deck_name(&deck) else {
-                return CommandOutcome::Continue;
-            };
+            let deck = known_deck(state, &deck)?;
             state.crossfade = None;
             state.stall = None;
             state.is_playing = true;
             state.switch_to(deck);
             send_log("info", &format!("Play deck {}", deck));
-            send_log(
-                "deck_changed",
-                &format!("deck={}, triggered_by=play_command", deck)
-            );
+            emit(OutputEvent::DeckChanged {
+                deck,
+                trigger: DeckChangeTrigger::PlayCommand
+            });
         }
 
         InputCommand::StopDeck { deck } => {
-            let Some(deck) = deck_name(&deck) else {
-                return CommandOutcome::Continue;
-            };
+            let deck = known_deck(state, &deck)?;
             state.stall = None;
             send_log("debug", &format!("Stopping deck {}", deck));
 
@@ -90,13 +160,20 @@ pub fn apply_command(state: &mut MixerState, cmd: InputCommand) -> CommandOutcom
             duration_ms,
             to_deck
         } => {
-            let Some(target) = deck_name(&to_deck) else {
-                return CommandOutcome::Continue;
-            };
-            state.stall = None;
-            if target == state.active_deck || state.crossfade.is_some() {
-                return CommandOutcome::Continue;
+            let target = known_deck(state, &to_deck)?;
+            if target == state.active_deck {
+                return Err(Rejection::new(
+                    RejectReason::InvalidState,
+                    format!("deck {} is already the active deck", target)
+                ));
+            }
+            if let Some(running) = state.crossfade.as_ref() {
+                return Err(Rejection::new(
+                    RejectReason::InvalidState,
+                    format!("crossfade to deck {} already running", running.target)
+                ));
             }
+            state.stall = None;
 
             state.deck_mut(target).poll_receiver();
             if state.is_ready(target) || state.download_complete(target) {
@@ -105,10 +182,10 @@ pub fn apply_command(state: &mut MixerState, cmd: InputCommand) -> CommandOutcom
                 state.is_playing = true;
                 state.pending = None;
                 let total = crossfade_samples(duration_ms);
-                send_log(
-                    "crossfade_started",
-                    &format!("from={}, to={}", state.active_deck, target)
-                );
+                emit(OutputEvent::CrossfadeStarted {
+                    from: state.active_deck,
+                    to: target
+                });
                 state.crossfade = Some(Crossfade {
                     target,
                     total,
@@ -125,6 +202,7 @@ pub fn apply_command(state: &mut MixerState, cmd: InputCommand) -> CommandOutcom
                     duration_ms
                 });
                 send_log("info", "⏳ Crossfade pending: target deck not ready");
+                return Ok(CommandOutcome::Deferred);
             }
         }
 
@@ -140,13 +218,14 @@ pub fn apply_command(state: &mut MixerState, cmd: InputCommand) -> CommandOutcom
         }
 
         InputCommand::SkipTo { target_deck } => {
-            let Some(target) = deck_name(&target_deck) else {
-                return CommandOutcome::Continue;
-            };
-            state.stall = None;
+            let target = known_deck(state, &target_deck)?;
             if target == state.active_deck {
-                return CommandOutcome::Continue;
+                return Err(Rejection::new(
+                    RejectReason::InvalidState,
+                    format!("deck {} is already the active deck", target)
+                ));
             }
+            state.stall = None;
 
             send_log(
                 "info",
@@ -155,7 +234,7 @@ pub fn apply_command(state: &mut MixerState, cmd: InputCommand) -> CommandOutcom
             state.deck_mut(target).poll_receiver();
 
             if state.is_ready(target) || state.download_complete(target) {
-                send_log("buffer_ready", target);
+                emit(OutputEvent::BufferReady { deck: target });
 
                 let old = state.active_deck;
                 state.reset_deck(old);
@@ -165,10 +244,10 @@ pub fn apply_command(state: &mut MixerState, cmd: InputCommand) -> CommandOutcom
                 state.switch_to(target);
 
                 send_log("info", &format!("⚡ Immediate skip → deck {}", target));
-                send_log(
-                    "deck_changed",
-                    &format!("deck={}, triggered_by=skip_command", target)
-                );
+                emit(OutputEvent::DeckChanged {
+                    deck: target,
+                    trigger: DeckChangeTrigger::SkipCommand
+                });
             } else {
                 // Target not ready: keep playing the current deck for now.
                 state.pending = Some(PendingTransition {
@@ -184,6 +263,100 @@ pub fn apply_command(state: &mut MixerState, cmd: InputCommand) -> CommandOutcom
                         target
                     )
                 );
+                return Ok(CommandOutcome::Deferred);
+            }
+        }
+
+        InputCommand::SetEq {
+            deck,
+            preset: preset_name,
+            bands
+        } => {
+            let gains: Gains = match (bands, preset_name.as_deref()) {
+                (Some(bands), _) => {
+                    std::array::from_fn(|i| bands.get(i).copied().unwrap_or(0.0))
+                }
+                (None, Some(name)) => match preset(name) {
+                    Some(gains) => gains,
+                    None => {
+                        return Err(Rejection::new(
+                            RejectReason::InvalidValue,
+                            format!("unknown EQ preset '{}'", name)
+                        ));
+                    }
+                },
+                (None, None) => [0.0; BANDS]
+            };
+
+            let (target, applied) = match deck {
+                Some(deck) => {
+                    let deck = known_deck(state, &deck)?;
+                    let eq = &mut state.deck_mut(deck).eq;
+                    eq.set_gains(gains);
+                    (deck, *eq.gains())
+                }
+                None => {
+                    state.master_eq.set_gains(gains);
+                    ("master", *state.master_eq.gains())
+                }
+            };
+            emit(OutputEvent::EqChanged {
+                target,
+                gains_db: applied.to_vec()
+            });
+        }
+
+        InputCommand::SetPlaybackRate {
+            deck,
+            rate,
+            preserve_pitch
+        } => {
+            if !rate.is_finite() {
+                return Err(Rejection::new(RejectReason::InvalidValue, "rate is not a number"));
+            }
+            let decks = match deck {
+                Some(deck) => vec![known_deck(state, &deck)?],
+                None => state.deck_names()
+            };
+            for deck in decks {
+                let processor = &mut state.deck_mut(deck).rate;
+                processor.set(rate, preserve_pitch);
+                emit(OutputEvent::PlaybackRateChanged {
+                    deck,
+                    rate: processor.rate(),
+                    preserve_pitch: processor.preserve_pitch()
+                });
+            }
+        }
+
+        InputCommand::Seek { deck, position_ms } => {
+            let deck = known_deck(state, &deck)?;
+            match state.deck_mut(deck).seek(position_ms) {
+                SeekOutcome::Done(position) => {
+                    emit(OutputEvent::DeckSeeked {
+                        deck,
+                        position_ms: position
+                    });
+                }
+                SeekOutcome::Pending => {
+                    send_log(
+                        "info",
+                        &format!(
+                            "⏳ Seek pending: deck {} has not downloaded {}ms yet",
+                            deck, position_ms
+                        )
+                    );
+                    return Ok(CommandOutcome::Deferred);
+                }
+                SeekOutcome::Unavailable => {
+                    return Err(Rejection::new(
+                        RejectReason::InvalidState,
+                        format!("deck {} holds no track", deck)
+                    ));
+                }
+                SeekOutcome::ReplayDropped => {
+                    return Err(replay_dropped(deck));
+                }
             }
         }
 
@@ -197,27 +370,251 @@ pub fn apply_command(state: &mut MixerState, cmd: InputCommand) -> CommandOutcom
             send_log("info", "Resumed all playback");
         }
 
+        InputCommand::SetVolume {
+            deck,
+            level,
+            ramp_ms
+        } => {
+            if !level.is_finite() {
+                return Err(Rejection::new(RejectReason::InvalidValue, "level is not a number"));
+            }
+            let level = level.clamp(0.0, MAX_VOLUME);
+            match deck {
+                Some(deck) => {
+                    let deck = known_deck(state, &deck)?;
+                    state.deck_mut(deck).volume.set(level, ramp_ms);
+                }
+                None => state.master_volume.set(level, ramp_ms)
+            }
+            emit(OutputEvent::VolumeChanged {
+                master: state.master_volume.level(),
+                decks: state
+                    .deck_names()
+                    .into_iter()
+                    .map(|name| (name, state.deck(name).volume.level()))
+                    .collect()
+            });
+        }
+
         InputCommand::RestartDeck { deck } => {
-            let Some(deck) = deck_name(&deck) else {
-                return CommandOutcome::Continue;
-            };
+            let deck = known_deck(state, &deck)?;
+            if !state.deck(deck).can_replay() {
+                return Err(replay_dropped(deck));
+            }
             send_log(
                 "info",
                 &format!(
                     "Restarting deck {} for replay ({} samples available)",
                     deck,
-                    state.deck(deck).full_samples.len()
+                    state.deck(deck).replay.len()
                 )
             );
             state.deck_mut(deck).restart();
-            send_log("deck_restarted", &format!("deck={}", deck));
+            emit(OutputEvent::DeckRestarted { deck });
+        }
+
+        InputCommand::SetNext { deck } => {
+            state.cued = match deck {
+                Some(deck) => Some(known_deck(state, &deck)?),
+                None => None
+            };
+            send_log(
+                "info",
+                &format!("Next deck: {}", state.cued.unwrap_or("A/B pair"))
+            );
+        }
+
+        InputCommand::RemoveDeck { deck } => {
+            let deck = known_deck(state, &deck)?;
+            if FIXED_DECKS.contains(&deck) {
+                return Err(Rejection::new(
+                    RejectReason::InvalidState,
+                    format!("deck {} cannot be removed", deck)
+                ));
+            }
+            if deck == state.active_deck || state.is_awaited(deck) {
+                return Err(Rejection::new(
+                    RejectReason::InvalidState,
+                    format!("deck {} is in use", deck)
+                ));
+            }
+            state.remove_deck(deck);
+            send_log("info", &format!("Removed deck {}", deck));
+        }
+
+        InputCommand::PlayOverlay { url, volume, duck } => {
+            if !volume.is_finite() || duck.is_some_and(|duck| !duck.is_finite()) {
+                return Err(Rejection::new(
+                    RejectReason::InvalidValue,
+                    "volume/duck is not a number"
+                ));
+            }
+            // Nothing is written out while paused or idle: the clip would sit
+            // in the bus and play late, on the next resume
+            if !state.is_playing {
+                return Err(Rejection::new(
+                    RejectReason::InvalidState,
+                    "nothing is playing"
+                ));
+            }
+            let volume = volume.clamp(0.0, MAX_VOLUME);
+            let duck = duck.map(|duck| duck.clamp(0.0, 1.0));
+            let Some(clip) = state.overlay.play(url.clone(), volume, duck) else {
+                return Err(Rejection::new(
+                    RejectReason::InvalidState,
+                    format!("{} overlay clips already playing", MAX_CLIPS)
+                ));
+            };
+            emit(OutputEvent::OverlayStarted { clip, url });
+        }
+
+        InputCommand::StopOverlay { clip } => {
+            if state.overlay.stop(clip).is_empty() {
+                if let Some(clip) = clip {
+                    return Err(Rejection::new(
+                        RejectReason::InvalidValue,
+                        format!("no overlay clip {}", clip)
+                    ));
+                }
+            }
+        }
+
+        InputCommand::GetState => {
+            emit(OutputEvent::State(Box::new(state.snapshot())));
         }
 
         InputCommand::Stop => {
             send_log("info", "Graceful shutdown");
-            return CommandOutcome::Shutdown;
+            return Ok(CommandOutcome::Shutdown);
+        }
+    }
+
+    Ok(CommandOutcome::Continue)
+}
+
+/// Handles one line from stdin: applies the command, then reports a refusal
+/// and, when the command carried an id, acknowledges it.
+pub fn handle_request(
+    state: &mut MixerState,
+    incoming: Result<Request, MalformedRequest>
+) -> CommandOutcome {
+    let (id, op, result) = match incoming {
+        Ok(Request { id, command }) => {
+            let op = command.op().to_string();
+            (id, Some(op), apply_command(state, command))
         }
+        Err(bad) => (
+            bad.id,
+            bad.op,
+            Err(Rejection::new(RejectReason::ParseError, bad.detail))
+        )
+    };
+
+    let (status, reason) = match &result {
+        Ok(CommandOutcome::Deferred) => (AckStatus::Deferred, None),
+        Ok(_) => (AckStatus::Accepted, None),
+        Err(rejection) => (AckStatus::Rejected, Some(rejection.reason))
+    };
+
+    if let Err(rejection) = result.as_ref() {
+        emit(OutputEvent::CommandRejected {
+            id: id.clone(),
+            op: op.clone(),
+            reason: rejection.reason,
+            detail: rejection.detail.clone()
+        });
+    }
+    if let Some(id) = id {
+        emit(OutputEvent::Ack {
+            id,
+            op: op.unwrap_or_else(|| "unknown".to_string()),
+            status,
+            reason,
+            state: state.summary()
+        });
+    }
+
+    result.unwrap_or(CommandOutcome::Continue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(result: Result<CommandOutcome, Rejection>) -> Option<RejectReason> {
+        result.err().map(|r| r.reason)
+    }
+
+    #[test]
+    fn unknown_decks_are_rejected() {
+        let mut state = MixerState::new();
+        let cmd = InputCommand::Play {
+            deck: "Z".to_string()
+        };
+        assert_eq!(reason(apply_command(&mut state, cmd)), Some(RejectReason::UnknownDeck));
+    }
+
+    #[test]
+    fn a_second_crossfade_is_rejected_while_one_runs() {
+        let mut state = MixerState::new();
+        state.crossfade = Some(Crossfade::new("B", 1000));
+        let cmd = InputCommand::Crossfade {
+            duration_ms: 1000,
+            to_deck: "B".to_string()
+        };
+        assert_eq!(reason(apply_command(&mut state, cmd)), Some(RejectReason::InvalidState));
+        // The running fade is left alone
+        assert_eq!(state.crossfade.as_ref().map(|c| c.left), Some(Crossfade::new("B", 1000).total));
+    }
+
+    #[test]
+    fn overlays_are_refused_while_nothing_plays() {
+        let mut state = MixerState::new();
+        let cmd = InputCommand::PlayOverlay {
+            url: "file:///tmp/ident.wav".to_string(),
+            volume: 1.0,
+            duck: None
+        };
+        assert_eq!(reason(apply_command(&mut state, cmd)), Some(RejectReason::InvalidState));
+        assert!(state.overlay.clip_ids().is_empty());
+    }
+
+    #[test]
+    fn a_rejected_skip_keeps_the_stall() {
+        use crate::state::Stall;
+
+        let mut state = MixerState::new();
+        state.stall = Some(Stall {
+            target: "B",
+            since: std::time::Instant::now()
+        });
+        let cmd = InputCommand::SkipTo {
+            target_deck: "A".to_string()
+        };
+        assert_eq!(reason(apply_command(&mut state, cmd)), Some(RejectReason::InvalidState));
+        assert!(state.stall.is_some());
     }
 
-    CommandOutcome::Continue
+    #[test]
+    fn skipping_to_a_deck_still_downloading_is_deferred() {
+        let mut state = MixerState::new();
+        let (_tx, rx) = crossbeam_channel::bounded(1);
+        state.deck_mut("B").receiver = Some(rx);
+        let cmd = InputCommand::SkipTo {
+            target_deck: "B".to_string()
+        };
+        assert!(matches!(apply_command(&mut state, cmd), Ok(CommandOutcome::Deferred)));
+        assert_eq!(state.summary().pending_to, Some("B"));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let mut state = MixerState::new();
+        let cmd = InputCommand::SetVolume {
+            deck: None,
+            level: f32::NAN,
+            ramp_ms: 0
+        };
+        assert_eq!(reason(apply_command(&mut state, cmd)), Some(RejectReason::InvalidValue));
+    }
 }
@@ -1,6 +1,7 @@
 //! Environment-driven configuration and audio format constants.
 
 use std::env;
+use std::path::PathBuf;
 
 pub const SAMPLE_RATE: usize = 48000;
 pub const CHANNELS: usize = 2;
@@ -26,6 +27,159 @@ pub fn get_download_watchdog_secs() -> u64 {
     60
 }
 
+/// Interval between two `position` events; 0 turns them off.
+pub fn get_position_interval_ms() -> u64 {
+    if let Ok(raw) = env::var("MIXER_POSITION_INTERVAL_MS") {
+        if let Ok(parsed) = raw.trim().parse::<u64>() {
+            if parsed == 0 {
+                return 0;
+            }
+            return parsed.clamp(100, 60_000);
+        }
+    }
+    1000
+}
+
+/// Interval between two `download_progress` events of a deck; 0 turns them
+/// off.
+pub fn get_download_progress_interval_ms() -> u64 {
+    if let Ok(raw) = env::var("MIXER_DOWNLOAD_PROGRESS_INTERVAL_MS") {
+        if let Ok(parsed) = raw.trim().parse::<u64>() {
+            if parsed == 0 {
+                return 0;
+            }
+            return parsed.clamp(250, 60_000);
+        }
+    }
+    1000
+}
+
+/// Loudness every track is normalized to; "none"/"off" turns normalization off.
+pub fn get_loudness_target_lufs() -> Option<f32> {
+    match env_opt("MIXER_LOUDNESS_TARGET_LUFS") {
+        Some(raw) => raw
+            .parse::<f32>()
+            .ok()
+            .filter(|lufs| lufs.is_finite())
+            .map(|lufs| lufs.clamp(-40.0, -5.0)),
+        None if env::var("MIXER_LOUDNESS_TARGET_LUFS").is_ok() => None,
+        None => Some(-14.0)
+    }
+}
+
+/// Level under which the start and the end of a track count as silence and
+/// are trimmed, in dBFS; "none"/"off" keeps tracks whole.
+pub fn get_silence_threshold_db() -> Option<f32> {
+    match env_opt("MIXER_SILENCE_THRESHOLD_DB") {
+        Some(raw) => raw
+            .parse::<f32>()
+            .ok()
+            .filter(|db| db.is_finite())
+            .map(|db| db.clamp(-96.0, -30.0)),
+        None if env::var("MIXER_SILENCE_THRESHOLD_DB").is_ok() => None,
+        None => Some(-60.0)
+    }
+}
+
+/// Silence trimmed at most from each end of a track; 0 turns trimming off.
+pub fn get_silence_max_trim_ms() -> u64 {
+    if let Ok(raw) = env::var("MIXER_SILENCE_MAX_TRIM_MS") {
+        if let Ok(parsed) = raw.trim().parse::<u64>() {
+            return parsed.min(60_000);
+        }
+    }
+    10_000
+}
+
+/// Level the master limiter never lets the output exceed, in dBFS.
+pub fn get_limiter_ceiling_db() -> f32 {
+    if let Ok(raw) = env::var("MIXER_LIMITER_CEILING_DB") {
+        if let Ok(parsed) = raw.trim().parse::<f32>() {
+            if parsed.is_finite() {
+                return parsed.clamp(-12.0, 0.0);
+            }
+        }
+    }
+    -1.0
+}
+
+/// How quickly the master limiter gives back gain after a peak.
+pub fn get_limiter_release_ms() -> u64 {
+    if let Ok(raw) = env::var("MIXER_LIMITER_RELEASE_MS") {
+        if let Ok(parsed) = raw.trim().parse::<u64>() {
+            return parsed.clamp(10, 2000);
+        }
+    }
+    150
+}
+
+/// Most decks that may exist at once, "A" and "B" included. Every deck can
+/// hold a full decoded track, so this is also a bound on memory.
+pub fn get_max_decks() -> usize {
+    if let Ok(raw) = env::var("MIXER_MAX_DECKS") {
+        if let Ok(parsed) = raw.trim().parse::<usize>() {
+            return parsed.clamp(2, 16);
+        }
+    }
+    4
+}
+
+/// Longest track a deck keeps a copy of for restart, seek and loop. A minute
+/// takes about 11 MiB per deck.
+pub fn get_replay_max_minutes() -> u64 {
+    if let Ok(raw) = env::var("MIXER_REPLAY_MAX_MINUTES") {
+        if let Ok(parsed) = raw.trim().parse::<u64>() {
+            return parsed.clamp(1, 600);
+        }
+    }
+    20
+}
+
+/// Attempts a failed download gets on top of the first one, each with another
+/// fallback strategy (see `YtDlpSource::next_strategy`). 0 turns retries off.
+pub fn get_download_retries() -> u32 {
+    if let Ok(raw) = env::var("MIXER_DOWNLOAD_RETRIES") {
+        if let Ok(parsed) = raw.trim().parse::<u32>() {
+            return parsed.min(5);
+        }
+    }
+    3
+}
+
+/// Directory of the track cache, shared by every guild's engine.
+pub fn get_track_cache_dir() -> PathBuf {
+    match env_opt("MIXER_CACHE_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(get_base_path()).join("cache").join("tracks")
+    }
+}
+
+/// Size cap of the track cache in MiB; 0 turns the cache off. A decoded
+/// four-minute song takes about 45 MiB.
+pub fn get_track_cache_max_mb() -> u64 {
+    if let Ok(raw) = env::var("MIXER_CACHE_MAX_MB") {
+        if let Ok(parsed) = raw.trim().parse::<u64>() {
+            return parsed.min(1_048_576);
+        }
+    }
+    2048
+}
+
+/// True when downloads are decoded in-process, which needs a build with the
+/// `native-decoder` feature; MIXER_DECODER=ffmpeg goes back to the ffmpeg
+/// subprocess.
+pub fn use_native_decoder() -> bool {
+    cfg!(feature = "native-decoder")
+        && !env::var("MIXER_DECODER").is_ok_and(|raw| raw.trim().eq_ignore_ascii_case("ffmpeg"))
+}
+
+/// Event shape requested by the client that spawned the engine, if any.
+pub fn get_protocol_version() -> Option<u8> {
+    env::var("MIXER_PROTOCOL_VERSION")
+        .ok()
+        .and_then(|raw| raw.trim().parse::<u8>().ok())
+}
+
 /// Reads an environment variable; empty values or "none"/"off"/"false" → None.
 pub fn env_opt(name: &str) -> Option<String> {
     env::var(name).ok().and_then(|v| {
@@ -9,12 +9,36 @@ use std::thread;
 
 use crate::config::{CHANNELS, SAMPLE_RATE};
 use crate::download::download_and_decode_advanced;
-use crate::protocol::send_log;
+use crate::eq::Equalizer;
+use crate::failure::FailureSlot;
+use crate::gain::GainRamp;
+use crate::loudness::Normalizer;
+use crate::protocol::{emit, send_log, DeckSnapshot, FailureCode, OutputEvent};
+use crate::rate::RateProcessor;
+use crate::replay::ReplayBuffer;
+
+/// What a seek request turned into.
+pub enum SeekOutcome {
+    /// The deck now plays from the requested position.
+    Done(u64),
+    /// The position has not been downloaded yet: the deck keeps playing and
+    /// jumps as soon as the download gets there.
+    Pending,
+    /// The deck holds no track to seek in.
+    Unavailable,
+    /// The track outgrew the replay buffer: only the stream is left.
+    ReplayDropped
+}
+
+/// Converts interleaved stereo samples into milliseconds of audio.
+fn samples_to_ms(samples: usize) -> u64 {
+    (samples / CHANNELS * 1000 / SAMPLE_RATE) as u64
+}
 
 pub struct Deck {
     name: &'static str,
     samples: VecDeque<f32>,
-    pub full_samples: Vec<f32>, // All samples received (for replay without re-download)
+    pub replay: ReplayBuffer, // Copy of the track (for replay without re-download)
     pub has_ended: bool,
     pub receiver: Option<Receiver<Vec<f32>>>,
     /// Samples handed over by the download thread, used to tell an empty
@@ -22,6 +46,11 @@ pub struct Deck {
     real_samples_received: usize,
     pub samples_played: usize, // Samples actually PLAYED (not just received)
     cancel_token: Option<Arc<AtomicBool>>, // Signals the download thread to stop
+    /// Set for a live stream, by `load` or by the download thread once the
+    /// source reports it.
+    live: Arc<AtomicBool>,
+    /// Why the download failed, filled in by the download thread.
+    failure: FailureSlot,
     pub load_started_at: Option<std::time::Instant>, // When load() was called
     // Flags for edge detection (managed by the mixer stages)
     pub buffer_prev_ready: bool,
@@ -32,8 +61,19 @@ pub struct Deck {
     pub fail_sent: bool,
     // Real audio actually reached the output for this playback (stats gating)
     pub play_confirmed_sent: bool,
-    // Replay: offset to read from full_samples without clone
-    replay_offset: Option<usize>
+    // Replay: offset to read from the replay buffer without clone
+    replay_offset: Option<usize>,
+    /// Seek target (in samples) waiting for the download to reach it
+    pending_seek: Option<usize>,
+    /// Per-deck gain set by Node.js; survives loads and `MixerState::reset_deck`.
+    pub volume: GainRamp,
+    /// Per-deck equalizer; kept alongside `volume` for the same reason.
+    pub eq: Equalizer,
+    /// Loudness normalization of the loaded track
+    normalizer: Normalizer,
+    /// Speed/pitch effect between the buffers and the output; a setting of
+    /// the slot, like `volume`.
+    pub rate: RateProcessor
 }
 
 impl Deck {
@@ -41,12 +81,14 @@ impl Deck {
         Self {
             name,
             samples: VecDeque::new(),
-            full_samples: Vec::new(),
+            replay: ReplayBuffer::new(),
             has_ended: false,
             receiver: None,
             real_samples_received: 0,
             samples_played: 0,
             cancel_token: None,
+            live: Arc::new(AtomicBool::new(false)),
+            failure: FailureSlot::default(),
             load_started_at: None,
             buffer_prev_ready: false,
             end_sent: false,
@@ -54,11 +96,16 @@ impl Deck {
             download_failed: false,
             fail_sent: false,
             play_confirmed_sent: false,
-            replay_offset: None
+            replay_offset: None,
+            pending_seek: None,
+            volume: GainRamp::default(),
+            eq: Equalizer::default(),
+            normalizer: Normalizer::new(),
+            rate: RateProcessor::default()
         }
     }
 
-    pub fn load(&mut self, url: String) {
+    pub fn load(&mut self, url: String, live: bool) {
         // Cancels the previous download (if in progress)
         // This signals the thread to kill yt-dlp/ffmpeg and exit
         if let Some(ref token) = self.cancel_token {
@@ -66,12 +113,15 @@ impl Deck {
         }
 
         self.samples.clear();
-        self.full_samples.clear();
+        self.replay.clear();
         self.real_samples_received = 0;
         self.samples_played = 0;
         self.has_ended = false;
         self.load_started_at = Some(std::time::Instant::now());
         self.replay_offset = None;
+        self.pending_seek = None;
+        self.normalizer = Normalizer::new();
+        self.rate.reset();
         self.reset_flags();
 
         let (tx, rx) = bounded::<Vec<f32>>(100);
@@ -79,11 +129,15 @@ impl Deck {
 
         let cancel = Arc::new(AtomicBool::new(false));
         self.cancel_token = Some(cancel.clone());
+        self.live = Arc::new(AtomicBool::new(live));
+        let live = self.live.clone();
+        self.failure = FailureSlot::default();
+        let failure = self.failure.clone();
         let deck_name = self.name;
 
         // Starts download thread
         thread::spawn(move || {
-            if let Err(e) = download_and_decode_advanced(&url, tx, cancel, deck_name) {
+            if let Err(e) = download_and_decode_advanced(&url, tx, cancel, deck_name, live, failure) {
                 send_log(
                     "error",
                     &format!("[Deck {}] Download error: {}", deck_name, e)
@@ -92,21 +146,47 @@ impl Deck {
         });
     }
 
+    /// Next sample at the deck's playback rate. `samples_played` counts
+    /// output samples, so everything gated on it stays in real time.
     pub fn get_next_sample(&mut self) -> Option<f32> {
-        // Replay mode: reads directly from full_samples without clone
+        if self.rate.is_bypassed() {
+            return self.read_source_sample();
+        }
+
+        let played = self.samples_played;
+        let mut rate = std::mem::take(&mut self.rate);
+        let sample = rate.next_sample(&mut || self.read_source_sample());
+        self.rate = rate;
+        // Only count output once real audio has been read, as the bypassed
+        // path does: a deck waiting on its first samples has played nothing.
+        if sample.is_some() && self.samples_played > 0 {
+            self.samples_played = played + 1;
+        }
+        sample
+    }
+
+    /// Next sample straight from the buffers, at the track's own speed.
+    fn read_source_sample(&mut self) -> Option<f32> {
+        // Replay mode: reads directly from the replay buffer without clone
         if let Some(offset) = self.replay_offset {
-            if offset < self.full_samples.len() {
-                let sample = self.full_samples[offset];
+            // A replay or seek can start before the download is over: keep
+            // following it, and wait in silence if playback catches up.
+            self.poll_receiver();
+            if let Some(sample) = self.replay.get(offset) {
                 self.replay_offset = Some(offset + 1);
                 self.samples_played += 1;
                 return Some(sample);
             }
+            if self.receiver.is_some() {
+                return Some(0.0);
+            }
             self.replay_offset = None;
             self.has_ended = true;
             return None;
         }
 
-        // Streaming mode: reads from VecDeque
+        // Streaming mode: reads from VecDeque (also where a replay falls back
+        // to when the replay buffer is dropped under it)
         self.poll_receiver();
 
         if let Some(sample) = self.samples.pop_front() {
@@ -128,9 +208,19 @@ impl Deck {
         Some(0.0)
     }
 
+    /// The next sample as the mixer hears it: `get_next_sample` followed by
+    /// the deck's equalizer, loudness normalization and its own gain stage.
+    pub fn next_output_sample(&mut self) -> Option<f32> {
+        let sample = self.get_next_sample()?;
+        Some(self.eq.process(sample) * self.normalizer.next_gain() * self.volume.next())
+    }
+
     /// Moves everything the download thread has produced into the buffer,
     /// without consuming any of it. Safe to call on inactive decks.
     pub fn poll_receiver(&mut self) {
+        let downloading = self.receiver.is_some();
+        let live = self.is_live();
+        let mut replay_dropped = false;
         if let Some(rx) = &self.receiver {
             // Read ALL available chunks, not just one
             let mut chunks_received = 0;
@@ -143,8 +233,25 @@ impl Deck {
                         if self.load_started_at.is_some() {
                             self.load_started_at = None;
                         }
-                        self.full_samples.extend(&chunk); // Saves copy for replay
-                        self.samples.extend(chunk);
+                        self.normalizer.feed(&chunk);
+                        if let Some(offset) = self.replay_offset {
+                            // The copy being replayed is about to go: what is
+                            // left of it is played from the stream buffer instead
+                            if live || !self.replay.fits(chunk.len()) {
+                                self.samples.extend(self.replay.tail(offset));
+                                self.replay_offset = None;
+                            }
+                        }
+                        // A live stream is never kept, however short so far
+                        if live {
+                            replay_dropped |= self.replay.stop_storing();
+                        }
+                        replay_dropped |= self.replay.extend(&chunk);
+                        // Replay reads the replay buffer directly: queueing the
+                        // chunk as well would play it a second time afterwards.
+                        if self.replay_offset.is_none() {
+                            self.samples.extend(chunk);
+                        }
                     }
                     Err(TryRecvError::Disconnected) => {
                         send_log("info", &format!("✅ [RX-DONE] Deck {} → {} chunks received, final buffer: {} samples", self.name, chunks_received, self.samples.len()));
@@ -162,17 +269,86 @@ impl Deck {
                 }
             }
         }
+
+        if replay_dropped {
+            self.drop_replay();
+        }
+
+        if downloading {
+            let audible = self.position_samples() > 0;
+            if let Some(report) = self.normalizer.update(self.receiver.is_none(), audible) {
+                emit(OutputEvent::LoudnessMeasured {
+                    deck: self.name,
+                    integrated_lufs: report.integrated_lufs,
+                    gain_db: report.gain_db
+                });
+            }
+        }
+
+        if let Some(target) = self.pending_seek {
+            if target < self.replay.len() || self.receiver.is_none() {
+                let position = self.jump_to(target);
+                emit(OutputEvent::DeckSeeked {
+                    deck: self.name,
+                    position_ms: position
+                });
+            }
+        }
+    }
+
+    /// The track just outgrew the replay buffer, or turned out to be live:
+    /// restart, seek and loop are over for it, and a seek still waiting on the
+    /// download is abandoned.
+    fn drop_replay(&mut self) {
+        self.pending_seek = None;
+        let limit_ms = samples_to_ms(self.replay.len());
+        let why = if self.is_live() {
+            "Live stream".to_string()
+        } else {
+            format!("Track longer than the replay buffer ({}ms)", limit_ms)
+        };
+        send_log(
+            "info",
+            &format!(
+                "💾 [Deck {}] {}: restart/seek/loop unavailable",
+                self.name, why
+            )
+        );
+        emit(OutputEvent::ReplayUnavailable {
+            deck: self.name,
+            limit_ms
+        });
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.live.load(Ordering::Relaxed)
+    }
+
+    /// Why the download failed, as far as it could tell.
+    pub fn failure(&self) -> Option<FailureCode> {
+        self.failure.get()
+    }
+
+    /// Whether the deck still holds a copy of its track to restart from.
+    pub fn can_replay(&self) -> bool {
+        self.replay.is_available()
     }
 
     pub fn is_ready_for_crossfade(&self) -> bool {
-        self.available_samples() >= SAMPLE_RATE * CHANNELS / 2
+        self.remaining_output_samples() >= SAMPLE_RATE * CHANNELS / 2
+    }
+
+    /// Buffered audio converted to output time: at a faster playback rate the
+    /// same buffer runs out sooner.
+    pub fn remaining_output_samples(&self) -> usize {
+        (self.available_samples() as f64 / self.rate.rate() as f64) as usize
     }
 
     /// Available samples (streaming + replay)
     pub fn available_samples(&self) -> usize {
         self.samples.len()
             + match self.replay_offset {
-                Some(offset) => self.full_samples.len().saturating_sub(offset),
+                Some(offset) => self.replay.len().saturating_sub(offset),
                 None => 0
             }
     }
@@ -181,7 +357,7 @@ impl Deck {
         !self.samples.is_empty()
             || self
                 .replay_offset
-                .is_some_and(|offset| offset < self.full_samples.len())
+                .is_some_and(|offset| offset < self.replay.len())
     }
 
     /// Seconds of audio this deck has played, for the periodic status log.
@@ -189,16 +365,107 @@ impl Deck {
         self.samples_played / (SAMPLE_RATE * CHANNELS)
     }
 
+    /// Position inside the track, in samples. Derived from the buffers rather
+    /// than `samples_played`, which restarts at every deck switch and so would
+    /// lose the part of the track already heard during a crossfade.
+    pub fn position_samples(&self) -> usize {
+        match self.replay_offset {
+            Some(offset) => offset,
+            None => self.replay.len().saturating_sub(self.samples.len())
+        }
+    }
+
+    pub fn played_ms(&self) -> u64 {
+        samples_to_ms(self.position_samples())
+    }
+
+    /// Audio downloaded but not played yet.
+    pub fn buffered_ms(&self) -> u64 {
+        samples_to_ms(self.available_samples())
+    }
+
+    /// Length of the track, known only once its download is over. A live
+    /// stream has none, even when it stops.
+    pub fn duration_ms(&self) -> Option<u64> {
+        if self.receiver.is_none() && !self.replay.is_empty() && !self.is_live() {
+            Some(samples_to_ms(self.replay.len()))
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn snapshot(&self) -> DeckSnapshot {
+        DeckSnapshot {
+            buffered_samples: self.available_samples(),
+            played_samples: self.position_samples(),
+            cached_samples: if self.can_replay() { self.replay.len() } else { 0 },
+            replay_available: self.can_replay(),
+            live: self.is_live(),
+            played_ms: self.played_ms(),
+            buffered_ms: self.buffered_ms(),
+            duration_ms: self.duration_ms(),
+            downloading: self.receiver.is_some(),
+            download_failed: self.download_failed,
+            failure: self.failure().filter(|_| self.download_failed),
+            has_ended: self.has_ended,
+            volume: self.volume.level(),
+            playback_rate: self.rate.rate(),
+            preserve_pitch: self.rate.preserve_pitch()
+        }
+    }
+
     /// Restarts the deck from the beginning without re-downloading.
-    /// Uses replay_offset to read from full_samples without cloning.
+    /// Uses replay_offset to read from the replay buffer without cloning;
+    /// callers check `can_replay` first.
     pub fn restart(&mut self) {
         self.samples.clear();
         self.replay_offset = Some(0);
+        self.pending_seek = None;
+        self.rate.reset();
         self.has_ended = false;
         self.samples_played = 0;
         self.reset_flags();
     }
 
+    /// Moves playback to `position_ms`, through the same replay machinery as
+    /// `restart`. A position past the end of a finished download lands on the
+    /// end, so the track simply finishes.
+    pub fn seek(&mut self, position_ms: u64) -> SeekOutcome {
+        let target = (position_ms as usize * SAMPLE_RATE / 1000) * CHANNELS;
+        self.poll_receiver();
+
+        if !self.can_replay() {
+            return SeekOutcome::ReplayDropped;
+        }
+        if target < self.replay.len() || self.receiver.is_none() {
+            if self.replay.is_empty() {
+                return SeekOutcome::Unavailable;
+            }
+            return SeekOutcome::Done(self.jump_to(target));
+        }
+
+        self.pending_seek = Some(target);
+        SeekOutcome::Pending
+    }
+
+    /// Starts replaying from sample `target` (clamped to the cache) and
+    /// returns the resulting position in milliseconds.
+    fn jump_to(&mut self, target: usize) -> u64 {
+        let offset = target.min(self.replay.len());
+        self.samples.clear();
+        self.replay_offset = Some(offset);
+        self.pending_seek = None;
+        self.rate.reset();
+        self.has_ended = false;
+        self.end_sent = false;
+        self.approaching_end_sent = false;
+        samples_to_ms(offset)
+    }
+
     /// Reset of edge detection flags (for transitions)
     pub fn reset_flags(&mut self) {
         self.buffer_prev_ready = false;
@@ -224,10 +491,15 @@ impl Drop for Deck {
 mod tests {
     use super::*;
 
+    /// Distinct sample values the 16-bit replay buffer stores exactly.
+    fn value(i: usize) -> f32 {
+        (i % 32768) as f32 / 32768.0
+    }
+
     /// Builds a deck holding `n` cached samples, as if a download had completed.
     fn deck_with_cache(n: usize) -> Deck {
         let mut deck = Deck::new("A");
-        deck.full_samples = (0..n).map(|i| i as f32).collect();
+        deck.replay.extend(&(0..n).map(value).collect::<Vec<_>>());
         deck
     }
 
@@ -255,9 +527,9 @@ mod tests {
         let mut deck = deck_with_cache(3);
         deck.restart();
 
-        assert_eq!(deck.get_next_sample(), Some(0.0));
-        assert_eq!(deck.get_next_sample(), Some(1.0));
-        assert_eq!(deck.get_next_sample(), Some(2.0));
+        assert_eq!(deck.get_next_sample(), Some(value(0)));
+        assert_eq!(deck.get_next_sample(), Some(value(1)));
+        assert_eq!(deck.get_next_sample(), Some(value(2)));
         // Cache exhausted: the deck ends instead of looping forever
         assert_eq!(deck.get_next_sample(), None);
         assert!(deck.has_ended);
@@ -302,6 +574,95 @@ mod tests {
         assert!(ready.is_ready_for_crossfade());
     }
 
+    #[test]
+    fn seek_jumps_inside_the_cache() {
+        let frames_per_ms = SAMPLE_RATE / 1000;
+        let mut deck = deck_with_cache(frames_per_ms * CHANNELS * 10);
+
+        assert!(matches!(deck.seek(5), SeekOutcome::Done(5)));
+        let offset = 5 * frames_per_ms * CHANNELS;
+        assert_eq!(deck.get_next_sample(), Some(value(offset)));
+        assert_eq!(deck.position_samples(), offset + 1);
+    }
+
+    #[test]
+    fn seek_past_a_finished_download_lands_on_the_end() {
+        let mut deck = deck_with_cache(4);
+        assert!(matches!(deck.seek(60_000), SeekOutcome::Done(_)));
+        assert_eq!(deck.get_next_sample(), None);
+        assert!(deck.has_ended);
+    }
+
+    #[test]
+    fn seek_beyond_the_download_waits_for_it() {
+        let mut deck = deck_with_cache(4);
+        let (tx, rx) = bounded::<Vec<f32>>(4);
+        deck.receiver = Some(rx);
+
+        assert!(matches!(deck.seek(1), SeekOutcome::Pending));
+        // Still playing from where it was until the target arrives
+        assert_eq!(deck.available_samples(), 0);
+
+        let target = SAMPLE_RATE / 1000 * CHANNELS;
+        tx.send((4..target + 2).map(value).collect()).unwrap();
+        deck.poll_receiver();
+        assert_eq!(deck.get_next_sample(), Some(value(target)));
+    }
+
+    #[test]
+    fn seek_on_an_empty_deck_is_unavailable() {
+        let mut deck = Deck::new("A");
+        assert!(matches!(deck.seek(1000), SeekOutcome::Unavailable));
+    }
+
+    #[test]
+    fn position_survives_a_deck_switch_and_follows_replay() {
+        let second = SAMPLE_RATE * CHANNELS;
+        let mut deck = Deck::new("A");
+        deck.replay.extend(&vec![0.0; second * 2]);
+        deck.samples = vec![0.0; second].into();
+        // Played during a crossfade, then switch_to resets the counter
+        deck.samples_played = 0;
+        assert_eq!(deck.played_ms(), 1000);
+        assert_eq!(deck.buffered_ms(), 1000);
+        assert_eq!(deck.duration_ms(), Some(2000));
+
+        deck.restart();
+        assert_eq!(deck.played_ms(), 0);
+        assert_eq!(deck.buffered_ms(), 2000);
+    }
+
+    #[test]
+    fn a_replay_outgrowing_its_buffer_carries_on_from_the_stream() {
+        let mut deck = Deck::new("A");
+        deck.replay = ReplayBuffer::with_limit(6);
+        let (tx, rx) = bounded::<Vec<f32>>(4);
+        deck.receiver = Some(rx);
+        tx.send((0..4).map(value).collect()).unwrap();
+        deck.restart();
+        assert_eq!(deck.get_next_sample(), Some(value(0)));
+
+        tx.send((4..8).map(value).collect()).unwrap();
+        deck.poll_receiver();
+        assert!(!deck.can_replay());
+        assert!(matches!(deck.seek(0), SeekOutcome::ReplayDropped));
+        // Nothing skipped, nothing played twice
+        let rest: Vec<_> = (0..7).filter_map(|_| deck.get_next_sample()).collect();
+        assert_eq!(rest, (1..8).map(value).collect::<Vec<_>>());
+        assert_eq!(deck.position_samples(), 8);
+    }
+
+    #[test]
+    fn faster_playback_shortens_the_remaining_time() {
+        let second = SAMPLE_RATE * CHANNELS;
+        let mut deck = deck_with_cache(second);
+        deck.restart();
+        deck.rate.set(2.0, false);
+        assert_eq!(deck.remaining_output_samples(), second / 2);
+        // Half a second of output left: still just enough to fade into
+        assert!(deck.is_ready_for_crossfade());
+    }
+
     #[test]
     fn played_seconds_counts_whole_seconds_of_output() {
         let mut deck = Deck::new("A");
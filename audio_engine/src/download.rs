@@ -1,173 +1,178 @@
-//! Download and decode pipeline: yt-dlp streams the audio, ffmpeg decodes it to
-//! PCM, and the samples are pushed to the owning deck over a channel.
+//! Download and decode pipeline: the deck's source (yt-dlp, a local file or a
+//! direct link, see `source.rs`) feeds the decoder, which turns it into PCM,
+//! and the samples are pushed to the owning deck over a channel.
+//!
+//! The decoder is an ffmpeg child process, or Symphonia in-process in builds
+//! with the `native-decoder` feature (see `native_decoder.rs`). Tracks that
+//! streamed to the end are kept in the on-disk cache (`cache.rs`), and a
+//! cached track is read back from there without starting either.
+//!
+//! A download that fails is tried again with the source's fallback
+//! strategies, and one that breaks mid-song is picked up where its audio
+//! stopped. A live stream has no end to reach: when its connection drops,
+//! the source is opened again after a growing delay, until it comes back or
+//! the attempts run out.
 
 use anyhow::{anyhow, Result};
 use byteorder::{ReadBytesExt, LE}; // Essential for reading audio
 use crossbeam_channel::Sender;
-#[cfg(not(windows))]
-use libc;
-use std::env;
-use std::io::{self, BufRead, BufReader};
-use std::process::{Command as ProcessCommand, Stdio};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Child, ChildStdout, Command as ProcessCommand, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 
+use crate::cache::{CacheWriter, TrackCache};
 use crate::config::{
-    default_ytdlp_cookie_browser, default_ytdlp_proxy_url, env_opt, get_base_path,
-    get_download_watchdog_secs, CHANNELS, SAMPLE_RATE,
+    get_download_progress_interval_ms, get_download_retries, get_download_watchdog_secs, CHANNELS,
+    SAMPLE_RATE
 };
-use crate::protocol::send_log;
+use crate::failure::{classify_ffmpeg, FailureSlot};
+#[cfg(feature = "native-decoder")]
+use crate::native_decoder::{self, DecodeError, NativeOpen};
+use crate::protocol::{emit, send_log, shortened, FailureCode, OutputEvent, TrackInfo, TrimEdge};
+use crate::rate::MAX_RATE;
+use crate::silence::SilenceTrimmer;
+use crate::source::{source_for, DecoderInput, Source};
 
-pub fn download_and_decode_advanced(
-    url: &str,
-    tx: Sender<Vec<f32>>,
-    cancel: Arc<AtomicBool>,
-    deck_name: &str,
-) -> Result<()> {
-    // Direct streaming flow:
-    // 1. yt-dlp downloads/streams audio to stdout
-    // 2. yt-dlp's stdout is connected to ffmpeg's stdin
-    // 3. ffmpeg decodes and returns PCM
-
-    send_log("info", &format!("Streaming: {}", &url[..url.len().min(60)]));
-
-    // Uses yt-dlp binary from bot directory
-    let yt_dlp_binary = format!("{}/bin/yt-dlp", get_base_path());
-
-    let mut yt_dlp_cmd = ProcessCommand::new(yt_dlp_binary);
-    yt_dlp_cmd
-        .arg("--no-update")
-        .arg("-f").arg("ba/b/bestaudio/best")
-        .arg("--ignore-no-formats-error")
-        .arg("--force-ipv4")
-        .arg("--user-agent").arg("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36")
-        .arg("-o").arg("-")
-        .arg("-q")
-        .arg("--no-warnings")
-        .arg("--no-cache-dir")
-        .arg("--no-playlist")
-        .arg("--socket-timeout").arg("30")
-        .arg("--retries").arg("5")
-        .arg("--fragment-retries").arg("5")
-        .arg("--concurrent-fragments").arg("1")
-        .arg("--js-runtimes").arg("node")
-        .arg("--impersonate").arg("chrome");
-
-    yt_dlp_cmd.env("PATH", env::var("PATH").unwrap_or_default());
-
-    // Proxy: default socks5h://127.0.0.1:5040; YTDLP_PROXY_URL=none to disable
-    let proxy_url = env_opt("YTDLP_PROXY_URL").or_else(|| {
-        if env::var("YTDLP_PROXY_URL").is_ok() {
-            None
-        } else {
-            Some(default_ytdlp_proxy_url())
-        }
-    });
-    if let Some(ref proxy) = proxy_url {
-        send_log("info", &format!("yt-dlp proxy active: {}", proxy));
-        yt_dlp_cmd.arg("--proxy").arg(proxy);
-    } else {
-        send_log("info", "yt-dlp proxy disabled (YTDLP_PROXY_URL=none)");
-    }
+/// Reconnections of a live stream in a row before the deck is given up on.
+const MAX_LIVE_RECONNECTS: u32 = 8;
 
-    // Cookie browser: disabled by default on Linux; YTDLP_COOKIE_BROWSER=chromium to force them
-    let cookie_browser = env_opt("YTDLP_COOKIE_BROWSER").or_else(|| {
-        if env::var("YTDLP_COOKIE_BROWSER").is_ok() {
-            None
-        } else {
-            default_ytdlp_cookie_browser()
-        }
-    });
-    if let Some(ref browser) = cookie_browser {
-        yt_dlp_cmd.arg("--cookies-from-browser").arg(browser);
-    } else {
-        send_log(
-            "info",
-            "yt-dlp cookies-from-browser disabled (YTDLP_COOKIE_BROWSER=none)",
-        );
+/// Delay before the first reconnection, doubled at each further one.
+const LIVE_RECONNECT_BASE_MS: u64 = 1000;
+const LIVE_RECONNECT_MAX_MS: u64 = 30_000;
+
+/// A connection that carried this much audio (30 seconds) was healthy: the
+/// next drop starts counting attempts from scratch.
+const LIVE_STABLE_SAMPLES: usize = SAMPLE_RATE * CHANNELS * 30;
+
+/// Delay before the first retry of a failed download, doubled at each further
+/// one.
+const RETRY_BASE_MS: u64 = 500;
+
+/// Times a download picks up a stream that broke mid-song before letting the
+/// track end there.
+const MAX_RESUMES: u32 = 3;
+
+/// How short of its announced length a track may stop and still count as
+/// complete: yt-dlp rounds durations to the second.
+const DURATION_SLACK_MS: u64 = 1000;
+
+/// Audio the deck keeps ahead of playback however much silence is held back.
+const SILENCE_HOLD_MARGIN_MS: u64 = 2000;
+
+/// How often the length of the track is asked for while it is unknown.
+const DURATION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+fn samples_to_ms(samples: usize) -> u64 {
+    (samples / CHANNELS * 1000 / SAMPLE_RATE) as u64
+}
+
+fn ms_to_samples(ms: u64) -> usize {
+    ms as usize * SAMPLE_RATE / 1000 * CHANNELS
+}
+
+/// Decoded audio, handed out one interleaved 48 kHz stereo sample at a time.
+/// The end of the stream is reported as `UnexpectedEof`, like a pipe running
+/// dry.
+pub trait PcmStream {
+    fn next_sample(&mut self) -> io::Result<f32>;
+
+    /// Length of the track, when the decoder read it from the container.
+    fn duration_ms(&self) -> Option<u64> {
+        None
     }
-    yt_dlp_cmd.arg("--mark-watched");
+}
 
-    // Fallback to cookies file if exists
-    let cookies_file = format!("{}/youtube-cookies.txt", get_base_path());
-    if std::path::Path::new(&cookies_file).exists() {
-        yt_dlp_cmd.arg("--cookies").arg(&cookies_file);
+/// ffmpeg's s16le output.
+impl<R: Read> PcmStream for BufReader<R> {
+    fn next_sample(&mut self) -> io::Result<f32> {
+        Ok(self.read_i16::<LE>()? as f32 / 32768.0)
     }
+}
 
-    // Extractor args configurable via env
-    let extractor_args = env::var("YTDLP_EXTRACTOR_ARGS").unwrap_or_else(|_| {
-        "youtube:player_client=web,android,ios,mweb".to_string()
-    });
-    send_log(
-        "info",
-        &format!("yt-dlp extractor-args active: {}", extractor_args),
-    );
-    yt_dlp_cmd.arg("--extractor-args").arg(&extractor_args);
-
-    let mut yt_dlp_child = yt_dlp_cmd
-        .arg(url)
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| anyhow!("Failed to spawn yt-dlp: {}", e))?;
+/// Encoded input on its way to the decoder, counted for `download_progress`.
+struct CountingReader<R> {
+    inner: R,
+    bytes: Arc<AtomicU64>,
+}
 
-    let yt_dlp_stdout = yt_dlp_child
-        .stdout
-        .take()
-        .ok_or(anyhow!("Failed to open yt-dlp stdout"))?;
-    let yt_dlp_stderr = yt_dlp_child
-        .stderr
-        .take()
-        .ok_or(anyhow!("Failed to open yt-dlp stderr"))?;
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
 
-    let stderr_lines: Arc<std::sync::Mutex<Vec<String>>> =
-        Arc::new(std::sync::Mutex::new(Vec::new()));
-    let stderr_lines_cap = stderr_lines.clone();
-    let cancel_stderr_yt = cancel.clone();
-    thread::spawn(move || {
-        let reader = BufReader::new(yt_dlp_stderr);
-        for line in reader.lines() {
-            if cancel_stderr_yt.load(Ordering::Relaxed) {
-                break;
-            }
-            if let Ok(l) = line {
-                let trimmed = l.trim();
-                if trimmed.is_empty() {
-                    continue;
-                }
-                if let Ok(mut buf) = stderr_lines_cap.lock() {
-                    if buf.len() >= 40 {
-                        buf.remove(0);
-                    }
-                    buf.push(trimmed.to_string());
-                }
-                let lower = trimmed.to_lowercase();
-                if lower.contains("error")
-                    || lower.contains("unable")
-                    || lower.contains("failed")
-                    || lower.contains("blocked")
-                    || lower.contains("sign in")
-                {
-                    send_log("error", &format!("[yt-dlp] {}", trimmed));
-                }
-            }
+/// Turns an opened source into PCM; `open_decoder`, short of tests that feed
+/// PCM straight through.
+type OpenDecoder = fn(
+    DecoderInput,
+    &[String],
+    &Arc<AtomicBool>,
+    &'static str,
+    &FailureSlot,
+) -> Result<(Box<dyn PcmStream>, Option<Child>)>;
+
+/// Starts the decoder for an opened source. Returns the ffmpeg child too when
+/// decoding happens out of process.
+fn open_decoder(
+    input: DecoderInput,
+    input_args: &[String],
+    cancel: &Arc<AtomicBool>,
+    deck_name: &'static str,
+    failure: &FailureSlot,
+) -> Result<(Box<dyn PcmStream>, Option<Child>)> {
+    #[cfg(feature = "native-decoder")]
+    let input = match native_decoder::open(input, deck_name) {
+        Ok(NativeOpen::Decoder(decoder)) => return Ok((decoder, None)),
+        Ok(NativeOpen::Fallback(input)) => input,
+        Err(e) => {
+            failure.record(FailureCode::DecodeFailed);
+            return Err(e);
         }
-    });
+    };
+    #[cfg(not(feature = "native-decoder"))]
+    let _ = deck_name;
 
+    let (stdout, child) = spawn_ffmpeg(input, input_args, cancel, failure)?;
+    Ok((Box::new(BufReader::new(stdout)), Some(child)))
+}
+
+/// Launches ffmpeg on the input provided by the source.
+fn spawn_ffmpeg(
+    input: DecoderInput,
+    input_args: &[String],
+    cancel: &Arc<AtomicBool>,
+    failure: &FailureSlot,
+) -> Result<(ChildStdout, Child)> {
     // Uses ffmpeg from system PATH
     let ffmpeg_path = "ffmpeg";
 
-    // Launches ffmpeg on the raw stream provided by yt-dlp.
-    let mut ffmpeg_child = ProcessCommand::new(ffmpeg_path)
+    let mut ffmpeg_cmd = ProcessCommand::new(ffmpeg_path);
+    ffmpeg_cmd
         .arg("-loglevel")
         .arg("error")
         .arg("-hide_banner")
         .arg("-fflags")
         .arg("+discardcorrupt")
-        .arg("-i")
-        .arg("pipe:0")
+        .args(input_args);
+    let mut fed = None;
+    match input {
+        DecoderInput::Pipe(stdout) => {
+            ffmpeg_cmd.arg("-i").arg("pipe:0").stdin(stdout);
+        }
+        DecoderInput::Location(location) => {
+            ffmpeg_cmd.arg("-i").arg(location).stdin(Stdio::null());
+        }
+        DecoderInput::Stream(reader) => {
+            ffmpeg_cmd.arg("-i").arg("pipe:0").stdin(Stdio::piped());
+            fed = Some(reader);
+        }
+    }
+    let mut ffmpeg_child = ffmpeg_cmd
         .arg("-vn")
         .arg("-ac")
         .arg("2")
@@ -180,11 +185,25 @@ pub fn download_and_decode_advanced(
         .arg("-acodec")
         .arg("pcm_s16le")
         .arg("-")
-        .stdin(yt_dlp_stdout)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
-        .map_err(|e| anyhow!("Failed to spawn ffmpeg: {}", e))?;
+        .map_err(|e| {
+            failure.record(FailureCode::SpawnFailed);
+            anyhow!("Failed to spawn ffmpeg: {}", e)
+        })?;
+
+    // Audio the engine reads itself is copied into ffmpeg's stdin; the copy
+    // stops when either side closes, and closing stdin ends the decoding
+    if let Some(mut reader) = fed {
+        let mut stdin = ffmpeg_child
+            .stdin
+            .take()
+            .ok_or(anyhow!("Failed to open ffmpeg stdin"))?;
+        thread::spawn(move || {
+            let _ = io::copy(&mut reader, &mut stdin);
+        });
+    }
 
     let stdout = ffmpeg_child
         .stdout
@@ -197,6 +216,7 @@ pub fn download_and_decode_advanced(
 
     // Error log handling thread - cancel-aware
     let cancel_stderr_ff = cancel.clone();
+    let failure_ff = failure.clone();
     thread::spawn(move || {
         let reader = BufReader::new(stderr);
         for line in reader.lines() {
@@ -207,29 +227,31 @@ pub fn download_and_decode_advanced(
                 let trimmed = l.trim();
                 if !trimmed.is_empty() {
                     send_log("stream_error", &format!("[ffmpeg] {}", trimmed));
+                    if let Some(code) = classify_ffmpeg(trimmed) {
+                        failure_ff.record(code);
+                    }
                 }
             }
         }
     });
 
-    send_log(
-        "stream_opened",
-        &format!(
-            "[Deck {}] Streaming: {}",
-            deck_name,
-            &url[..url.len().min(60)]
-        ),
-    );
-
-    // ── Download watchdog ──────────────────────────────────────
-    // If yt-dlp/ffmpeg don't produce data within 30 seconds, they're stuck.
-    // The watchdog kills them by PID, unblocking the read_i16 (which will get EOF).
-    let yt_dlp_pid = yt_dlp_child.id();
-    let ffmpeg_pid = ffmpeg_child.id();
+    Ok((stdout, ffmpeg_child))
+}
+
+/// If the source/ffmpeg don't produce data within the configured time,
+/// they're stuck. The watchdog kills them by PID, unblocking the read (which
+/// will get EOF). Returns the flag the reader sets once data arrives.
+fn spawn_watchdog(
+    deck_name: &'static str,
+    upstream_pid: Option<u32>,
+    ffmpeg_pid: Option<u32>,
+    cancel: &Arc<AtomicBool>,
+    failure: &FailureSlot,
+) -> Arc<AtomicBool> {
     let cancel_wd = cancel.clone();
+    let failure_wd = failure.clone();
     let first_data_arrived = Arc::new(AtomicBool::new(false));
     let first_data_wd = first_data_arrived.clone();
-    let deck_name_wd = deck_name.to_string();
     thread::spawn(move || {
         let watchdog_secs = get_download_watchdog_secs();
         send_log(
@@ -245,173 +267,1090 @@ pub fn download_and_decode_advanced(
             }
         }
         // Timeout without data → kill stuck processes
-        send_log("error", &format!("⏰ [Deck {}] Download watchdog: {}s without data, killing yt-dlp (PID {}) + ffmpeg (PID {})",
-            deck_name_wd, watchdog_secs, yt_dlp_pid, ffmpeg_pid));
-        #[cfg(windows)]
-        {
-            // /F = force, /T = tree (kills sub-processes too)
-            let _ = ProcessCommand::new("taskkill")
-                .args(["/F", "/T", "/PID", &yt_dlp_pid.to_string()])
-                .stdout(Stdio::null())
-                .stderr(Stdio::null())
-                .status();
-            let _ = ProcessCommand::new("taskkill")
-                .args(["/F", "/T", "/PID", &ffmpeg_pid.to_string()])
-                .stdout(Stdio::null())
-                .stderr(Stdio::null())
-                .status();
-        }
-        #[cfg(not(windows))]
-        unsafe {
-            libc::kill(yt_dlp_pid as i32, libc::SIGKILL);
-            libc::kill(ffmpeg_pid as i32, libc::SIGKILL);
+        send_log("error", &format!("⏰ [Deck {}] Download watchdog: {}s without data, killing source (PID {:?}) + ffmpeg (PID {:?})",
+            deck_name, watchdog_secs, upstream_pid, ffmpeg_pid));
+        failure_wd.record(FailureCode::WatchdogTimeout);
+        for pid in upstream_pid.into_iter().chain(ffmpeg_pid) {
+            #[cfg(windows)]
+            {
+                // /F = force, /T = tree (kills sub-processes too)
+                let _ = ProcessCommand::new("taskkill")
+                    .args(["/F", "/T", "/PID", &pid.to_string()])
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status();
+            }
+            #[cfg(not(windows))]
+            unsafe {
+                libc::kill(pid as i32, libc::SIGKILL);
+            }
         }
     });
+    first_data_arrived
+}
 
-    // RAW DATA READER - ADVANCED VERSION
-    let mut reader = BufReader::new(stdout);
-    let mut buffer: Vec<f32> = Vec::with_capacity(8192);
-    let mut total_samples = 0;
-    let stream_start = std::time::Instant::now();
-    let mut cancelled = false;
+/// How reading a decoder's output stopped.
+enum ReadEnd {
+    /// The deck no longer wants the audio (replaced, or its receiver is gone).
+    Cancelled,
+    /// The decoder ran dry.
+    Eof,
+    /// Reading failed mid-stream.
+    Failed(io::Error),
+}
 
-    loop {
-        // Checks if download was canceled (deck replaced)
-        if cancel.load(Ordering::Relaxed) {
-            send_log(
-                "info",
-                &format!(
-                    "🛑 [Deck {}] Download cancelled, killing processes",
-                    deck_name
-                ),
-            );
-            cancelled = true;
-            break;
+/// One deck's download, across the connections a live stream may need.
+struct Download<'a> {
+    url: &'a str,
+    tx: Sender<Vec<f32>>,
+    cancel: Arc<AtomicBool>,
+    deck_name: &'static str,
+    live: Arc<AtomicBool>,
+    /// Copy of the track for the on-disk cache; never kept for a live stream.
+    cache_writer: Option<CacheWriter>,
+    /// Why the download failed, read by the deck if no audio came out.
+    failure: FailureSlot,
+    /// Samples of the track read by the current attempt.
+    total_samples: usize,
+    /// Position in the track (in samples) the current attempt started at:
+    /// not the top when a broken stream was resumed.
+    stream_offset: usize,
+    /// Samples earlier attempts already handed to the deck: a retry reads
+    /// the track from the top again and only forwards what comes after.
+    delivered: usize,
+    /// Samples sent to the deck over every attempt.
+    sent: usize,
+    /// When the deck got its first samples: it cannot have played more than
+    /// what `MAX_RATE` gets through since.
+    first_sent: Option<Instant>,
+    /// Fallback strategy of the current attempt, logged once it brings audio.
+    retry_strategy: Option<&'static str>,
+    /// Encoded bytes read from the source over every attempt; None while the
+    /// decoder opens the source itself.
+    bytes_received: Option<Arc<AtomicU64>>,
+    /// Length of the track, once the source or the decoder tells.
+    duration_ms: Option<u64>,
+    /// When the current attempt started reading, for its realtime factor.
+    attempt_start: Instant,
+    /// `track_metadata` is sent once, not at every attempt.
+    metadata_sent: bool,
+    open_decoder: OpenDecoder,
+    /// Cuts the silence at both ends of the track; None for a live stream,
+    /// which has no end to trim.
+    trimmer: Option<SilenceTrimmer>,
+}
+
+impl<'a> Download<'a> {
+    fn new(
+        url: &'a str,
+        tx: Sender<Vec<f32>>,
+        cancel: Arc<AtomicBool>,
+        deck_name: &'static str,
+        live: Arc<AtomicBool>,
+        failure: FailureSlot,
+    ) -> Self {
+        Self {
+            url,
+            tx,
+            cancel,
+            deck_name,
+            live,
+            cache_writer: None,
+            failure,
+            total_samples: 0,
+            stream_offset: 0,
+            delivered: 0,
+            sent: 0,
+            first_sent: None,
+            retry_strategy: None,
+            bytes_received: None,
+            duration_ms: None,
+            attempt_start: Instant::now(),
+            metadata_sent: false,
+            open_decoder,
+            trimmer: None,
         }
+    }
 
-        // Read 2 bytes (16 bit)
-        match reader.read_i16::<LE>() {
-            Ok(sample_i16) => {
-                let sample_f32 = sample_i16 as f32 / 32768.0;
-                buffer.push(sample_f32);
-                total_samples += 1;
+    /// Position in the track of the next sample read.
+    fn position(&self) -> usize {
+        self.stream_offset + self.total_samples
+    }
 
-                // Signals watchdog that data is arriving
-                if !first_data_arrived.load(Ordering::Relaxed) {
-                    first_data_arrived.store(true, Ordering::Relaxed);
-                    send_log(
-                        "info",
-                        &format!(
-                            "📦 [Deck {}] First audio data received after {}ms",
-                            deck_name,
-                            stream_start.elapsed().as_millis()
-                        ),
-                    );
-                }
+    fn is_live(&self) -> bool {
+        self.live.load(Ordering::Relaxed)
+    }
 
-                // Sends in ~20ms chunks for buffer_ready reactivity
-                if buffer.len() >= 1920 {
-                    if tx.send(std::mem::take(&mut buffer)).is_err() {
-                        send_log(
-                            "info",
-                            &format!("🛑 [Deck {}] Receiver closed, stopping download", deck_name),
-                        );
-                        cancelled = true;
-                        break;
-                    }
-                    buffer.reserve(1920);
-                }
+    /// From now on the stream is live: the deck is told, and nothing of it is
+    /// cached.
+    fn mark_live(&mut self) {
+        self.live.store(true, Ordering::Relaxed);
+        self.cache_writer = None;
+        emit(OutputEvent::LiveStream {
+            deck: self.deck_name,
+        });
+    }
+
+    /// Sends what the source knows about the track, the first time it knows
+    /// something.
+    fn announce_metadata(&mut self, info: Option<TrackInfo>) {
+        if self.metadata_sent {
+            return;
+        }
+        if let Some(info) = info {
+            self.metadata_sent = true;
+            emit(OutputEvent::TrackMetadata {
+                deck: self.deck_name,
+                info,
+            });
+        }
+    }
+
+    /// Emits `silence_trimmed` for `samples` cut from one end of the track.
+    fn report_trim(&self, edge: TrimEdge, samples: usize) {
+        let trimmed_ms = samples_to_ms(samples);
+        send_log(
+            "info",
+            &format!(
+                "✂️ [Deck {}] Trimmed {}ms of silence at the {}",
+                self.deck_name,
+                trimmed_ms,
+                edge.as_str()
+            ),
+        );
+        emit(OutputEvent::SilenceTrimmed {
+            deck: self.deck_name,
+            edge,
+            trimmed_ms,
+        });
+    }
+
+    /// Asks for the length of the track until someone knows it: yt-dlp prints
+    /// it once it found the video, after the download started.
+    fn learn_duration(&mut self, pcm: &dyn PcmStream, source: Option<&dyn Source>) {
+        if self.duration_ms.is_none() {
+            self.duration_ms = source
+                .and_then(|source| source.duration_ms())
+                .or_else(|| pcm.duration_ms().map(|ms| samples_to_ms(self.stream_offset) + ms));
+        }
+    }
+
+    /// Whether the audio read so far reaches the announced end of the track.
+    /// False while its length is unknown.
+    fn reached_end(&self) -> bool {
+        self.duration_ms
+            .is_some_and(|duration_ms| samples_to_ms(self.position()) + DURATION_SLACK_MS >= duration_ms)
+    }
+
+    /// Most silence the trimmer may hold back right now, in samples. None
+    /// while the announced end of the track is further away than the trimmer
+    /// trims, so a pause mid-song goes straight to the deck; and whatever the
+    /// length, never so much that the deck could run short.
+    fn silence_hold_limit(&self) -> usize {
+        let Some(trimmer) = self.trimmer.as_ref() else {
+            return 0;
+        };
+        if let Some(duration_ms) = self.duration_ms {
+            let remaining = ms_to_samples(duration_ms).saturating_sub(self.position());
+            if remaining > trimmer.cap() + ms_to_samples(DURATION_SLACK_MS) {
+                return 0;
+            }
+        }
+        let Some(first_sent) = self.first_sent else {
+            return 0;
+        };
+        let playable_ms = (first_sent.elapsed().as_secs_f32() * MAX_RATE * 1000.0) as u64;
+        self.sent.saturating_sub(ms_to_samples(playable_ms + SILENCE_HOLD_MARGIN_MS))
+    }
+
+    /// Hands `samples` to the deck. False once the deck is gone.
+    fn send(&mut self, samples: Vec<f32>) -> bool {
+        self.sent += samples.len();
+        self.first_sent.get_or_insert_with(Instant::now);
+        self.tx.send(samples).is_ok()
+    }
+
+    /// Emits `download_progress`: how much of the track is decoded, and how
+    /// fast the current attempt decodes it compared with playback.
+    fn report_progress(&self, finished: bool) {
+        let elapsed_secs = self.attempt_start.elapsed().as_secs_f32();
+        let realtime_factor = if elapsed_secs > 0.0 {
+            samples_to_ms(self.total_samples) as f32 / 1000.0 / elapsed_secs
+        } else {
+            0.0
+        };
+        emit(OutputEvent::DownloadProgress {
+            deck: self.deck_name,
+            decoded_ms: samples_to_ms(self.position()),
+            realtime_factor,
+            finished,
+            bytes_received: self
+                .bytes_received
+                .as_ref()
+                .map(|bytes| bytes.load(Ordering::Relaxed)),
+            duration_ms: self.duration_ms.filter(|_| !self.is_live()),
+        });
+    }
+
+    /// Forwards decoded audio to the deck until the decoder stops. `source`,
+    /// when there is one, is asked whether it turned out to be live once
+    /// audio flows.
+    fn read_pcm(
+        &mut self,
+        pcm: &mut dyn PcmStream,
+        source: Option<&dyn Source>,
+        first_data_arrived: &AtomicBool,
+    ) -> ReadEnd {
+        // RAW DATA READER - ADVANCED VERSION
+        let mut buffer: Vec<f32> = Vec::with_capacity(8192);
+        let stream_start = Instant::now();
+        let deck_name = self.deck_name;
+        let progress_interval = Duration::from_millis(get_download_progress_interval_ms());
+        let mut last_progress = stream_start;
+        let mut duration_checked: Option<Instant> = None;
+        let mut hold_limit = self.silence_hold_limit();
+        self.attempt_start = stream_start;
+
+        let end = loop {
+            // Checks if download was canceled (deck replaced)
+            if self.cancel.load(Ordering::Relaxed) {
+                send_log(
+                    "info",
+                    &format!(
+                        "🛑 [Deck {}] Download cancelled, killing processes",
+                        deck_name
+                    ),
+                );
+                break ReadEnd::Cancelled;
             }
-            Err(e) => {
-                let stream_duration_ms = stream_start.elapsed().as_millis() as u64;
-                let audio_seconds = total_samples / (SAMPLE_RATE * CHANNELS);
-
-                if e.kind() == io::ErrorKind::UnexpectedEof {
-                    // Normal song end - send remaining buffer
-                    if !buffer.is_empty() {
-                        let _ = tx.send(std::mem::take(&mut buffer));
+
+            match pcm.next_sample() {
+                Ok(sample_f32) => {
+                    self.total_samples += 1;
+                    if let Some(writer) = self.cache_writer.as_mut() {
+                        writer.push(sample_f32);
                     }
-                    if total_samples == 0 {
-                        send_log(
-                            "error",
-                            "❌ CRITICAL: 0 samples downloaded - yt-dlp or ffmpeg failed!",
-                        );
-                        send_log("error", "Check: (1) yt-dlp is installed correctly");
-                        send_log(
-                            "error",
-                            "Check: (2) the YouTube URL is valid and reachable",
-                        );
-                        send_log(
-                            "error",
-                            &format!(
-                                "Check: (3) SOCKS proxy/tunnel active (proxy: {})",
-                                proxy_url
-                                    .as_deref()
-                                    .unwrap_or("none")
-                            ),
-                        );
-                        if let Ok(buf) = stderr_lines.lock() {
-                            if !buf.is_empty() {
-                                send_log("error", "Last yt-dlp stderr messages:");
-                                for line in buf.iter().rev().take(8).rev() {
-                                    send_log("error", &format!("[yt-dlp] {}", line));
+                    if self.position() > self.delivered {
+                        match self.trimmer.as_mut() {
+                            Some(trimmer) => {
+                                if let Some(trimmed) = trimmer.push(sample_f32, &mut buffer, hold_limit) {
+                                    self.report_trim(TrimEdge::Start, trimmed);
                                 }
                             }
+                            None => buffer.push(sample_f32),
                         }
-                    } else if audio_seconds < 10 {
-                        // PREMATURE TERMINATION - important to log
-                        send_log("error", &format!("⚠️ PREMATURE STREAM END: only {} seconds of audio after {}ms of streaming!", audio_seconds, stream_duration_ms));
-                        send_log("error", "This likely indicates that yt-dlp or ffmpeg failed mid-stream (possible Opus codec issue)");
-                    } else {
+                    }
+
+                    // Signals watchdog that data is arriving
+                    if !first_data_arrived.load(Ordering::Relaxed) {
+                        first_data_arrived.store(true, Ordering::Relaxed);
                         send_log(
-                            "debug",
+                            "info",
                             &format!(
-                                "Song finished ({} seconds, {} samples total)",
-                                audio_seconds, total_samples
+                                "📦 [Deck {}] First audio data received after {}ms",
+                                deck_name,
+                                stream_start.elapsed().as_millis()
                             ),
                         );
+                        if !self.is_live() && source.is_some_and(|source| source.detected_live()) {
+                            self.mark_live();
+                        }
+                        if self.is_live() {
+                            if let Some(trimmer) = self.trimmer.take() {
+                                trimmer.release(&mut buffer);
+                            }
+                        }
+                        self.announce_metadata(source.and_then(|source| source.metadata()));
+                        if let Some(strategy) = self.retry_strategy.take() {
+                            send_log(
+                                "info",
+                                &format!("✅ [Deck {}] Download recovered with fallback: {}", deck_name, strategy),
+                            );
+                        }
                     }
-                } else {
-                    // Actual read error (e.g. yt-dlp crashed or pipe broken)
+
+                    // Sends in ~20ms chunks for buffer_ready reactivity
+                    if buffer.len() >= 1920 {
+                        let chunk = std::mem::take(&mut buffer);
+                        if !self.send(chunk) {
+                            send_log(
+                                "info",
+                                &format!("🛑 [Deck {}] Receiver closed, stopping download", deck_name),
+                            );
+                            break ReadEnd::Cancelled;
+                        }
+                        buffer.reserve(1920);
+
+                        if self.trimmer.is_some() {
+                            if self.duration_ms.is_none()
+                                && duration_checked.is_none_or(|at| at.elapsed() >= DURATION_CHECK_INTERVAL)
+                            {
+                                self.learn_duration(&*pcm, source);
+                                duration_checked = Some(Instant::now());
+                            }
+                            hold_limit = self.silence_hold_limit();
+                        }
+
+                        if !progress_interval.is_zero() && last_progress.elapsed() >= progress_interval {
+                            self.learn_duration(&*pcm, source);
+                            self.report_progress(false);
+                            last_progress = Instant::now();
+                        }
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break ReadEnd::Eof,
+                Err(e) => {
+                    #[cfg(feature = "native-decoder")]
+                    if let Some(err) = e.get_ref().and_then(|inner| inner.downcast_ref::<DecodeError>()) {
+                        err.report(deck_name);
+                        self.failure.record(FailureCode::DecodeFailed);
+                    }
+                    break ReadEnd::Failed(e);
+                }
+            }
+        };
+
+        // Sends remaining data only if NOT canceled
+        if !matches!(end, ReadEnd::Cancelled) && !buffer.is_empty() {
+            self.send(buffer);
+        }
+        self.learn_duration(&*pcm, source);
+        end
+    }
+
+    /// One connection: opens the source and its decoder and forwards their
+    /// audio until it stops. Returns how it stopped, and whether every
+    /// process it started exited cleanly.
+    fn run_session(&mut self, source: &mut dyn Source) -> Result<(ReadEnd, bool)> {
+        let opened = source.open(&self.cancel)?;
+        let mut upstream_child = opened.upstream;
+        // Bytes the engine can see pass through a counter; a location is
+        // opened by the decoder itself
+        let input = match opened.input {
+            DecoderInput::Pipe(stdout) => DecoderInput::Stream(Box::new(self.counted(stdout))),
+            DecoderInput::Stream(reader) => DecoderInput::Stream(Box::new(self.counted(reader))),
+            location @ DecoderInput::Location(_) => location,
+        };
+        let (mut pcm, mut ffmpeg_child) =
+            match (self.open_decoder)(
+                input,
+                &opened.input_args,
+                &self.cancel,
+                self.deck_name,
+                &self.failure,
+            ) {
+                Ok(decoder) => decoder,
+                Err(e) => {
+                    if let Some(child) = upstream_child.as_mut() {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                    }
+                    return Err(e);
+                }
+            };
+
+        emit(OutputEvent::StreamOpened {
+            deck: self.deck_name,
+            url: self.url.to_string(),
+        });
+
+        // ── Download watchdog ──────────────────────────────────────
+        let first_data_arrived = spawn_watchdog(
+            self.deck_name,
+            upstream_child.as_ref().map(|child| child.id()),
+            ffmpeg_child.as_ref().map(|child| child.id()),
+            &self.cancel,
+            &self.failure,
+        );
+
+        let end = self.read_pcm(pcm.as_mut(), Some(&*source), &first_data_arrived);
+        // An in-process decoder may own the source's pipe: closing it lets
+        // the source exit
+        drop(pcm);
+
+        // If canceled, kill processes immediately to free resources and
+        // prevent concurrent downloads of the same URL from blocking each other
+        if matches!(end, ReadEnd::Cancelled) {
+            for child in upstream_child.iter_mut().chain(ffmpeg_child.iter_mut()) {
+                let _ = child.kill();
+            }
+        }
+
+        // Wait for processes to terminate (after kill it's immediate)
+        let mut clean_exit = true;
+        for child in upstream_child.iter_mut().chain(ffmpeg_child.iter_mut()) {
+            if !child.wait().is_ok_and(|status| status.success()) {
+                clean_exit = false;
+            }
+        }
+        Ok((end, clean_exit))
+    }
+
+    fn counted<R: Read>(&mut self, inner: R) -> CountingReader<R> {
+        CountingReader {
+            inner,
+            bytes: self.bytes_received.get_or_insert_with(Default::default).clone(),
+        }
+    }
+
+    /// Logs how a track (not a live stream) ended. Returns true when it
+    /// reached its end normally: a candidate for the track cache.
+    fn report_end(&self, end: &ReadEnd, source: &dyn Source, stream_start: Instant) -> bool {
+        let total_samples = self.total_samples;
+        let stream_duration_ms = stream_start.elapsed().as_millis() as u64;
+        let audio_seconds = self.position() / (SAMPLE_RATE * CHANNELS);
+
+        match end {
+            ReadEnd::Cancelled => false,
+            ReadEnd::Eof => {
+                if total_samples == 0 {
                     send_log(
                         "error",
                         &format!(
-                            "❌ CRITICAL READ ERROR: {} (read {} samples / {} sec total)",
-                            e, total_samples, audio_seconds
+                            "❌ CRITICAL: 0 samples downloaded - {} or ffmpeg failed!",
+                            source.kind()
                         ),
                     );
+                    for hint in source.failure_hints() {
+                        send_log("error", &hint);
+                    }
+                    false
+                } else if audio_seconds < 10 {
+                    // PREMATURE TERMINATION - important to log
+                    send_log("error", &format!("⚠️ PREMATURE STREAM END: only {} seconds of audio after {}ms of streaming!", audio_seconds, stream_duration_ms));
+                    send_log("error", &format!("This likely indicates that {} or ffmpeg failed mid-stream (possible Opus codec issue)", source.kind()));
+                    false
+                } else {
                     send_log(
-                        "error",
-                        "This means the yt-dlp/ffmpeg pipe is broken or crashed",
+                        "debug",
+                        &format!(
+                            "Song finished ({} seconds, {} samples total)",
+                            audio_seconds,
+                            self.position()
+                        ),
                     );
-                    // If it's a broken pipe error, it could be due to process issues
-                    if total_samples > 0 {
-                        send_log(
-                            "debug",
-                            "Attempted to continue playback with partial audio loaded",
-                        );
+                    true
+                }
+            }
+            ReadEnd::Failed(e) => {
+                // Actual read error (e.g. the source crashed or pipe broken)
+                send_log(
+                    "error",
+                    &format!(
+                        "❌ CRITICAL READ ERROR: {} (read {} samples / {} sec total)",
+                        e, total_samples, audio_seconds
+                    ),
+                );
+                send_log(
+                    "error",
+                    "This means the source/ffmpeg pipe is broken or crashed",
+                );
+                // If it's a broken pipe error, it could be due to process issues
+                if total_samples > 0 {
+                    send_log(
+                        "debug",
+                        "Attempted to continue playback with partial audio loaded",
+                    );
+                }
+                false
+            }
+        }
+    }
+
+    /// Prepares another attempt at a download that produced no audio or
+    /// stopped within seconds, with the source's next fallback strategy.
+    /// Returns false when there is nothing left to try.
+    fn retry(&mut self, source: &mut dyn Source, cache: Option<&TrackCache>, retries: &mut u32) -> bool {
+        if let Some(code) = source.failure() {
+            self.failure.record(code);
+        }
+        let reason = self.failure.get().unwrap_or(FailureCode::Unknown);
+        // Private, removed, or no yt-dlp binary: no strategy brings it back
+        if *retries >= get_download_retries()
+            || matches!(reason, FailureCode::Unavailable | FailureCode::SpawnFailed)
+            || self.cancel.load(Ordering::Relaxed)
+        {
+            return false;
+        }
+        let Some(strategy) = source.next_strategy() else {
+            return false;
+        };
+        *retries += 1;
+
+        let delay_ms = RETRY_BASE_MS << (*retries - 1);
+        send_log(
+            "info",
+            &format!(
+                "🔁 [Deck {}] Download retry {} in {}ms ({}): {}",
+                self.deck_name,
+                retries,
+                delay_ms,
+                reason.as_str(),
+                strategy
+            ),
+        );
+        emit(OutputEvent::DownloadRetry {
+            deck: self.deck_name,
+            attempt: *retries,
+            strategy,
+            reason,
+            delay_ms,
+        });
+        if !self.wait_unless_cancelled(Duration::from_millis(delay_ms)) {
+            return false;
+        }
+
+        self.retry_strategy = Some(strategy);
+        self.restart(source, cache);
+        true
+    }
+
+    /// Picks up a stream that broke mid-song (the source or the decoder died)
+    /// where it stopped. Returns false once it broke too many times.
+    fn resume(&mut self, source: &mut dyn Source, cache: Option<&TrackCache>, resumes: &mut u32) -> bool {
+        if *resumes >= MAX_RESUMES || self.cancel.load(Ordering::Relaxed) {
+            return false;
+        }
+        *resumes += 1;
+        let position_ms = samples_to_ms(self.position());
+        send_log(
+            "error",
+            &format!(
+                "⏯️ [Deck {}] Stream broke at {}s, resuming ({}/{})",
+                self.deck_name,
+                position_ms / 1000,
+                resumes,
+                MAX_RESUMES
+            ),
+        );
+        emit(OutputEvent::DownloadResumed {
+            deck: self.deck_name,
+            attempt: *resumes,
+            position_ms,
+        });
+        self.restart(source, cache);
+        true
+    }
+
+    /// Sets up the next attempt to continue after the audio the deck already
+    /// has: from there if the source can start mid-track, otherwise from the
+    /// top, with what the deck already has skipped.
+    fn restart(&mut self, source: &mut dyn Source, cache: Option<&TrackCache>) {
+        self.delivered = self.delivered.max(self.position());
+        let position_ms = samples_to_ms(self.delivered);
+        self.stream_offset = if position_ms > 0 && source.resume_from(position_ms) {
+            ms_to_samples(position_ms)
+        } else {
+            source.resume_from(0);
+            0
+        };
+        self.total_samples = 0;
+        // A spliced track is not cached: only an attempt from the top can be
+        self.cache_writer = cache
+            .filter(|_| self.stream_offset == 0)
+            .and_then(|cache| cache.writer(self.url));
+    }
+
+    /// Sleeps before another attempt. Returns false if the deck was cancelled
+    /// meanwhile.
+    fn wait_unless_cancelled(&self, delay: Duration) -> bool {
+        let until = Instant::now() + delay;
+        while Instant::now() < until {
+            if self.cancel.load(Ordering::Relaxed) {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        !self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// Streams the track from the cache, or from its source, reconnecting a
+    /// live stream as long as it keeps coming back.
+    fn run(&mut self, source: &mut dyn Source, cache: Option<TrackCache>) -> Result<()> {
+        let url = self.url;
+        let deck_name = self.deck_name;
+
+        if let Some(file) = cache.as_ref().and_then(|cache| cache.open(url)) {
+            send_log(
+                "info",
+                &format!("Streaming: {} (cache)", shortened(url, 60)),
+            );
+            emit(OutputEvent::StreamOpened {
+                deck: deck_name,
+                url: url.to_string(),
+            });
+            // Stored as 16-bit samples
+            self.duration_ms = file
+                .metadata()
+                .ok()
+                .map(|metadata| samples_to_ms(metadata.len() as usize / 2));
+            // What the source told when the track was stored; at least its length
+            let mut info = cache
+                .as_ref()
+                .and_then(|cache| cache.info(url))
+                .unwrap_or_default();
+            info.duration_ms = info.duration_ms.or(self.duration_ms);
+            self.announce_metadata(Some(info));
+            let stream_start = Instant::now();
+            let end = self.read_pcm(&mut BufReader::new(file), None, &AtomicBool::new(false));
+            self.report_end(&end, source, stream_start);
+            return Ok(());
+        }
+
+        send_log(
+            "info",
+            &format!("Streaming: {} ({})", shortened(url, 60), source.kind()),
+        );
+        self.cache_writer = cache.as_ref().and_then(|cache| cache.writer(url));
+
+        let mut reconnects = 0;
+        let mut retries = 0;
+        let mut resumes = 0;
+        loop {
+            let stream_start = Instant::now();
+            let samples_before = self.total_samples;
+            let session = self.run_session(source);
+
+            if !self.is_live() {
+                match session {
+                    Ok((end, clean_exit)) => {
+                        if self.stream_offset > 0
+                            && self.total_samples == 0
+                            && matches!(end, ReadEnd::Eof)
+                            && clean_exit
+                            && self.reached_end()
+                        {
+                            // Resumed right at the end: the "break" was the track finishing
+                            send_log("debug", "Song finished (nothing left after resuming)");
+                            return Ok(());
+                        }
+                        // An empty resumed attempt short of the end failed: it is
+                        // retried from the same position below
+                        let complete = self.report_end(&end, source, stream_start);
+                        // Only a whole track is cached: a stream cut short by a failing
+                        // process would otherwise be replayed cut short every time
+                        if let Some(writer) = self.cache_writer.take().filter(|_| complete && clean_exit) {
+                            if let Err(e) = writer.commit(source.metadata().as_ref()) {
+                                send_log("error", &format!("Track cache: not stored: {}", e));
+                            }
+                        }
+                        // A process dying mid-song: continue where the audio stopped
+                        let broke_mid_stream = self.total_samples > 0
+                            && match end {
+                                ReadEnd::Cancelled => false,
+                                ReadEnd::Eof => complete && !clean_exit,
+                                ReadEnd::Failed(_) => true,
+                            };
+                        if broke_mid_stream {
+                            if self.resume(source, cache.as_ref(), &mut resumes) {
+                                continue;
+                            }
+                            return Ok(());
+                        }
+                        // Retried: no audio at all, or a premature end
+                        let failed_early = match end {
+                            ReadEnd::Cancelled => false,
+                            ReadEnd::Eof => !complete,
+                            ReadEnd::Failed(_) => true,
+                        };
+                        if !failed_early {
+                            return Ok(());
+                        }
+                        if self.retry(source, cache.as_ref(), &mut retries) {
+                            continue;
+                        }
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        if self.retry(source, cache.as_ref(), &mut retries) {
+                            continue;
+                        }
+                        return Err(e);
                     }
                 }
-                break;
+            }
+
+            match session {
+                Ok((ReadEnd::Cancelled, _)) => return Ok(()),
+                Ok((ReadEnd::Eof, _)) => {
+                    send_log("error", &format!("📡 [Deck {}] Live stream ended", deck_name));
+                }
+                Ok((ReadEnd::Failed(e), _)) => {
+                    send_log("error", &format!("📡 [Deck {}] Live stream dropped: {}", deck_name, e));
+                }
+                Err(e) => {
+                    send_log("error", &format!("📡 [Deck {}] Live stream unreachable: {}", deck_name, e));
+                }
+            }
+
+            if self.total_samples - samples_before >= LIVE_STABLE_SAMPLES {
+                reconnects = 0;
+            }
+            reconnects += 1;
+            if reconnects > MAX_LIVE_RECONNECTS {
+                send_log(
+                    "error",
+                    &format!(
+                        "📡 [Deck {}] Live stream lost, giving up after {} reconnections",
+                        deck_name, MAX_LIVE_RECONNECTS
+                    ),
+                );
+                return Ok(());
+            }
+            let delay_ms = (LIVE_RECONNECT_BASE_MS << (reconnects - 1)).min(LIVE_RECONNECT_MAX_MS);
+            emit(OutputEvent::StreamReconnecting {
+                deck: deck_name,
+                attempt: reconnects,
+                delay_ms,
+            });
+            if !self.wait_unless_cancelled(Duration::from_millis(delay_ms)) {
+                return Ok(());
             }
         }
     }
+}
 
-    // If canceled, kill processes immediately to free resources and
-    // prevent concurrent downloads of the same URL from blocking each other
-    if cancelled {
-        let _ = yt_dlp_child.kill();
-        let _ = ffmpeg_child.kill();
+pub fn download_and_decode_advanced(
+    url: &str,
+    tx: Sender<Vec<f32>>,
+    cancel: Arc<AtomicBool>,
+    deck_name: &'static str,
+    live: Arc<AtomicBool>,
+    failure: FailureSlot,
+) -> Result<()> {
+    // Direct streaming flow:
+    // 1. the source opens the audio (yt-dlp on stdout, or a path/URL)
+    // 2. the decoder reads it, from yt-dlp's stdout or by opening it itself
+    // 3. the decoder returns PCM
+
+    let mut download = Download::new(url, tx, cancel, deck_name, live, failure);
+    let mut source = source_for(url, download.is_live(), deck_name);
+    let cache = TrackCache::from_config().filter(|_| source.cacheable() && !download.is_live());
+    if download.is_live() {
+        download.mark_live();
     } else {
-        // Sends remaining data only if NOT canceled
-        if !buffer.is_empty() {
-            let _ = tx.send(std::mem::take(&mut buffer));
+        download.trimmer = SilenceTrimmer::from_config();
+    }
+
+    let result = download.run(source.as_mut(), cache);
+    // yt-dlp's verdict on the video is in its stderr, complete once it exited
+    if let Some(code) = source.failure() {
+        download.failure.record(code);
+    }
+    // A replaced deck already belongs to the next track
+    if !download.cancel.load(Ordering::Relaxed) {
+        // What is still held back is either the track's tail or, for a
+        // track without sound, the whole of it
+        if let Some(mut trimmer) = download.trimmer.take() {
+            let mut rest = Vec::new();
+            let trimmed = trimmer.finish(&mut rest);
+            if !rest.is_empty() {
+                download.send(rest);
+            }
+            if trimmed > 0 {
+                download.report_trim(TrimEdge::End, trimmed);
+            }
+        }
+        if get_download_progress_interval_ms() > 0 {
+            download.report_progress(true);
         }
     }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::OpenedSource;
+    use std::collections::VecDeque;
+    use std::io::Cursor;
+
+    /// Sample `index` of the scripted track.
+    fn sample(index: usize) -> i16 {
+        (index % 30_000) as i16
+    }
 
-    // Wait for processes to terminate (after kill it's immediate)
-    let _ = yt_dlp_child.wait();
-    let _ = ffmpeg_child.wait();
+    fn seconds(seconds: usize) -> usize {
+        seconds * SAMPLE_RATE * CHANNELS
+    }
 
-    Ok(())
+    /// A stream that breaks instead of ending.
+    struct Broken;
+
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::ConnectionReset.into())
+        }
+    }
+
+    enum Ending {
+        Eof,
+        /// The stream errors out, like a pipe whose writer crashed.
+        Broken,
+        /// The stream ends, but the process behind it exits with a failure.
+        #[cfg(unix)]
+        FailedExit,
+    }
+
+    /// One connection: the samples `from..to` of the track, then `ending`.
+    struct Attempt {
+        from: usize,
+        to: usize,
+        ending: Ending,
+    }
+
+    /// A source playing back scripted attempts, already as 16-bit PCM.
+    struct Scripted {
+        attempts: VecDeque<Attempt>,
+        can_resume: bool,
+        duration_ms: Option<u64>,
+        /// The track itself, sample by sample.
+        pcm: fn(usize) -> i16,
+        /// Positions every `open` was asked to start at.
+        opened_at: Vec<u64>,
+        start_ms: u64,
+    }
+
+    impl Scripted {
+        fn new(can_resume: bool, attempts: Vec<Attempt>) -> Self {
+            Self {
+                attempts: attempts.into(),
+                can_resume,
+                duration_ms: None,
+                pcm: sample,
+                opened_at: Vec::new(),
+                start_ms: 0,
+            }
+        }
+    }
+
+    impl Source for Scripted {
+        fn kind(&self) -> &'static str {
+            "scripted"
+        }
+
+        fn open(&mut self, _: &Arc<AtomicBool>) -> Result<OpenedSource> {
+            self.opened_at.push(self.start_ms);
+            let attempt = self.attempts.pop_front().ok_or_else(|| anyhow!("no attempt left"))?;
+            let bytes: Vec<u8> = (attempt.from..attempt.to)
+                .flat_map(|index| (self.pcm)(index).to_le_bytes())
+                .collect();
+            let (input, upstream): (Box<dyn Read + Send + Sync>, Option<Child>) = match attempt.ending {
+                Ending::Eof => (Box::new(Cursor::new(bytes)), None),
+                Ending::Broken => (Box::new(Cursor::new(bytes).chain(Broken)), None),
+                #[cfg(unix)]
+                Ending::FailedExit => (
+                    Box::new(Cursor::new(bytes)),
+                    Some(ProcessCommand::new("false").spawn()?),
+                ),
+            };
+            Ok(OpenedSource {
+                input: DecoderInput::Stream(input),
+                input_args: Vec::new(),
+                upstream,
+            })
+        }
+
+        fn duration_ms(&self) -> Option<u64> {
+            self.duration_ms
+        }
+
+        fn metadata(&self) -> Option<TrackInfo> {
+            Some(TrackInfo {
+                title: Some("Scripted".to_string()),
+                duration_ms: self.duration_ms,
+                ..TrackInfo::default()
+            })
+        }
+
+        fn resume_from(&mut self, position_ms: u64) -> bool {
+            if position_ms > 0 && !self.can_resume {
+                return false;
+            }
+            self.start_ms = position_ms;
+            true
+        }
+
+        fn next_strategy(&mut self) -> Option<&'static str> {
+            Some("scripted fallback")
+        }
+    }
+
+    /// The scripted PCM goes straight to the deck: there is nothing to decode.
+    fn passthrough(
+        input: DecoderInput,
+        _: &[String],
+        _: &Arc<AtomicBool>,
+        _: &'static str,
+        _: &FailureSlot,
+    ) -> Result<(Box<dyn PcmStream>, Option<Child>)> {
+        match input {
+            DecoderInput::Stream(reader) => Ok((Box::new(BufReader::new(reader)), None)),
+            _ => Err(anyhow!("scripted sources only stream")),
+        }
+    }
+
+    /// Runs the download of `source` and returns what reached the deck.
+    fn run(source: &mut Scripted, cache: Option<TrackCache>) -> Vec<i16> {
+        run_trimmed(source, cache, None).concat()
+    }
+
+    /// `run`, with `trimmer` cutting silence like `download_and_decode_advanced`
+    /// does. Returns the chunks the deck got.
+    fn run_trimmed(source: &mut Scripted, cache: Option<TrackCache>, trimmer: Option<SilenceTrimmer>) -> Vec<Vec<i16>> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut download = Download::new(
+            "https://example.com/scripted",
+            tx,
+            Arc::new(AtomicBool::new(false)),
+            "A",
+            Arc::new(AtomicBool::new(false)),
+            FailureSlot::default(),
+        );
+        download.open_decoder = passthrough;
+        download.trimmer = trimmer;
+        let _ = download.run(source, cache);
+        if let Some(mut trimmer) = download.trimmer.take() {
+            let mut rest = Vec::new();
+            trimmer.finish(&mut rest);
+            download.send(rest);
+        }
+        drop(download);
+        rx.iter()
+            .map(|chunk| chunk.iter().map(|sample| (sample * 32768.0).round() as i16).collect())
+            .collect()
+    }
+
+    fn track(to: usize) -> Vec<i16> {
+        (0..to).map(sample).collect()
+    }
+
+    #[test]
+    fn a_retry_from_the_top_forwards_only_what_the_deck_lacks() {
+        let mut source = Scripted::new(
+            false,
+            vec![
+                // Premature end: retried with another strategy
+                Attempt { from: 0, to: seconds(1), ending: Ending::Eof },
+                Attempt { from: 0, to: seconds(12), ending: Ending::Eof },
+            ],
+        );
+        let received = run(&mut source, None);
+        assert_eq!(received.len(), seconds(12));
+        assert!(received == track(seconds(12)));
+        assert_eq!(source.opened_at, vec![0, 0]);
+    }
+
+    #[test]
+    fn a_premature_end_is_retried_where_it_stopped() {
+        let mut source = Scripted::new(
+            true,
+            vec![
+                // A clean end five seconds in is not the end of the track
+                Attempt { from: 0, to: seconds(5), ending: Ending::Eof },
+                Attempt { from: seconds(5), to: seconds(12), ending: Ending::Eof },
+            ],
+        );
+        let received = run(&mut source, None);
+        assert!(received == track(seconds(12)));
+        assert_eq!(source.opened_at, vec![0, 5000]);
+    }
+
+    #[test]
+    fn a_source_failing_before_any_audio_is_retried() {
+        let mut source = Scripted::new(
+            true,
+            vec![
+                Attempt { from: 0, to: 0, ending: Ending::Broken },
+                Attempt { from: 0, to: seconds(12), ending: Ending::Eof },
+            ],
+        );
+        let received = run(&mut source, None);
+        assert_eq!(received.len(), seconds(12));
+        assert_eq!(source.opened_at, vec![0, 0]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_resume_that_fails_before_any_audio_is_not_the_end() {
+        let mut source = Scripted::new(
+            true,
+            vec![
+                Attempt { from: 0, to: seconds(12), ending: Ending::Broken },
+                // yt-dlp refused the resumed range: nothing, then exit 1
+                Attempt { from: seconds(12), to: seconds(12), ending: Ending::FailedExit },
+                Attempt { from: seconds(12), to: seconds(20), ending: Ending::Eof },
+            ],
+        );
+        source.duration_ms = Some(20_000);
+        let received = run(&mut source, None);
+        assert_eq!(received.len(), seconds(20));
+        assert!(received == track(seconds(20)));
+        assert_eq!(source.opened_at, vec![0, 12_000, 12_000]);
+    }
+
+    #[test]
+    fn a_resume_with_nothing_left_at_the_announced_end_is_the_end() {
+        let mut source = Scripted::new(
+            true,
+            vec![
+                Attempt { from: 0, to: seconds(12), ending: Ending::Broken },
+                Attempt { from: seconds(12), to: seconds(12), ending: Ending::Eof },
+            ],
+        );
+        source.duration_ms = Some(12_000);
+        assert_eq!(run(&mut source, None).len(), seconds(12));
+        // No attempt after the empty one
+        assert_eq!(source.opened_at, vec![0, 12_000]);
+    }
+
+    #[test]
+    fn a_spliced_track_is_never_cached() {
+        let dir = std::env::temp_dir().join(format!("mixer-download-splice-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = TrackCache::at(dir.clone());
+        let mut source = Scripted::new(
+            true,
+            vec![
+                Attempt { from: 0, to: seconds(12), ending: Ending::Broken },
+                Attempt { from: seconds(12), to: seconds(20), ending: Ending::Eof },
+            ],
+        );
+        assert_eq!(run(&mut source, Some(cache.clone())).len(), seconds(20));
+        assert!(cache.open("https://example.com/scripted").is_none());
+
+        // The same track read whole in one go is
+        let mut source = Scripted::new(
+            true,
+            vec![Attempt { from: 0, to: seconds(20), ending: Ending::Eof }],
+        );
+        run(&mut source, Some(cache.clone()));
+        assert!(cache.open("https://example.com/scripted").is_some());
+        // With what the source knew, for the next load to announce
+        let info = cache.info("https://example.com/scripted").unwrap();
+        assert_eq!(info.title.as_deref(), Some("Scripted"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn only_the_ends_of_a_resumed_track_are_trimmed() {
+        let mut source = Scripted::new(
+            true,
+            vec![
+                Attempt { from: 0, to: seconds(6), ending: Ending::Broken },
+                Attempt { from: seconds(6), to: seconds(12), ending: Ending::Eof },
+            ],
+        );
+        source.duration_ms = Some(12_000);
+        // Quiet for the first second, from 4 to 5 seconds and for the last two
+        source.pcm = |index| {
+            let quiet = index < seconds(1) || (seconds(4)..seconds(5)).contains(&index) || index >= seconds(10);
+            if quiet { 0 } else { 1000 }
+        };
+        let chunks = run_trimmed(&mut source, None, Some(SilenceTrimmer::new(-60.0, 3000)));
+        let expected: Vec<i16> = (seconds(1)..seconds(10)).map(source.pcm).collect();
+        assert!(chunks.concat() == expected);
+        // The pause was not held back until the sound came back
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 1920 + CHANNELS));
+    }
 }
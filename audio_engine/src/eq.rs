@@ -0,0 +1,225 @@
+//! Ten-band graphic equalizer, used on every deck and on the master bus.
+//!
+//! Retuning a filter bank in place while audio flows through it makes it ring,
+//! so a change builds a new bank and crossfades from the old one over a chunk.
+
+use crate::biquad::{Biquad, Coefficients};
+use crate::config::{CHANNELS, CHUNK_SIZE, SAMPLE_RATE};
+
+pub const BANDS: usize = 10;
+
+/// Octave band centres, in Hz.
+const BAND_FREQUENCIES: [f64; BANDS] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0
+];
+
+/// Bandwidth of each peaking filter: about one octave.
+const BAND_Q: f64 = 1.41;
+
+/// Limit of a single band's boost or cut.
+pub const MAX_BAND_GAIN_DB: f32 = 12.0;
+
+/// Samples an old bank keeps playing, fading out, after a change.
+const FADE_SAMPLES: usize = CHUNK_SIZE;
+
+pub type Gains = [f32; BANDS];
+
+/// Built-in settings Node.js can ask for by name.
+pub fn preset(name: &str) -> Option<Gains> {
+    match name {
+        "flat" => Some([0.0; BANDS]),
+        "bass_boost" => Some([6.0, 5.0, 4.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+        "vocal" => Some([-2.0, -2.0, -1.0, 0.0, 2.0, 3.0, 3.0, 2.0, 0.0, -1.0]),
+        "treble_boost" => Some([0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 3.0, 4.0, 5.0]),
+        _ => None
+    }
+}
+
+/// RBJ cookbook peaking filter.
+fn peaking(frequency: f64, gain_db: f32) -> Coefficients {
+    let a = 10f64.powf(gain_db as f64 / 40.0);
+    let w0 = std::f64::consts::TAU * frequency / SAMPLE_RATE as f64;
+    let alpha = w0.sin() / (2.0 * BAND_Q);
+    let cos = w0.cos();
+    let a0 = 1.0 + alpha / a;
+    Coefficients {
+        b0: (1.0 + alpha * a) / a0,
+        b1: -2.0 * cos / a0,
+        b2: (1.0 - alpha * a) / a0,
+        a1: -2.0 * cos / a0,
+        a2: (1.0 - alpha / a) / a0
+    }
+}
+
+#[derive(Clone)]
+struct Bank {
+    filters: [[Biquad; CHANNELS]; BANDS]
+}
+
+impl Bank {
+    fn new(gains: &Gains) -> Self {
+        Self {
+            filters: std::array::from_fn(|band| {
+                [Biquad::new(peaking(BAND_FREQUENCIES[band], gains[band])); CHANNELS]
+            })
+        }
+    }
+
+    /// Same bank with new gains, keeping each filter's history so the new
+    /// bank starts from where the old one was rather than from silence.
+    fn retuned(&self, gains: &Gains) -> Self {
+        let mut bank = self.clone();
+        for (band, filters) in bank.filters.iter_mut().enumerate() {
+            for filter in filters.iter_mut() {
+                filter.coefficients = peaking(BAND_FREQUENCIES[band], gains[band]);
+            }
+        }
+        bank
+    }
+
+    fn process(&mut self, channel: usize, sample: f32) -> f32 {
+        self.filters
+            .iter_mut()
+            .fold(sample as f64, |x, band| band[channel].process(x)) as f32
+    }
+}
+
+/// A bank being faded out after a change; `None` stands for the dry signal.
+struct Fade {
+    previous: Option<Bank>,
+    left: usize
+}
+
+fn run(bank: &mut Option<Bank>, channel: usize, sample: f32) -> f32 {
+    match bank {
+        Some(bank) => bank.process(channel, sample),
+        None => sample
+    }
+}
+
+pub struct Equalizer {
+    gains: Gains,
+    /// `None` while every band is flat: audio passes through untouched.
+    current: Option<Bank>,
+    fade: Option<Fade>,
+    /// Channel of the next interleaved sample.
+    channel: usize
+}
+
+impl Equalizer {
+    pub fn new() -> Self {
+        Self {
+            gains: [0.0; BANDS],
+            current: None,
+            fade: None,
+            channel: 0
+        }
+    }
+
+    pub fn gains(&self) -> &Gains {
+        &self.gains
+    }
+
+    /// Applies new band gains from the next sample on. Values are clamped to
+    /// the supported range; anything that is not a number counts as flat.
+    pub fn set_gains(&mut self, gains: Gains) {
+        let gains = gains.map(|g| {
+            if g.is_finite() {
+                g.clamp(-MAX_BAND_GAIN_DB, MAX_BAND_GAIN_DB)
+            } else {
+                0.0
+            }
+        });
+        if gains == self.gains {
+            return;
+        }
+        self.gains = gains;
+
+        let next = if gains.iter().all(|&g| g == 0.0) {
+            None
+        } else {
+            Some(match &self.current {
+                Some(bank) => bank.retuned(&gains),
+                None => Bank::new(&gains)
+            })
+        };
+        let previous = std::mem::replace(&mut self.current, next);
+        self.fade = Some(Fade {
+            previous,
+            left: FADE_SAMPLES
+        });
+    }
+
+    /// Filters one interleaved sample.
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let channel = self.channel;
+        self.channel = (channel + 1) % CHANNELS;
+
+        let wet = run(&mut self.current, channel, sample);
+        let Some(fade) = self.fade.as_mut() else {
+            return wet;
+        };
+
+        let old = run(&mut fade.previous, channel, sample);
+        let ratio = 1.0 - fade.left as f32 / FADE_SAMPLES as f32;
+        fade.left -= 1;
+        if fade.left == 0 {
+            self.fade = None;
+        }
+        old * (1.0 - ratio) + wet * ratio
+    }
+}
+
+impl Default for Equalizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Peak output level of a stereo sine at `frequency` after the EQ settles.
+    fn response(eq: &mut Equalizer, frequency: f32) -> f32 {
+        let mut peak = 0.0f32;
+        for i in 0..SAMPLE_RATE / 2 {
+            let s = 0.1 * (i as f32 * frequency * std::f32::consts::TAU / SAMPLE_RATE as f32).sin();
+            let l = eq.process(s);
+            eq.process(s);
+            if i > SAMPLE_RATE / 4 {
+                peak = peak.max(l.abs());
+            }
+        }
+        peak
+    }
+
+    #[test]
+    fn flat_eq_is_transparent() {
+        let mut eq = Equalizer::new();
+        assert_eq!(eq.process(0.25), 0.25);
+        eq.set_gains(preset("flat").unwrap());
+        assert_eq!(eq.process(-0.5), -0.5);
+    }
+
+    #[test]
+    fn bass_boost_lifts_lows_and_leaves_highs() {
+        let mut eq = Equalizer::new();
+        eq.set_gains(preset("bass_boost").unwrap());
+        assert!(response(&mut eq, 60.0) > 0.15);
+        let high = response(&mut eq, 5000.0);
+        assert!((high - 0.1).abs() < 0.01, "{}", high);
+    }
+
+    #[test]
+    fn changes_fade_in_over_one_chunk() {
+        let mut eq = Equalizer::new();
+        eq.set_gains(preset("bass_boost").unwrap());
+        // The first sample is still the dry signal
+        assert_eq!(eq.process(0.5), 0.5);
+        for _ in 1..FADE_SAMPLES {
+            eq.process(0.0);
+        }
+        assert!(eq.fade.is_none());
+    }
+}
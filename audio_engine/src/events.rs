@@ -1,15 +1,17 @@
-//! Edge-triggered notifications the mixer sends to Node.js about the deck that
-//! is currently being heard. Both are suppressed during a crossfade, when
-//! "the active deck" is momentarily ambiguous.
+//! Notifications the mixer sends to Node.js about the deck that is currently
+//! being heard. The edge-triggered ones are suppressed during a crossfade, when
+//! "the active deck" is momentarily ambiguous; the periodic position report
+//! covers both decks of a fade instead.
 
 use crate::config::{CHANNELS, SAMPLE_RATE};
-use crate::protocol::send_log;
+use crate::protocol::{emit, OutputEvent};
 use crate::state::MixerState;
 
 /// Audio actually pushed out before a playback counts as real (1 second).
 const PLAYBACK_CONFIRM_THRESHOLD: usize = SAMPLE_RATE * CHANNELS;
 
-/// Remaining audio at which the track is announced as nearly over (3 seconds).
+/// Remaining audio at which the track is announced as nearly over (3 seconds
+/// of output, whatever the deck's playback rate).
 const APPROACHING_END_THRESHOLD: usize = SAMPLE_RATE * CHANNELS * 3;
 
 /// Reports that the active deck is really producing audio.
@@ -27,7 +29,9 @@ pub fn emit_playback_confirmed(state: &mut MixerState) {
     }
 
     state.active_mut().play_confirmed_sent = true;
-    send_log("playback_confirmed", state.active_deck);
+    emit(OutputEvent::PlaybackConfirmed {
+        deck: state.active_deck
+    });
 }
 
 /// Reports that the active deck is about to run out, giving Node.js the chance
@@ -38,14 +42,35 @@ pub fn emit_approaching_end(state: &mut MixerState) {
     }
 
     let deck = state.active();
+    // A live stream that stops was cut, not finished: nothing to fade out of
     let approaching = deck.has_ended
+        && !deck.is_live()
         && deck.receiver.is_none()
         && !deck.approaching_end_sent
-        && deck.available_samples() < APPROACHING_END_THRESHOLD;
+        && deck.remaining_output_samples() < APPROACHING_END_THRESHOLD;
     if !approaching {
         return;
     }
 
     state.active_mut().approaching_end_sent = true;
-    send_log("approaching_end", state.active_deck);
+    emit(OutputEvent::ApproachingEnd {
+        deck: state.active_deck
+    });
+}
+
+/// Reports where playback stands, for the dashboard progress bar.
+///
+/// During a crossfade the target deck is reported too, so Node.js already knows
+/// how far into the next track the fade has taken it when the switch happens.
+pub fn emit_position(state: &MixerState) {
+    let target = state.crossfade.as_ref().map(|c| c.target);
+    for name in std::iter::once(state.active_deck).chain(target) {
+        let deck = state.deck(name);
+        emit(OutputEvent::Position {
+            deck: name,
+            played_ms: deck.played_ms(),
+            buffered_ms: deck.buffered_ms(),
+            duration_ms: deck.duration_ms()
+        });
+    }
 }
@@ -0,0 +1,176 @@
+//! Why a download failed: the stderr of yt-dlp and ffmpeg, the watchdog and
+//! the decoder all leave a clue, and the most telling one is reported with
+//! `deck_failed` so Node.js can tell a song that will never play apart from
+//! a network hiccup.
+
+use std::sync::{Arc, Mutex};
+
+use crate::protocol::FailureCode;
+
+/// How much a code says about the failure: a yt-dlp verdict on the video
+/// explains the empty pipe ffmpeg then complains about, not the other way
+/// round.
+pub fn weight(code: FailureCode) -> u8 {
+    match code {
+        FailureCode::AgeRestricted
+        | FailureCode::GeoBlocked
+        | FailureCode::Unavailable
+        | FailureCode::SignInRequired
+        | FailureCode::RateLimited
+        | FailureCode::ProxyUnreachable => 3,
+        FailureCode::SpawnFailed | FailureCode::WatchdogTimeout => 2,
+        FailureCode::Network | FailureCode::DecodeFailed => 1,
+        FailureCode::Stalled | FailureCode::Unknown => 0
+    }
+}
+
+/// Failure cause of one load, shared between the deck and the threads of its
+/// download.
+#[derive(Clone, Default)]
+pub struct FailureSlot(Arc<Mutex<Option<FailureCode>>>);
+
+impl FailureSlot {
+    /// Keeps `code` unless a more telling one is already there.
+    pub fn record(&self, code: FailureCode) {
+        if let Ok(mut current) = self.0.lock() {
+            if current.is_none_or(|current| weight(code) > weight(current)) {
+                *current = Some(code);
+            }
+        }
+    }
+
+    pub fn get(&self) -> Option<FailureCode> {
+        self.0.lock().ok().and_then(|current| *current)
+    }
+}
+
+/// Reads a yt-dlp stderr line.
+pub fn classify_ytdlp(line: &str) -> Option<FailureCode> {
+    let lower = line.to_lowercase();
+    let has = |needles: &[&str]| needles.iter().any(|needle| lower.contains(needle));
+
+    // Age checks also ask to sign in: test them first
+    if has(&["confirm your age", "age-restricted", "age restricted", "inappropriate for some users"]) {
+        Some(FailureCode::AgeRestricted)
+    } else if has(&["in your country", "geo restrict", "geo-restrict"]) {
+        Some(FailureCode::GeoBlocked)
+    } else if has(&[
+        "private video",
+        "video unavailable",
+        "has been removed",
+        "no longer available",
+        "account associated with this video has been terminated",
+        "members-only"
+    ]) {
+        Some(FailureCode::Unavailable)
+    } else if has(&["sign in", "login required", "use --cookies"]) {
+        Some(FailureCode::SignInRequired)
+    } else if has(&["http error 429", "too many requests", "rate-limit", "rate limit"]) {
+        Some(FailureCode::RateLimited)
+    } else if has(&["proxyerror", "proxy", "socks"]) && has(&["error", "unable", "failed", "refused"]) {
+        Some(FailureCode::ProxyUnreachable)
+    } else if has(&["http error 404", "http error 410"]) {
+        // A bare HTTP answer is the media URL expiring far more often than
+        // the video going away, which the extractor words itself
+        Some(FailureCode::Network)
+    } else {
+        classify_network(&lower)
+    }
+}
+
+/// Reads an ffmpeg stderr line: ffmpeg fetches direct links itself, so HTTP
+/// answers show up here too.
+pub fn classify_ffmpeg(line: &str) -> Option<FailureCode> {
+    let lower = line.to_lowercase();
+    let has = |needles: &[&str]| needles.iter().any(|needle| lower.contains(needle));
+
+    if has(&["404 not found", "410 gone"]) {
+        Some(FailureCode::Unavailable)
+    } else if has(&["429 too many requests"]) {
+        Some(FailureCode::RateLimited)
+    } else if has(&[
+        "invalid data found",
+        "could not find codec",
+        "error while decoding",
+        "decoding error",
+        "unsupported codec"
+    ]) {
+        Some(FailureCode::DecodeFailed)
+    } else {
+        classify_network(&lower)
+    }
+}
+
+fn classify_network(lower: &str) -> Option<FailureCode> {
+    let network = [
+        "connection refused",
+        "connection reset",
+        "timed out",
+        "network is unreachable",
+        "temporary failure in name resolution",
+        "name or service not known",
+        "failed to resolve"
+    ];
+    network
+        .iter()
+        .any(|needle| lower.contains(needle))
+        .then_some(FailureCode::Network)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ytdlp_errors_are_classified() {
+        let code = |line| classify_ytdlp(line);
+        assert_eq!(
+            code("ERROR: [youtube] abc: Sign in to confirm your age. This video may be inappropriate for some users."),
+            Some(FailureCode::AgeRestricted)
+        );
+        assert_eq!(
+            code("ERROR: [youtube] abc: The uploader has not made this video available in your country"),
+            Some(FailureCode::GeoBlocked)
+        );
+        assert_eq!(code("ERROR: [youtube] abc: Private video"), Some(FailureCode::Unavailable));
+        assert_eq!(
+            code("ERROR: [youtube] abc: Sign in to confirm you're not a bot"),
+            Some(FailureCode::SignInRequired)
+        );
+        assert_eq!(
+            code("ERROR: unable to download video data: HTTP Error 429: Too Many Requests"),
+            Some(FailureCode::RateLimited)
+        );
+        assert_eq!(
+            code("ERROR: Unable to download webpage: ('Unable to connect to proxy', ProxyError)"),
+            Some(FailureCode::ProxyUnreachable)
+        );
+        assert_eq!(
+            code("ERROR: unable to download video data: HTTP Error 404: Not Found"),
+            Some(FailureCode::Network)
+        );
+        assert_eq!(code("[download] 12.5% of 3.40MiB"), None);
+    }
+
+    #[test]
+    fn ffmpeg_errors_are_classified() {
+        assert_eq!(
+            classify_ffmpeg("https://x/a.mp3: Server returned 404 Not Found"),
+            Some(FailureCode::Unavailable)
+        );
+        assert_eq!(
+            classify_ffmpeg("pipe:0: Invalid data found when processing input"),
+            Some(FailureCode::DecodeFailed)
+        );
+        assert_eq!(classify_ffmpeg("Connection timed out"), Some(FailureCode::Network));
+    }
+
+    #[test]
+    fn the_most_telling_cause_is_kept() {
+        let slot = FailureSlot::default();
+        slot.record(FailureCode::DecodeFailed);
+        slot.record(FailureCode::GeoBlocked);
+        slot.record(FailureCode::WatchdogTimeout);
+        assert_eq!(slot.get(), Some(FailureCode::GeoBlocked));
+    }
+}
@@ -0,0 +1,97 @@
+//! Smoothed gain stage shared by the master bus and every deck.
+//!
+//! A gain that jumps from one value to another between two samples is heard as
+//! a click, so every change is spread over a ramp, sample by sample, and may
+//! therefore span several chunks.
+
+use crate::config::{CHANNELS, SAMPLE_RATE};
+
+/// Highest level accepted from Node.js: a +6 dB boost for quiet uploads.
+pub const MAX_VOLUME: f32 = 2.0;
+
+/// Shortest ramp ever applied, even when Node.js asks for an instant change:
+/// one chunk is enough to keep a step change inaudible.
+const MIN_RAMP_MS: u64 = 10;
+
+pub struct GainRamp {
+    /// Gain applied to the next sample.
+    current: f32,
+    /// Gain the ramp is heading towards.
+    target: f32,
+    /// Change applied per sample until `current` reaches `target`.
+    step: f32
+}
+
+impl GainRamp {
+    pub fn new(level: f32) -> Self {
+        Self {
+            current: level,
+            target: level,
+            step: 0.0
+        }
+    }
+
+    /// Starts moving towards `level` over `ramp_ms` of audio.
+    pub fn set(&mut self, level: f32, ramp_ms: u64) {
+        let ramp_samples = (ramp_ms.max(MIN_RAMP_MS) as usize * SAMPLE_RATE / 1000) * CHANNELS;
+        self.target = level;
+        self.step = (level - self.current) / ramp_samples as f32;
+    }
+
+    /// The level the ramp settles on, which is what Node.js asked for.
+    pub fn level(&self) -> f32 {
+        self.target
+    }
+
+    /// Returns the gain for the next sample and advances the ramp by one.
+    pub fn next(&mut self) -> f32 {
+        let gain = self.current;
+        if self.current != self.target {
+            let next = self.current + self.step;
+            // Snap on the sample that would overshoot, so float drift can never
+            // leave the ramp hovering just short of its target.
+            let reached = (self.step > 0.0 && next >= self.target)
+                || (self.step < 0.0 && next <= self.target);
+            self.current = if reached { self.target } else { next };
+        }
+        gain
+    }
+}
+
+impl Default for GainRamp {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ramp_moves_gradually_and_lands_on_the_target() {
+        let mut ramp = GainRamp::default();
+        ramp.set(0.0, 10);
+
+        let total = SAMPLE_RATE * CHANNELS / 100;
+        let first = ramp.next();
+        let second = ramp.next();
+        assert_eq!(first, 1.0);
+        assert!(second < 1.0 && second > 0.99);
+
+        for _ in 2..=total {
+            ramp.next();
+        }
+        assert_eq!(ramp.next(), 0.0);
+        assert_eq!(ramp.level(), 0.0);
+    }
+
+    #[test]
+    fn instant_changes_are_still_ramped() {
+        let mut ramp = GainRamp::default();
+        ramp.set(2.0, 0);
+        ramp.next();
+        // A zero-length ramp would jump straight to 2.0 and click
+        assert!(ramp.next() < 1.01);
+    }
+}
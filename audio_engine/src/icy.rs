@@ -0,0 +1,379 @@
+//! Internet radio over plain HTTP, read in-process so that the "now playing"
+//! metadata Shoutcast/Icecast servers interleave with the audio (ICY) can be
+//! forwarded to Node.js as `stream_metadata` events.
+//!
+//! Only `http://` is spoken here, without TLS: a link that redirects to https
+//! or turns out not to be a radio stream is handed over to yt-dlp.
+
+use anyhow::{anyhow, Result};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::protocol::{emit, send_log, FailureCode, OutputEvent, TrackInfo};
+use crate::source::{DecoderInput, OpenedSource, Source, YtDlpSource};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A radio that sends nothing for this long has dropped the connection.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+const MAX_REDIRECTS: usize = 3;
+
+/// Response header lines read before giving up on a server.
+const MAX_HEADER_LINES: usize = 100;
+
+/// Status line and headers of a server's response.
+struct Head {
+    status: u16,
+    /// Header names are lowercased.
+    headers: Vec<(String, String)>
+}
+
+impl Head {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Shoutcast answers `ICY 200 OK`, Icecast a regular HTTP status line.
+    fn parse<R: BufRead>(reader: &mut R) -> io::Result<Self> {
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| invalid("no status line"))?;
+
+        let mut headers = Vec::new();
+        for _ in 0..MAX_HEADER_LINES {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            let trimmed = line.trim_end();
+            if trimmed.is_empty() {
+                return Ok(Self { status, headers });
+            }
+            if let Some((key, value)) = trimmed.split_once(':') {
+                headers.push((key.trim().to_ascii_lowercase(), value.trim().to_string()));
+            }
+        }
+        Err(invalid("unterminated headers"))
+    }
+
+    fn is_radio(&self) -> bool {
+        let content_type = self.header("content-type").unwrap_or("").to_ascii_lowercase();
+        self.header("icy-metaint").is_some()
+            || self.header("icy-name").is_some()
+            || content_type.starts_with("audio/")
+            || content_type.starts_with("application/ogg")
+    }
+}
+
+/// Splits `http://host[:port]/path` into what the request needs.
+fn split_url(url: &str) -> Option<(String, u16, String)> {
+    let rest = url
+        .get(..7)
+        .filter(|scheme| scheme.eq_ignore_ascii_case("http://"))
+        .map(|_| &url[7..])?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => (&rest[..slash], &rest[slash..]),
+        None => (rest, "/")
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (authority, 80)
+    };
+    (!host.is_empty()).then(|| (host.to_string(), port, path.to_string()))
+}
+
+/// What answered at the end of the redirects.
+enum Answer {
+    Radio(Head, BufReader<TcpStream>),
+    /// Not something to read here; the reason goes to the log.
+    Elsewhere(String)
+}
+
+fn connect(url: &str) -> Result<Answer> {
+    let mut url = url.to_string();
+    for _ in 0..=MAX_REDIRECTS {
+        let Some((host, port, path)) = split_url(&url) else {
+            return Ok(Answer::Elsewhere(format!("redirected to {}", url)));
+        };
+        let addr = (host.as_str(), port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("{} does not resolve", host))?;
+        let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        write!(
+            stream,
+            "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: Mozilla/5.0\r\nAccept: */*\r\nIcy-MetaData: 1\r\n\r\n",
+            path, host
+        )?;
+
+        let mut reader = BufReader::new(stream);
+        let head = Head::parse(&mut reader)?;
+        match head.status {
+            200 if head.is_radio() => return Ok(Answer::Radio(head, reader)),
+            200 => {
+                let content_type = head.header("content-type").unwrap_or("unknown");
+                return Ok(Answer::Elsewhere(format!("content type {}", content_type)));
+            }
+            301 | 302 | 303 | 307 | 308 => {
+                url = head
+                    .header("location")
+                    .ok_or_else(|| anyhow!("HTTP {} without a location", head.status))?
+                    .to_string();
+            }
+            status => return Err(anyhow!("HTTP {}", status))
+        }
+    }
+    Err(anyhow!("more than {} redirects", MAX_REDIRECTS))
+}
+
+/// A radio stream, read by the engine itself.
+pub struct IcySource {
+    url: String,
+    deck: &'static str,
+    /// Takes over when the link turns out to be a web page rather than a
+    /// stream.
+    fallback: Option<YtDlpSource>,
+    /// The last connection attempt failed.
+    unreachable: bool
+}
+
+impl IcySource {
+    pub fn new(url: &str, deck: &'static str) -> Self {
+        Self {
+            url: url.to_string(),
+            deck,
+            fallback: None,
+            unreachable: false
+        }
+    }
+}
+
+impl Source for IcySource {
+    fn kind(&self) -> &'static str {
+        match &self.fallback {
+            Some(fallback) => fallback.kind(),
+            None => "icy"
+        }
+    }
+
+    fn open(&mut self, cancel: &Arc<AtomicBool>) -> Result<OpenedSource> {
+        if let Some(fallback) = self.fallback.as_mut() {
+            return fallback.open(cancel);
+        }
+        let answer = connect(&self.url);
+        self.unreachable = answer.is_err();
+        let (head, reader) = match answer? {
+            Answer::Radio(head, reader) => (head, reader),
+            Answer::Elsewhere(reason) => {
+                send_log(
+                    "info",
+                    &format!("Not a radio stream ({}), handing it to yt-dlp", reason)
+                );
+                return self.fallback.insert(YtDlpSource::new(&self.url)).open(cancel);
+            }
+        };
+
+        let station = head.header("icy-name").map(str::to_string);
+        let metaint = head.header("icy-metaint").and_then(|raw| raw.parse().ok());
+        if station.is_some() {
+            emit(OutputEvent::StreamMetadata {
+                deck: self.deck,
+                station: station.clone(),
+                title: None
+            });
+        }
+        Ok(OpenedSource {
+            input: DecoderInput::Stream(Box::new(IcyReader {
+                inner: reader,
+                metaint,
+                until_meta: metaint.unwrap_or(0),
+                deck: self.deck,
+                station,
+                title: None,
+                cancel: cancel.clone()
+            })),
+            input_args: Vec::new(),
+            upstream: None
+        })
+    }
+
+    fn cacheable(&self) -> bool {
+        false
+    }
+
+    fn detected_live(&self) -> bool {
+        self.fallback
+            .as_ref()
+            .is_some_and(|fallback| fallback.detected_live())
+    }
+
+    fn duration_ms(&self) -> Option<u64> {
+        self.fallback.as_ref()?.duration_ms()
+    }
+
+    fn metadata(&self) -> Option<TrackInfo> {
+        self.fallback.as_ref()?.metadata()
+    }
+
+    fn resume_from(&mut self, position_ms: u64) -> bool {
+        match self.fallback.as_mut() {
+            Some(fallback) => fallback.resume_from(position_ms),
+            None => position_ms == 0
+        }
+    }
+
+    fn next_strategy(&mut self) -> Option<&'static str> {
+        self.fallback.as_mut()?.next_strategy()
+    }
+
+    fn failure(&self) -> Option<FailureCode> {
+        match &self.fallback {
+            Some(fallback) => fallback.failure(),
+            None => self.unreachable.then_some(FailureCode::Network)
+        }
+    }
+
+    fn failure_hints(&self) -> Vec<String> {
+        match &self.fallback {
+            Some(fallback) => fallback.failure_hints(),
+            None => vec!["Check: the radio server is online and reachable".to_string()]
+        }
+    }
+}
+
+/// The audio of an ICY stream, with the metadata blocks taken out.
+struct IcyReader<R> {
+    inner: R,
+    /// Audio bytes between two metadata blocks; None when the server sends
+    /// no metadata.
+    metaint: Option<usize>,
+    until_meta: usize,
+    deck: &'static str,
+    station: Option<String>,
+    title: Option<String>,
+    cancel: Arc<AtomicBool>
+}
+
+impl<R: Read> IcyReader<R> {
+    /// Reads one metadata block: a length byte (in 16-byte units), then
+    /// `StreamTitle='…';` padded with NULs. Most blocks are empty.
+    fn read_metadata(&mut self) -> io::Result<()> {
+        let mut len = [0u8];
+        self.inner.read_exact(&mut len)?;
+        let mut block = vec![0u8; len[0] as usize * 16];
+        self.inner.read_exact(&mut block)?;
+
+        let Some(title) = stream_title(&block) else {
+            return Ok(());
+        };
+        if self.title.as_deref() != Some(title.as_str()) {
+            self.title = Some(title.clone());
+            emit(OutputEvent::StreamMetadata {
+                deck: self.deck,
+                station: self.station.clone(),
+                title: Some(title)
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for IcyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancel.load(Ordering::Relaxed) {
+            return Err(io::Error::other("cancelled"));
+        }
+        let Some(metaint) = self.metaint else {
+            return self.inner.read(buf);
+        };
+        if self.until_meta == 0 {
+            self.read_metadata()?;
+            self.until_meta = metaint;
+        }
+        let len = buf.len().min(self.until_meta);
+        let read = self.inner.read(&mut buf[..len])?;
+        self.until_meta -= read;
+        Ok(read)
+    }
+}
+
+fn stream_title(block: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(block);
+    let start = text.find("StreamTitle='")? + "StreamTitle='".len();
+    // Titles may contain quotes: only a quote followed by ';' ends the field
+    let end = text[start..]
+        .find("';")
+        .or_else(|| text[start..].rfind('\''))?;
+    let title = text[start..start + end].trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn shoutcast_and_icecast_heads_are_read() {
+        let mut raw = Cursor::new(&b"ICY 200 OK\r\nicy-name: Radio Test\r\nicy-metaint: 8192\r\n\r\naudio"[..]);
+        let head = Head::parse(&mut raw).unwrap();
+        assert_eq!(head.status, 200);
+        assert_eq!(head.header("icy-name"), Some("Radio Test"));
+        assert!(head.is_radio());
+
+        let mut raw = Cursor::new(&b"HTTP/1.1 302 Found\r\nLocation: http://b/\r\n\r\n"[..]);
+        let head = Head::parse(&mut raw).unwrap();
+        assert_eq!(head.status, 302);
+        assert_eq!(head.header("location"), Some("http://b/"));
+    }
+
+    #[test]
+    fn urls_split_into_host_port_and_path() {
+        assert_eq!(
+            split_url("http://radio.example.com:8000/live?x=1"),
+            Some(("radio.example.com".to_string(), 8000, "/live?x=1".to_string()))
+        );
+        assert_eq!(split_url("HTTP://host"), Some(("host".to_string(), 80, "/".to_string())));
+        assert_eq!(split_url("https://host/"), None);
+    }
+
+    #[test]
+    fn metadata_blocks_are_taken_out_of_the_audio() {
+        let title = b"StreamTitle='It's Me - Song';StreamUrl='';";
+        let blocks = title.len().div_ceil(16);
+        let mut raw = b"abcd".to_vec();
+        raw.push(blocks as u8);
+        raw.extend(title);
+        raw.resize(5 + blocks * 16, 0);
+        raw.extend(b"efgh");
+        raw.push(0);
+        raw.extend(b"ij");
+
+        let mut reader = IcyReader {
+            inner: Cursor::new(raw),
+            metaint: Some(4),
+            until_meta: 4,
+            deck: "A",
+            station: None,
+            title: None,
+            cancel: Arc::new(AtomicBool::new(false))
+        };
+        let mut audio = Vec::new();
+        reader.read_to_end(&mut audio).unwrap();
+        assert_eq!(audio, b"abcdefghij");
+        assert_eq!(reader.title.as_deref(), Some("It's Me - Song"));
+    }
+}
@@ -0,0 +1,169 @@
+//! Brickwall limiter on the master bus.
+//!
+//! Equal-power crossfades and the gain stages can push the mix past full
+//! scale; clamping those samples is heard as distortion. The limiter instead
+//! looks a couple of milliseconds ahead, turns the gain down smoothly before a
+//! peak reaches the output, and lets it recover over the release time.
+
+use std::collections::VecDeque;
+
+use crate::config::{get_limiter_ceiling_db, get_limiter_release_ms, CHANNELS, SAMPLE_RATE};
+
+/// How far ahead the limiter sees (2 ms): the gain reduction is faded in over
+/// this span, which keeps it free of clicks.
+const LOOKAHEAD_FRAMES: usize = SAMPLE_RATE / 500;
+
+/// Extra frames held back so the inter-sample peak estimate, which needs the
+/// samples on both sides of a segment, is known before that segment goes out.
+const TRUE_PEAK_DELAY_FRAMES: usize = 2;
+
+/// Points checked between two consecutive samples when estimating true peaks.
+const INTER_SAMPLE_POINTS: [f32; 3] = [0.25, 0.5, 0.75];
+
+type Frame = [f32; CHANNELS];
+
+/// Catmull-Rom interpolation between `p1` and `p2` at `t`: close enough to the
+/// band-limited signal to catch the overshoots a DAC would produce.
+fn interpolate(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t * t * t)
+}
+
+pub struct Limiter {
+    ceiling: f32,
+    release_coef: f32,
+    /// Last four input frames, oldest first, for the true-peak estimate.
+    history: [Frame; 4],
+    /// Audio waiting to go out, so the gain can drop ahead of its peaks.
+    delay: VecDeque<Frame>,
+    /// Sliding minimum of the gain each frame requires: (frame index, gain).
+    hold: VecDeque<(u64, f32)>,
+    frame_index: u64,
+    /// Gain after release smoothing, before the look-ahead average.
+    envelope: f32,
+    /// Last `LOOKAHEAD_FRAMES` envelope values and their sum.
+    window: VecDeque<f32>,
+    window_sum: f64
+}
+
+impl Limiter {
+    pub fn new() -> Self {
+        let ceiling = 10f32.powf(get_limiter_ceiling_db() / 20.0);
+        let release_frames = get_limiter_release_ms() as f32 * SAMPLE_RATE as f32 / 1000.0;
+        Self {
+            ceiling,
+            release_coef: 1.0 - (-1.0 / release_frames).exp(),
+            history: [[0.0; CHANNELS]; 4],
+            delay: VecDeque::with_capacity(LOOKAHEAD_FRAMES + TRUE_PEAK_DELAY_FRAMES),
+            hold: VecDeque::new(),
+            frame_index: 0,
+            envelope: 1.0,
+            window: VecDeque::from(vec![1.0; LOOKAHEAD_FRAMES]),
+            window_sum: LOOKAHEAD_FRAMES as f64
+        }
+    }
+
+    /// Highest level in the newest frame, plus the estimated peaks of the
+    /// segment between the two frames before it.
+    fn peak(&self) -> f32 {
+        let [p0, p1, p2, p3] = self.history;
+        let mut peak = p3.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+        for ch in 0..CHANNELS {
+            for t in INTER_SAMPLE_POINTS {
+                peak = peak.max(interpolate(p0[ch], p1[ch], p2[ch], p3[ch], t).abs());
+            }
+        }
+        peak
+    }
+
+    /// Takes one stereo frame in and returns the limited frame that leaves the
+    /// look-ahead buffer. Both channels share one gain so the image never moves.
+    pub fn process(&mut self, frame: Frame) -> Frame {
+        self.history.rotate_left(1);
+        self.history[3] = frame;
+
+        let peak = self.peak();
+        let required = if peak > self.ceiling { self.ceiling / peak } else { 1.0 };
+
+        // The required gain is held for the whole look-ahead span (plus the
+        // true-peak delay), so the average below has fully settled on it by
+        // the time the peak leaves the buffer.
+        let hold_span = (LOOKAHEAD_FRAMES + TRUE_PEAK_DELAY_FRAMES) as u64;
+        while self.hold.back().is_some_and(|&(_, g)| g >= required) {
+            self.hold.pop_back();
+        }
+        self.hold.push_back((self.frame_index, required));
+        while self
+            .hold
+            .front()
+            .is_some_and(|&(i, _)| i + hold_span <= self.frame_index)
+        {
+            self.hold.pop_front();
+        }
+        self.frame_index += 1;
+        let held = self.hold.front().map_or(1.0, |&(_, g)| g);
+
+        self.envelope = if held < self.envelope {
+            held
+        } else {
+            self.envelope + (held - self.envelope) * self.release_coef
+        };
+
+        if let Some(oldest) = self.window.pop_front() {
+            self.window_sum -= oldest as f64;
+        }
+        self.window.push_back(self.envelope);
+        self.window_sum += self.envelope as f64;
+        let gain = (self.window_sum / LOOKAHEAD_FRAMES as f64) as f32;
+
+        self.delay.push_back(frame);
+        if self.delay.len() < LOOKAHEAD_FRAMES + TRUE_PEAK_DELAY_FRAMES {
+            return [0.0; CHANNELS];
+        }
+        let out = self.delay.pop_front().unwrap_or([0.0; CHANNELS]);
+        out.map(|s| s * gain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(limiter: &mut Limiter, input: &[Frame]) -> Vec<Frame> {
+        input.iter().map(|&f| limiter.process(f)).collect()
+    }
+
+    #[test]
+    fn quiet_audio_passes_through_delayed_and_untouched() {
+        let mut limiter = Limiter::new();
+        let input: Vec<Frame> = (0..500).map(|i| [0.1 * (i % 7) as f32 / 7.0; 2]).collect();
+        let output = run(&mut limiter, &input);
+
+        let delay = LOOKAHEAD_FRAMES + TRUE_PEAK_DELAY_FRAMES - 1;
+        for (i, frame) in output.iter().enumerate().skip(delay) {
+            assert!((frame[0] - input[i - delay][0]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn loud_audio_never_exceeds_the_ceiling() {
+        let mut limiter = Limiter::new();
+        let ceiling = limiter.ceiling;
+        // A 1.5x overdriven sine with a sudden single-sample spike
+        let mut input: Vec<Frame> = (0..SAMPLE_RATE / 10)
+            .map(|i| {
+                let s = 1.5 * (i as f32 * 0.05).sin();
+                [s, -s]
+            })
+            .collect();
+        input[2000] = [3.0, 3.0];
+
+        for frame in run(&mut limiter, &input) {
+            for s in frame {
+                assert!(s.abs() <= ceiling + 1e-4, "{} above {}", s, ceiling);
+            }
+        }
+    }
+}
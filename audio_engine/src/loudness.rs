@@ -0,0 +1,260 @@
+//! Loudness normalization: measures each track's integrated loudness as its
+//! download streams in (ITU-R BS.1770 / EBU R128) and derives the gain that
+//! brings it to the configured target.
+//!
+//! The measurement runs while the track is still downloading, so the gain
+//! starts from a running estimate and settles once the whole track is in.
+
+use std::collections::VecDeque;
+
+use crate::biquad::{Biquad, Coefficients};
+use crate::config::{get_loudness_target_lufs, CHANNELS, SAMPLE_RATE};
+use crate::gain::GainRamp;
+
+/// K-weighting stage 1 at 48 kHz: the head-related high shelf.
+const K_SHELF: Coefficients = Coefficients {
+    b0: 1.535_124_859_586_97,
+    b1: -2.691_696_189_406_38,
+    b2: 1.198_392_810_852_85,
+    a1: -1.690_659_293_182_41,
+    a2: 0.732_480_774_215_85
+};
+
+/// K-weighting stage 2 at 48 kHz: the RLB high-pass.
+const K_HIGH_PASS: Coefficients = Coefficients {
+    b0: 1.0,
+    b1: -2.0,
+    b2: 1.0,
+    a1: -1.990_047_454_833_98,
+    a2: 0.990_072_250_366_21
+};
+
+/// Gating blocks are 400 ms long and overlap by 75%, so a new one completes
+/// every 100 ms sub-block.
+const SUB_BLOCK_FRAMES: usize = SAMPLE_RATE / 10;
+const SUB_BLOCKS_PER_BLOCK: usize = 4;
+
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// New blocks (of 100 ms) between two running estimates: re-deriving the gain
+/// more often than once a second of audio only adds wobble.
+const ESTIMATE_EVERY_BLOCKS: usize = 10;
+
+/// A running estimate only moves the gain when it differs by more than this.
+const RETUNE_THRESHOLD_DB: f64 = 0.5;
+
+/// Quiet tracks are boosted at most this much: past it, noise floors and
+/// near-silent intros become audible.
+const MAX_BOOST_DB: f64 = 6.0;
+const MAX_CUT_DB: f64 = -24.0;
+
+/// Ramp used when the gain moves while the track is already audible.
+const RETUNE_RAMP_MS: u64 = 1000;
+
+fn power_to_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+fn lufs_to_power(lufs: f64) -> f64 {
+    10f64.powf((lufs + 0.691) / 10.0)
+}
+
+pub struct LoudnessMeter {
+    /// K-weighting chain of each channel.
+    filters: [[Biquad; 2]; CHANNELS],
+    /// Channel of the next interleaved sample.
+    channel: usize,
+    sub_block_energy: f64,
+    sub_block_frames: usize,
+    /// Mean-square power of the latest sub-blocks, oldest first.
+    recent: VecDeque<f64>,
+    /// Mean-square power of every completed 400 ms block.
+    blocks: Vec<f64>
+}
+
+impl LoudnessMeter {
+    pub fn new() -> Self {
+        Self {
+            filters: [[Biquad::new(K_SHELF), Biquad::new(K_HIGH_PASS)]; CHANNELS],
+            channel: 0,
+            sub_block_energy: 0.0,
+            sub_block_frames: 0,
+            recent: VecDeque::with_capacity(SUB_BLOCKS_PER_BLOCK),
+            blocks: Vec::new()
+        }
+    }
+
+    /// Feeds interleaved stereo samples.
+    pub fn process(&mut self, samples: &[f32]) {
+        for &sample in samples {
+            let [shelf, high_pass] = &mut self.filters[self.channel];
+            let weighted = high_pass.process(shelf.process(sample as f64));
+            self.sub_block_energy += weighted * weighted;
+
+            self.channel += 1;
+            if self.channel < CHANNELS {
+                continue;
+            }
+            self.channel = 0;
+            self.sub_block_frames += 1;
+            if self.sub_block_frames == SUB_BLOCK_FRAMES {
+                self.close_sub_block();
+            }
+        }
+    }
+
+    fn close_sub_block(&mut self) {
+        // Channel weights are all 1 for stereo: the block power is simply the
+        // per-frame energy summed over both channels.
+        let power = self.sub_block_energy / SUB_BLOCK_FRAMES as f64;
+        self.sub_block_energy = 0.0;
+        self.sub_block_frames = 0;
+
+        if self.recent.len() == SUB_BLOCKS_PER_BLOCK {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(power);
+        if self.recent.len() == SUB_BLOCKS_PER_BLOCK {
+            self.blocks
+                .push(self.recent.iter().sum::<f64>() / SUB_BLOCKS_PER_BLOCK as f64);
+        }
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Gated integrated loudness of everything fed so far, or None while no
+    /// block has risen above the absolute gate (silence, or too little audio).
+    pub fn integrated_lufs(&self) -> Option<f64> {
+        let absolute = lufs_to_power(ABSOLUTE_GATE_LUFS);
+        let gated_mean = |threshold: f64| {
+            let (sum, count) = self
+                .blocks
+                .iter()
+                .filter(|&&p| p > threshold)
+                .fold((0.0, 0usize), |(sum, count), p| (sum + p, count + 1));
+            (count > 0).then(|| sum / count as f64)
+        };
+
+        let ungated = gated_mean(absolute)?;
+        let relative = lufs_to_power(power_to_lufs(ungated) + RELATIVE_GATE_LU);
+        gated_mean(absolute.max(relative)).map(power_to_lufs)
+    }
+}
+
+/// Loudness measured for a track, reported once its download is over.
+pub struct LoudnessReport {
+    pub integrated_lufs: f64,
+    pub gain_db: f64
+}
+
+/// Per-deck normalization: meter plus the gain stage it drives.
+pub struct Normalizer {
+    target_lufs: Option<f64>,
+    meter: LoudnessMeter,
+    gain: GainRamp,
+    applied_db: f64,
+    blocks_at_last_estimate: usize,
+    settled: bool
+}
+
+impl Normalizer {
+    pub fn new() -> Self {
+        Self {
+            target_lufs: get_loudness_target_lufs().map(f64::from),
+            meter: LoudnessMeter::new(),
+            gain: GainRamp::default(),
+            applied_db: 0.0,
+            blocks_at_last_estimate: 0,
+            settled: false
+        }
+    }
+
+    pub fn feed(&mut self, samples: &[f32]) {
+        if self.target_lufs.is_some() && !self.settled {
+            self.meter.process(samples);
+        }
+    }
+
+    /// Re-derives the gain from the audio measured so far. `download_over`
+    /// settles it for good and returns what was measured, for reporting;
+    /// `audible` makes a change ramp slowly instead of applying at once.
+    pub fn update(&mut self, download_over: bool, audible: bool) -> Option<LoudnessReport> {
+        let target = self.target_lufs?;
+        if self.settled {
+            return None;
+        }
+        let new_blocks = self.meter.block_count() - self.blocks_at_last_estimate;
+        if !download_over && new_blocks < ESTIMATE_EVERY_BLOCKS {
+            return None;
+        }
+        self.blocks_at_last_estimate = self.meter.block_count();
+        self.settled = download_over;
+
+        let measured = self.meter.integrated_lufs()?;
+        let gain_db = (target - measured).clamp(MAX_CUT_DB, MAX_BOOST_DB);
+        if download_over || (gain_db - self.applied_db).abs() > RETUNE_THRESHOLD_DB {
+            self.applied_db = gain_db;
+            let ramp_ms = if audible { RETUNE_RAMP_MS } else { 0 };
+            self.gain.set(10f64.powf(gain_db / 20.0) as f32, ramp_ms);
+        }
+
+        download_over.then_some(LoudnessReport {
+            integrated_lufs: measured,
+            gain_db: self.applied_db
+        })
+    }
+
+    /// Gain for the next sample.
+    pub fn next_gain(&mut self) -> f32 {
+        self.gain.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1 kHz stereo sine of `amplitude`, `seconds` long.
+    fn sine(amplitude: f32, seconds: usize) -> Vec<f32> {
+        (0..SAMPLE_RATE * seconds)
+            .flat_map(|i| {
+                let phase = i as f32 * 1000.0 * std::f32::consts::TAU / SAMPLE_RATE as f32;
+                let s = amplitude * phase.sin();
+                [s, s]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn full_scale_sine_measures_close_to_reference() {
+        // BS.1770 reference: a 0 dBFS 1 kHz sine reads -3 LUFS on one channel,
+        // so 0 LUFS on both.
+        let mut meter = LoudnessMeter::new();
+        meter.process(&sine(1.0, 3));
+        let lufs = meter.integrated_lufs().unwrap();
+        assert!(lufs.abs() < 0.2, "measured {}", lufs);
+    }
+
+    #[test]
+    fn silence_has_no_loudness() {
+        let mut meter = LoudnessMeter::new();
+        meter.process(&vec![0.0; SAMPLE_RATE * CHANNELS * 2]);
+        assert_eq!(meter.integrated_lufs(), None);
+    }
+
+    #[test]
+    fn loud_track_is_turned_down_to_the_target() {
+        let mut normalizer = Normalizer {
+            target_lufs: Some(-14.0),
+            ..Normalizer::new()
+        };
+        normalizer.feed(&sine(0.5, 3));
+        let report = normalizer.update(true, false).unwrap();
+        assert!((report.integrated_lufs + 14.0 + report.gain_db).abs() < 0.01);
+        // Half scale sits 6 dB below full scale, which reads 0 LUFS
+        assert!((report.gain_db + 8.0).abs() < 0.2);
+    }
+}
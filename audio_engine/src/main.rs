@@ -1,23 +1,37 @@
 //! Entry point: wires stdin (JSON commands from Node.js) to the mixer thread.
 
+mod biquad;
+mod cache;
 mod commands;
 mod config;
 mod deck;
 mod download;
+mod eq;
 mod events;
+mod failure;
+mod gain;
+mod icy;
+mod limiter;
+mod loudness;
 mod mixer;
+#[cfg(feature = "native-decoder")]
+mod native_decoder;
+mod overlay;
 mod protocol;
+mod rate;
+mod replay;
+mod silence;
+mod source;
 mod state;
 mod transitions;
 
 use crossbeam_channel::bounded;
-#[cfg(unix)]
-use libc;
-use std::io;
+use std::io::{self, BufRead};
 use std::thread;
 
+use crate::config::get_protocol_version;
 use crate::mixer::mixer_loop;
-use crate::protocol::{send_log, InputCommand};
+use crate::protocol::{parse_request, send_log, set_protocol_version};
 
 fn main() {
     // Prevents Rust process from terminating on SIGPIPE when Node closes pipe
@@ -37,15 +51,37 @@ fn main() {
         std::process::exit(1);
     }));
 
-    let (tx, rx) = bounded::<InputCommand>(10);
+    // Events keep the legacy shape unless the client asked for another one
+    if let Some(version) = get_protocol_version() {
+        if !set_protocol_version(version) {
+            send_log("error", &format!("Unknown protocol version {} ignored", version));
+        }
+    }
+
+    let (tx, rx) = bounded(10);
 
     // Audio thread (Priority)
     thread::spawn(move || mixer_loop(rx));
 
-    // Input JSON thread (Node -> Rust)
-    let stdin = io::stdin();
-    let iterator = serde_json::Deserializer::from_reader(stdin).into_iter::<InputCommand>();
-    for cmd in iterator.flatten() {
-        let _ = tx.send(cmd);
+    // Input JSON thread (Node -> Rust): one command per line. A line that does
+    // not parse is passed on for the mixer to report, and reading resumes at
+    // the next line.
+    let mut stdin = io::stdin().lock();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match stdin.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                send_log("error", &format!("stdin read error: {}", e));
+                break;
+            }
+        }
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        let _ = tx.send(parse_request(&line));
     }
 }
@@ -9,10 +9,12 @@ use std::io::{self, Write};
 use std::thread;
 use std::time::{Duration, Instant};
 
-use crate::commands::{apply_command, CommandOutcome};
-use crate::config::CHUNK_SIZE;
-use crate::events::{emit_approaching_end, emit_playback_confirmed};
-use crate::protocol::{send_log, InputCommand};
+use crate::commands::{handle_request, CommandOutcome};
+use crate::config::{get_position_interval_ms, CHANNELS, CHUNK_SIZE};
+use crate::events::{emit_approaching_end, emit_playback_confirmed, emit_position};
+use crate::protocol::{
+    emit, hello_event, send_log, DeckChangeTrigger, MalformedRequest, OutputEvent, Request
+};
 use crate::state::MixerState;
 use crate::transitions::{
     detect_failed_decks, emit_buffer_ready_edges, handle_track_end, poll_crossfade_stall,
@@ -59,12 +61,13 @@ fn mix_crossfade_sample(state: &mut MixerState) -> f32 {
     };
 
     if !state.deck(target).has_samples() {
-        return state.active_mut().get_next_sample().unwrap_or(0.0);
+        return state.active_mut().next_output_sample().unwrap_or(0.0);
     }
 
     // Both decks hold audio: consume from both so neither drifts out of sync.
-    let sample_a = state.deck_a.get_next_sample().unwrap_or(0.0);
-    let sample_b = state.deck_b.get_next_sample().unwrap_or(0.0);
+    let source = state.active_deck;
+    let source_sample = state.deck_mut(source).next_output_sample().unwrap_or(0.0);
+    let target_sample = state.deck_mut(target).next_output_sample().unwrap_or(0.0);
 
     let (ratio, completed) = {
         let crossfade = state
@@ -76,23 +79,18 @@ fn mix_crossfade_sample(state: &mut MixerState) -> f32 {
         (ratio, crossfade.left == 0)
     };
 
-    let source_is_a = state.active_deck == "A";
-    let target_is_a = target == "A";
-
     if completed {
         let previous = state.active_deck;
         state.deck_mut(previous).reset_flags();
         state.crossfade = None;
         state.switch_to(target);
         send_log("info", &format!("Crossfade completed, switched to {}", target));
-        send_log(
-            "deck_changed",
-            &format!("deck={}, triggered_by=crossfade_completion", target)
-        );
+        emit(OutputEvent::DeckChanged {
+            deck: target,
+            trigger: DeckChangeTrigger::CrossfadeCompletion
+        });
     }
 
-    let source_sample = if source_is_a { sample_a } else { sample_b };
-    let target_sample = if target_is_a { sample_a } else { sample_b };
     let ratio = if completed { 1.0 } else { ratio };
 
     // Equal-power crossfade: keeps perceived volume constant
@@ -104,7 +102,7 @@ fn mix_crossfade_sample(state: &mut MixerState) -> f32 {
 /// other deck part-way through the chunk when this one runs dry. Handling the
 /// hand-over here rather than after the chunk is what makes it gapless.
 fn mix_direct_sample(state: &mut MixerState, event: &mut ChunkEvent) -> f32 {
-    if let Some(sample) = state.active_mut().get_next_sample() {
+    if let Some(sample) = state.active_mut().next_output_sample() {
         return sample;
     }
 
@@ -126,12 +124,18 @@ fn mix_direct_sample(state: &mut MixerState, event: &mut ChunkEvent) -> f32 {
     }
 
     if state.loop_mode {
+        // Nothing to loop from: `handle_track_end` reports the end instead
+        if !state.active().can_replay() {
+            return 0.0;
+        }
         state.active_mut().restart();
         *event = ChunkEvent::LoopRestart;
-        return state.active_mut().get_next_sample().unwrap_or(0.0);
+        return state.active_mut().next_output_sample().unwrap_or(0.0);
     }
 
-    let other = state.idle_deck();
+    let Some(other) = state.next_deck() else {
+        return 0.0;
+    };
     if !state.deck(other).has_samples() {
         return 0.0;
     }
@@ -140,36 +144,48 @@ fn mix_direct_sample(state: &mut MixerState, event: &mut ChunkEvent) -> f32 {
     state.reset_deck(previous);
     state.switch_to(other);
     *event = ChunkEvent::AutoSwitch(other);
-    state.active_mut().get_next_sample().unwrap_or(0.0)
+    state.active_mut().next_output_sample().unwrap_or(0.0)
 }
 
-/// Fills `out` with one chunk of little-endian 16-bit PCM.
-/// Returns whether any audible sample was produced, plus any deck change that
-/// happened part-way through.
-fn mix_chunk(state: &mut MixerState, out: &mut Vec<u8>) -> (bool, ChunkEvent) {
+/// Fills `out` with one chunk of little-endian 16-bit PCM, reading the decks
+/// only when `decks` is set. Returns whether the decks produced any audible
+/// sample (overlays do not count: a jingle must not hide the end of a track),
+/// plus any deck change that happened part-way through.
+fn mix_chunk(state: &mut MixerState, out: &mut Vec<u8>, decks: bool) -> (bool, ChunkEvent) {
     let mut has_audio = false;
     let mut event = ChunkEvent::None;
     out.clear();
 
-    for _ in 0..CHUNK_SIZE {
-        let sample = if state.crossfade.is_some() {
-            mix_crossfade_sample(state)
-        } else {
-            mix_direct_sample(state, &mut event)
-        };
+    for _ in 0..CHUNK_SIZE / CHANNELS {
+        let mut frame = [0.0; CHANNELS];
+        for sample in frame.iter_mut() {
+            let music = if !decks {
+                0.0
+            } else if state.crossfade.is_some() {
+                mix_crossfade_sample(state)
+            } else {
+                mix_direct_sample(state, &mut event)
+            };
+            if music.abs() > 0.0001 {
+                has_audio = true;
+            }
 
-        if sample.abs() > 0.0001 {
-            has_audio = true;
+            let mixed = state.overlay.mix(music);
+            *sample = state.master_eq.process(mixed) * state.master_volume.next();
         }
 
-        let pcm = (sample.clamp(-1.0, 1.0) * 32767.0) as i16;
-        out.extend_from_slice(&pcm.to_le_bytes());
+        // The clamp only guards the i16 conversion: the limiter already keeps
+        // the output under its ceiling.
+        for sample in state.limiter.process(frame) {
+            let pcm = (sample.clamp(-1.0, 1.0) * 32767.0) as i16;
+            out.extend_from_slice(&pcm.to_le_bytes());
+        }
     }
 
     (has_audio, event)
 }
 
-pub fn mixer_loop(cmd_rx: Receiver<InputCommand>) {
+pub fn mixer_loop(cmd_rx: Receiver<Result<Request, MalformedRequest>>) {
     let mut state = MixerState::new();
     let mut buffer_monitor_counter: u32 = 0;
 
@@ -180,19 +196,24 @@ pub fn mixer_loop(cmd_rx: Receiver<InputCommand>) {
     let mut out_bytes: Vec<u8> = Vec::with_capacity(CHUNK_SIZE * 2);
 
     send_log("info", "Rust Mixer Ready");
+    emit(hello_event());
     let mut last_status_log = Instant::now();
+    let position_interval = Duration::from_millis(get_position_interval_ms());
+    let mut last_position_event = Instant::now();
 
     'main: loop {
         // Node -> Rust command handling
-        while let Ok(cmd) = cmd_rx.try_recv() {
-            if matches!(apply_command(&mut state, cmd), CommandOutcome::Shutdown) {
+        while let Ok(incoming) = cmd_rx.try_recv() {
+            if matches!(handle_request(&mut state, incoming), CommandOutcome::Shutdown) {
                 break 'main;
             }
         }
 
         // Updates buffers of ALL decks, even inactive ones
-        state.deck_a.poll_receiver();
-        state.deck_b.poll_receiver();
+        for deck in state.decks_mut() {
+            deck.poll_receiver();
+        }
+        state.overlay.poll();
 
         detect_failed_decks(&mut state);
         poll_stall(&mut state);
@@ -211,10 +232,10 @@ pub fn mixer_loop(cmd_rx: Receiver<InputCommand>) {
             continue;
         }
 
-        // Waiting on a download: emit silence without consuming any samples
+        // Waiting on a download: no deck sample is consumed, but overlay clips
+        // (an ident between two songs) still play over the silence.
         if state.stall.is_some() {
-            out_bytes.clear();
-            out_bytes.resize(CHUNK_SIZE * 2, 0u8);
+            mix_chunk(&mut state, &mut out_bytes, false);
             if let Err(e) = write_pcm_chunk(&mut handle, &out_bytes) {
                 send_log("error", &format!("Fatal stdout write error: {}", e));
                 break 'main;
@@ -222,7 +243,7 @@ pub fn mixer_loop(cmd_rx: Receiver<InputCommand>) {
             continue;
         }
 
-        let (has_audio, chunk_event) = mix_chunk(&mut state, &mut out_bytes);
+        let (has_audio, chunk_event) = mix_chunk(&mut state, &mut out_bytes, true);
         if let Err(e) = write_pcm_chunk(&mut handle, &out_bytes) {
             send_log("error", &format!("Fatal stdout write error: {}", e));
             break 'main;
@@ -233,7 +254,9 @@ pub fn mixer_loop(cmd_rx: Receiver<InputCommand>) {
 
         match chunk_event {
             ChunkEvent::LoopRestart => {
-                send_log("auto_loop_restart", state.active_deck);
+                emit(OutputEvent::AutoLoopRestart {
+                    deck: state.active_deck
+                });
                 send_log(
                     "info",
                     &format!(
@@ -243,11 +266,11 @@ pub fn mixer_loop(cmd_rx: Receiver<InputCommand>) {
                 );
             }
             ChunkEvent::AutoSwitch(deck) => {
-                send_log("auto_end_switch", deck);
-                send_log(
-                    "deck_changed",
-                    &format!("deck={}, triggered_by=mid_chunk_auto_gapless", deck)
-                );
+                emit(OutputEvent::AutoEndSwitch { deck });
+                emit(OutputEvent::DeckChanged {
+                    deck,
+                    trigger: DeckChangeTrigger::MidChunkAutoGapless
+                });
                 send_log(
                     "info",
                     &format!("⚡ Mid-chunk auto-gapless: instant switch → deck {}", deck)
@@ -262,14 +285,23 @@ pub fn mixer_loop(cmd_rx: Receiver<InputCommand>) {
             }
         }
 
+        if !position_interval.is_zero() && last_position_event.elapsed() >= position_interval {
+            emit_position(&state);
+            last_position_event = Instant::now();
+        }
+
         if last_status_log.elapsed().as_secs() >= STATUS_LOG_INTERVAL_SECS {
             send_log(
                 "debug",
                 &format!(
-                    "Status - Active: {}, A: {}s played, B: {}s played, pending: {}",
+                    "Status - Active: {}, {}, pending: {}",
                     state.active_deck,
-                    state.deck_a.played_seconds(),
-                    state.deck_b.played_seconds(),
+                    state
+                        .deck_names()
+                        .into_iter()
+                        .map(|name| format!("{}: {}s played", name, state.deck(name).played_seconds()))
+                        .collect::<Vec<_>>()
+                        .join(", "),
                     state.pending.is_some()
                 )
             );
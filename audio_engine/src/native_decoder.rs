@@ -0,0 +1,503 @@
+//! In-process decoding with Symphonia, in place of the ffmpeg subprocess
+//! (`native-decoder` feature).
+//!
+//! Containers: WebM/Matroska, MP4/M4A, Ogg, WAV; codecs: AAC, MP3, FLAC,
+//! Vorbis, PCM. Symphonia has no Opus decoder, so with this feature on yt-dlp
+//! asks YouTube for its M4A/AAC audio rather than the default Opus/WebM
+//! (`source::format_selector`). Input it still cannot read is handed to
+//! ffmpeg instead, a local file by its path and a stream from its first byte
+//! again, replaying what the probe consumed. Everything is converted to
+//! 48 kHz stereo, resampling with rubato when the rate is not 48 kHz; a
+//! stream that changes rate midway gets a new resampler.
+
+use anyhow::{anyhow, Result};
+use rubato::{FftFixedIn, Resampler};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::sync::{Arc, Mutex};
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::{MediaSource, MediaSourceStream, ReadOnlySource};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::config::{use_native_decoder, CHANNELS, SAMPLE_RATE};
+use crate::download::PcmStream;
+use crate::protocol::{emit, send_log, DecodeErrorKind, OutputEvent};
+use crate::source::DecoderInput;
+
+/// Input frames per resampler pass: about 20 ms at common rates.
+const RESAMPLE_CHUNK_FRAMES: usize = 1024;
+
+/// A decoding failure, reported to Node.js as a `decoder_error` event.
+#[derive(Debug)]
+pub struct DecodeError {
+    pub kind: DecodeErrorKind,
+    pub detail: String
+}
+
+impl DecodeError {
+    fn new(kind: DecodeErrorKind, detail: impl Into<String>) -> Self {
+        Self {
+            kind,
+            detail: detail.into()
+        }
+    }
+
+    pub fn report(&self, deck: &'static str) {
+        emit(OutputEvent::DecoderError {
+            deck,
+            kind: self.kind,
+            detail: self.detail.clone()
+        });
+    }
+
+    /// Formats Symphonia cannot read at all, as opposed to a broken stream.
+    fn is_unsupported(&self) -> bool {
+        matches!(
+            self.kind,
+            DecodeErrorKind::UnrecognizedFormat | DecodeErrorKind::UnsupportedCodec
+        )
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.detail)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<DecodeError> for io::Error {
+    fn from(err: DecodeError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// What `open` made of a source's input.
+pub enum NativeOpen {
+    Decoder(Box<NativeDecoder>),
+    /// Left to ffmpeg: in-process decoding is off, the input is a URL, or it
+    /// is in a format Symphonia cannot read.
+    Fallback(DecoderInput)
+}
+
+struct Recorded<R> {
+    inner: R,
+    /// Bytes read so far, kept while `recording`.
+    bytes: Vec<u8>,
+    recording: bool
+}
+
+/// A stream the probe reads through while a copy of what it read is kept, so
+/// that a stream Symphonia turns down can be handed to ffmpeg whole. Once a
+/// decoder is set up the copy is dropped and reads go straight through.
+struct Rewindable<R> {
+    shared: Arc<Mutex<Recorded<R>>>
+}
+
+impl<R: Read> Read for Rewindable<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut recorded = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        let read = recorded.inner.read(buf)?;
+        if recorded.recording {
+            recorded.bytes.extend_from_slice(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+/// A pipe or a network stream: read front to back, format found by probing.
+fn open_unseekable<R>(reader: R, deck: &'static str) -> Result<NativeOpen>
+where
+    R: Read + Send + Sync + 'static
+{
+    let shared = Arc::new(Mutex::new(Recorded {
+        inner: reader,
+        bytes: Vec::new(),
+        recording: true
+    }));
+    let source = ReadOnlySource::new(Rewindable {
+        shared: shared.clone()
+    });
+    let result = NativeDecoder::new(Box::new(source), Hint::new());
+    let mut recorded = shared.lock().unwrap_or_else(|e| e.into_inner());
+    recorded.recording = false;
+    let probed = std::mem::take(&mut recorded.bytes);
+    drop(recorded);
+
+    match result {
+        Ok(decoder) => Ok(NativeOpen::Decoder(Box::new(decoder))),
+        Err(err) if err.is_unsupported() => {
+            send_log(
+                "info",
+                &format!("Native decoder: {} → falling back to ffmpeg", err)
+            );
+            // The failed probe dropped its reader: this is the last handle
+            let rest = Rewindable { shared };
+            Ok(NativeOpen::Fallback(DecoderInput::Stream(Box::new(
+                Cursor::new(probed).chain(rest)
+            ))))
+        }
+        Err(err) => {
+            err.report(deck);
+            Err(anyhow!("Native decoder: {}", err))
+        }
+    }
+}
+
+/// Opens `input` for in-process decoding when possible.
+pub fn open(input: DecoderInput, deck: &'static str) -> Result<NativeOpen> {
+    if !use_native_decoder() {
+        return Ok(NativeOpen::Fallback(input));
+    }
+    match input {
+        DecoderInput::Pipe(stdout) => open_unseekable(stdout, deck),
+        DecoderInput::Stream(reader) => open_unseekable(reader, deck),
+        DecoderInput::Location(location) => {
+            let Ok(file) = File::open(&location) else {
+                // Not a local path: a URL, which only ffmpeg can fetch
+                return Ok(NativeOpen::Fallback(DecoderInput::Location(location)));
+            };
+            let mut hint = Hint::new();
+            if let Some((_, ext)) = location.rsplit_once('.') {
+                hint.with_extension(ext);
+            }
+            match NativeDecoder::new(Box::new(file), hint) {
+                Ok(decoder) => Ok(NativeOpen::Decoder(Box::new(decoder))),
+                Err(err) if err.is_unsupported() => {
+                    send_log(
+                        "info",
+                        &format!("Native decoder: {} → falling back to ffmpeg", err)
+                    );
+                    Ok(NativeOpen::Fallback(DecoderInput::Location(location)))
+                }
+                Err(err) => {
+                    err.report(deck);
+                    Err(anyhow!("Native decoder: {}", err))
+                }
+            }
+        }
+    }
+}
+
+/// Resamples stereo audio to the engine rate.
+struct Resample {
+    /// Rate of the input.
+    rate: usize,
+    resampler: FftFixedIn<f32>,
+    /// Input frames waiting for a full resampler pass, per channel.
+    pending: [Vec<f32>; CHANNELS],
+    /// Leading output frames that are only the resampler's delay.
+    skip: usize
+}
+
+impl Resample {
+    fn new(rate: usize) -> Result<Self, DecodeError> {
+        let resampler = FftFixedIn::new(rate, SAMPLE_RATE, RESAMPLE_CHUNK_FRAMES, 2, CHANNELS)
+            .map_err(|e| DecodeError::new(DecodeErrorKind::UnsupportedCodec, e.to_string()))?;
+        let skip = resampler.output_delay();
+        Ok(Self {
+            rate,
+            resampler,
+            pending: [Vec::new(), Vec::new()],
+            skip
+        })
+    }
+
+    /// Makes `resample` fit input at `rate`: none at the engine rate, and a
+    /// new one when the rate changed, after what the old one held went out.
+    fn follow(resample: &mut Option<Self>, rate: usize, out: &mut VecDeque<f32>) -> Result<(), DecodeError> {
+        if resample.as_ref().map_or(SAMPLE_RATE, |resample| resample.rate) == rate {
+            return Ok(());
+        }
+        if let Some(mut old) = resample.take() {
+            old.flush(out);
+        }
+        if rate != SAMPLE_RATE {
+            *resample = Some(Self::new(rate)?);
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, frame: [f32; CHANNELS], out: &mut VecDeque<f32>) -> Result<(), DecodeError> {
+        for (channel, sample) in self.pending.iter_mut().zip(frame) {
+            channel.push(sample);
+        }
+        if self.pending[0].len() < self.resampler.input_frames_next() {
+            return Ok(());
+        }
+        let resampled = self
+            .resampler
+            .process(&self.pending, None)
+            .map_err(|e| DecodeError::new(DecodeErrorKind::CorruptStream, e.to_string()))?;
+        for channel in &mut self.pending {
+            channel.clear();
+        }
+        self.emit(resampled, out);
+        Ok(())
+    }
+
+    /// Pushes out what is still held at the end of the stream.
+    fn flush(&mut self, out: &mut VecDeque<f32>) {
+        if let Ok(resampled) = self.resampler.process_partial(Some(&self.pending), None) {
+            self.emit(resampled, out);
+        }
+        if let Ok(resampled) = self.resampler.process_partial::<Vec<f32>>(None, None) {
+            self.emit(resampled, out);
+        }
+        for channel in &mut self.pending {
+            channel.clear();
+        }
+    }
+
+    fn emit(&mut self, resampled: Vec<Vec<f32>>, out: &mut VecDeque<f32>) {
+        let frames = resampled[0].len();
+        let skipped = self.skip.min(frames);
+        self.skip -= skipped;
+        for i in skipped..frames {
+            for channel in &resampled {
+                out.push_back(channel[i]);
+            }
+        }
+    }
+}
+
+pub struct NativeDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    /// Set up on the first decoded packet, once the real rate is known, and
+    /// again whenever it changes.
+    resample: Option<Resample>,
+    /// Decoded 48 kHz stereo samples not handed out yet.
+    ready: VecDeque<f32>,
+    finished: bool,
+    /// Length the container announces, if it does.
+    duration_ms: Option<u64>
+}
+
+impl NativeDecoder {
+    fn new(source: Box<dyn MediaSource>, hint: Hint) -> Result<Self, DecodeError> {
+        let stream = MediaSourceStream::new(source, Default::default());
+        let probed = symphonia::default::get_probe()
+            .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+            .map_err(|e| DecodeError::new(DecodeErrorKind::UnrecognizedFormat, e.to_string()))?;
+        let format = probed.format;
+
+        let track = format
+            .tracks()
+            .iter()
+            .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| DecodeError::new(DecodeErrorKind::UnrecognizedFormat, "no audio track"))?;
+        let track_id = track.id;
+        let duration_ms = track
+            .codec_params
+            .n_frames
+            .zip(track.codec_params.sample_rate)
+            .filter(|&(_, rate)| rate > 0)
+            .map(|(frames, rate)| frames * 1000 / rate as u64);
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .map_err(|e| DecodeError::new(DecodeErrorKind::UnsupportedCodec, e.to_string()))?;
+
+        Ok(Self {
+            format,
+            decoder,
+            track_id,
+            resample: None,
+            ready: VecDeque::new(),
+            finished: false,
+            duration_ms
+        })
+    }
+
+    /// Decodes packets until some audio is ready or the stream is over.
+    fn refill(&mut self) -> Result<(), DecodeError> {
+        while self.ready.is_empty() && !self.finished {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    self.finish();
+                    return Ok(());
+                }
+                Err(SymphoniaError::ResetRequired) => {
+                    self.decoder.reset();
+                    continue;
+                }
+                Err(SymphoniaError::IoError(e)) => {
+                    return Err(DecodeError::new(DecodeErrorKind::Io, e.to_string()));
+                }
+                Err(e) => return Err(DecodeError::new(DecodeErrorKind::CorruptStream, e.to_string()))
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A damaged packet is dropped, like ffmpeg's +discardcorrupt
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(e) => return Err(DecodeError::new(DecodeErrorKind::CorruptStream, e.to_string()))
+            };
+            let spec = *decoded.spec();
+            let channels = spec.channels.count();
+            if channels == 0 {
+                continue;
+            }
+            let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+            buffer.copy_interleaved_ref(decoded);
+
+            Resample::follow(&mut self.resample, spec.rate as usize, &mut self.ready)?;
+            for frame in buffer.samples().chunks_exact(channels) {
+                // Mono is duplicated; beyond stereo only front left/right are kept
+                let stereo = [frame[0], frame[channels.min(2) - 1]];
+                match self.resample.as_mut() {
+                    Some(resample) => resample.push(stereo, &mut self.ready)?,
+                    None => self.ready.extend(stereo)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) {
+        self.finished = true;
+        if let Some(resample) = self.resample.as_mut() {
+            resample.flush(&mut self.ready);
+        }
+    }
+}
+
+impl PcmStream for NativeDecoder {
+    fn next_sample(&mut self) -> io::Result<f32> {
+        if self.ready.is_empty() {
+            self.refill()?;
+        }
+        self.ready
+            .pop_front()
+            .ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+    }
+
+    fn duration_ms(&self) -> Option<u64> {
+        self.duration_ms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A 16-bit PCM WAV file of a sine, `channels` wide.
+    fn wav(rate: u32, channels: u16, frames: usize) -> Vec<u8> {
+        let data_len = (frames * channels as usize * 2) as u32;
+        let mut out = Vec::new();
+        out.extend(b"RIFF");
+        out.extend((36 + data_len).to_le_bytes());
+        out.extend(b"WAVEfmt ");
+        out.extend(16u32.to_le_bytes());
+        out.extend(1u16.to_le_bytes());
+        out.extend(channels.to_le_bytes());
+        out.extend(rate.to_le_bytes());
+        out.extend((rate * channels as u32 * 2).to_le_bytes());
+        out.extend((channels * 2).to_le_bytes());
+        out.extend(16u16.to_le_bytes());
+        out.extend(b"data");
+        out.extend(data_len.to_le_bytes());
+        for i in 0..frames {
+            let phase = i as f32 * 440.0 * std::f32::consts::TAU / rate as f32;
+            let sample = (phase.sin() * 16000.0) as i16;
+            for _ in 0..channels {
+                out.extend(sample.to_le_bytes());
+            }
+        }
+        out
+    }
+
+    fn decode_all(bytes: Vec<u8>) -> Vec<f32> {
+        let mut decoder = NativeDecoder::new(Box::new(Cursor::new(bytes)), Hint::new()).unwrap();
+        let mut samples = Vec::new();
+        loop {
+            match decoder.next_sample() {
+                Ok(sample) => samples.push(sample),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return samples,
+                Err(e) => panic!("decode failed: {}", e)
+            }
+        }
+    }
+
+    #[test]
+    fn mono_48k_is_duplicated_to_stereo() {
+        let samples = decode_all(wav(48000, 1, 4800));
+        assert_eq!(samples.len(), 4800 * CHANNELS);
+        assert!(samples.chunks(2).all(|frame| frame[0] == frame[1]));
+    }
+
+    #[test]
+    fn other_rates_are_resampled_to_48k() {
+        let samples = decode_all(wav(44100, 2, 44100));
+        let frames = samples.len() / CHANNELS;
+        // One second in, one second out, give or take the resampler's padding
+        assert!((frames as i64 - 48000).abs() < 2400, "{} frames", frames);
+        let peak = samples.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+        assert!((peak - 0.49).abs() < 0.05, "peak {}", peak);
+    }
+
+    #[test]
+    fn a_rate_change_midway_gets_a_new_resampler() {
+        let mut resample = None;
+        let mut out = VecDeque::new();
+        for (rate, frames) in [(44100, 44100), (32000, 32000), (48000, 4800)] {
+            Resample::follow(&mut resample, rate, &mut out).unwrap();
+            for _ in 0..frames {
+                match resample.as_mut() {
+                    Some(resample) => resample.push([0.25, 0.25], &mut out).unwrap(),
+                    None => out.extend([0.25, 0.25])
+                }
+            }
+        }
+        assert!(resample.is_none());
+        // Two seconds and a tenth in, as many out, give or take the padding
+        let frames = out.len() / CHANNELS;
+        assert!((frames as i64 - 100_800).abs() < 4800, "{} frames", frames);
+    }
+
+    #[test]
+    fn the_announced_length_is_reported() {
+        let decoder = NativeDecoder::new(Box::new(Cursor::new(wav(44100, 2, 66150))), Hint::new()).unwrap();
+        assert_eq!(decoder.duration_ms(), Some(1500));
+    }
+
+    #[test]
+    fn a_stream_symphonia_turns_down_goes_to_ffmpeg_whole() {
+        let garbage: Vec<u8> = (0..20_000).map(|i| (i * 7 % 251) as u8).collect();
+        let opened = open_unseekable(Cursor::new(garbage.clone()), "A").unwrap();
+        let NativeOpen::Fallback(DecoderInput::Stream(mut stream)) = opened else {
+            panic!("expected an ffmpeg fallback");
+        };
+        let mut replayed = Vec::new();
+        stream.read_to_end(&mut replayed).unwrap();
+        assert_eq!(replayed, garbage);
+    }
+
+    #[test]
+    fn a_stream_symphonia_reads_is_decoded_in_process() {
+        let opened = open_unseekable(Cursor::new(wav(48000, 2, 4800)), "A").unwrap();
+        assert!(matches!(opened, NativeOpen::Decoder(_)));
+    }
+
+    #[test]
+    fn garbage_is_an_unrecognized_format() {
+        let err = NativeDecoder::new(Box::new(Cursor::new(vec![7u8; 4096])), Hint::new())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, DecodeErrorKind::UnrecognizedFormat);
+    }
+}
@@ -0,0 +1,185 @@
+//! Overlay bus: short clips (station idents, soundboard reactions) mixed on
+//! top of the music, optionally ducking it while they play.
+//!
+//! Each clip runs on a private deck of its own, so it is downloaded and
+//! decoded exactly like a track, but it never takes part in transitions: it is
+//! simply summed into the output until it runs out.
+
+use crate::deck::Deck;
+use crate::gain::GainRamp;
+use crate::protocol::{emit, OutputEvent, OverlayEndReason};
+
+/// Name the clips' decks report in their own events (stream_opened, …).
+pub const OVERLAY_DECK: &str = "overlay";
+
+/// Clips that may sound at once: each one is a download in flight.
+pub const MAX_CLIPS: usize = 4;
+
+/// The music dips quickly when a clip starts, so its first word is heard, and
+/// comes back slowly so the return does not sound like a jump.
+const DUCK_ATTACK_MS: u64 = 150;
+const DUCK_RELEASE_MS: u64 = 600;
+
+struct Clip {
+    id: u32,
+    deck: Deck,
+    volume: f32,
+    /// Level the music is brought down to while this clip is audible.
+    duck: Option<f32>
+}
+
+pub struct OverlayBus {
+    clips: Vec<Clip>,
+    next_id: u32,
+    /// Gain applied to the music underneath the clips.
+    music_gain: GainRamp
+}
+
+impl OverlayBus {
+    pub fn new() -> Self {
+        Self {
+            clips: Vec::new(),
+            next_id: 1,
+            music_gain: GainRamp::default()
+        }
+    }
+
+    /// Starts downloading a clip, which plays as soon as audio arrives.
+    /// Returns its id, or None when the bus is full.
+    pub fn play(&mut self, url: String, volume: f32, duck: Option<f32>) -> Option<u32> {
+        if self.clips.len() >= MAX_CLIPS {
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut deck = Deck::new(OVERLAY_DECK);
+        deck.load(url, false);
+        self.clips.push(Clip {
+            id,
+            deck,
+            volume,
+            duck
+        });
+        Some(id)
+    }
+
+    /// Stops one clip, or every clip when `id` is None. Returns what was
+    /// stopped; dropping a clip's deck cancels its download.
+    pub fn stop(&mut self, id: Option<u32>) -> Vec<u32> {
+        let mut stopped = Vec::new();
+        self.clips.retain(|clip| {
+            let keep = id.is_some_and(|id| id != clip.id);
+            if !keep {
+                stopped.push(clip.id);
+            }
+            keep
+        });
+        for &clip in &stopped {
+            emit(OutputEvent::OverlayEnded {
+                clip,
+                reason: OverlayEndReason::Stopped
+            });
+        }
+        stopped
+    }
+
+    pub fn clip_ids(&self) -> Vec<u32> {
+        self.clips.iter().map(|clip| clip.id).collect()
+    }
+
+    /// Moves downloaded audio into the clips, retires the ones that are over
+    /// and retargets the ducking. Runs once per loop, like `poll_receiver`.
+    pub fn poll(&mut self) {
+        for clip in &mut self.clips {
+            clip.deck.poll_receiver();
+        }
+
+        self.clips.retain(|clip| {
+            let reason = if clip.deck.download_failed {
+                OverlayEndReason::Failed
+            } else if clip.deck.receiver.is_none() && !clip.deck.has_samples() {
+                OverlayEndReason::Finished
+            } else {
+                return true;
+            };
+            emit(OutputEvent::OverlayEnded {
+                clip: clip.id,
+                reason
+            });
+            false
+        });
+
+        // Only clips already producing audio duck the music: a clip still
+        // downloading would otherwise leave a dip with nothing over it.
+        let target = self
+            .clips
+            .iter()
+            .filter(|clip| clip.deck.has_samples())
+            .filter_map(|clip| clip.duck)
+            .fold(1.0, f32::min);
+        if target != self.music_gain.level() {
+            let ramp_ms = if target < self.music_gain.level() {
+                DUCK_ATTACK_MS
+            } else {
+                DUCK_RELEASE_MS
+            };
+            self.music_gain.set(target, ramp_ms);
+        }
+    }
+
+    /// Mixes one sample of every clip over one sample of `music`.
+    pub fn mix(&mut self, music: f32) -> f32 {
+        let mut mixed = music * self.music_gain.next();
+        for clip in &mut self.clips {
+            mixed += clip.deck.next_output_sample().unwrap_or(0.0) * clip.volume;
+        }
+        mixed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clip whose whole download is already waiting in its channel.
+    fn clip(id: u32, samples: Vec<f32>, duck: Option<f32>) -> Clip {
+        let mut deck = Deck::new(OVERLAY_DECK);
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        tx.send(samples).unwrap();
+        deck.receiver = Some(rx);
+        Clip {
+            id,
+            deck,
+            volume: 1.0,
+            duck
+        }
+    }
+
+    #[test]
+    fn music_is_ducked_while_a_clip_sounds() {
+        let mut bus = OverlayBus::new();
+        bus.clips.push(clip(1, vec![0.5; 4], Some(0.2)));
+        bus.poll();
+        assert_eq!(bus.music_gain.level(), 0.2);
+        // The ramp starts from full level
+        assert_eq!(bus.mix(0.1), 0.1 + 0.5);
+
+        for _ in 0..3 {
+            bus.mix(0.0);
+        }
+        bus.poll();
+        assert!(bus.clip_ids().is_empty());
+        assert_eq!(bus.music_gain.level(), 1.0);
+    }
+
+    #[test]
+    fn stop_removes_one_clip_or_all() {
+        let mut bus = OverlayBus::new();
+        bus.clips.push(clip(1, vec![0.5; 4], None));
+        bus.clips.push(clip(2, vec![0.5; 4], None));
+        assert_eq!(bus.stop(Some(1)), vec![1]);
+        assert_eq!(bus.clip_ids(), vec![2]);
+        assert_eq!(bus.stop(None), vec![2]);
+    }
+}
@@ -1,20 +1,122 @@
 //! Wire protocol with Node.js: commands read from stdin, events written to stderr.
+//!
+//! Log lines are always `{event, data}`. State events are typed `OutputEvent`s,
+//! written with their own fields under the structured protocol, or packed into
+//! the legacy `data` string for clients that still parse it.
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::use_native_decoder;
+
+/// Events carry their fields packed into a `data` string.
+pub const LEGACY_PROTOCOL: u8 = 1;
+/// Events carry their fields as JSON properties, plus a timestamp.
+pub const STRUCTURED_PROTOCOL: u8 = 2;
+
+/// Every protocol version this engine can speak.
+pub const SUPPORTED_PROTOCOLS: [u8; 2] = [LEGACY_PROTOCOL, STRUCTURED_PROTOCOL];
+
+/// The `op` of every `InputCommand`, announced in the handshake so a client can
+/// tell which commands this build understands before relying on them.
+pub const SUPPORTED_COMMANDS: &[&str] = &[
+    "hello",
+    "load",
+    "crossfade",
+    "play",
+    "stop_deck",
+    "set_loop",
+    "skip_to",
+    "restart_deck",
+    "set_eq",
+    "set_playback_rate",
+    "seek",
+    "pause_all",
+    "resume_all",
+    "set_volume",
+    "set_next",
+    "remove_deck",
+    "play_overlay",
+    "stop_overlay",
+    "get_state",
+    "stop"
+];
+
+/// Capabilities beyond the command set itself.
+pub const FEATURES: &[&str] = &[
+    "volume",
+    "seek",
+    "eq",
+    "playback_rate",
+    "loudness_normalization",
+    "limiter",
+    "position_events",
+    "command_ack",
+    "state_snapshot",
+    "named_decks",
+    "overlay",
+    "file_sources",
+    "http_sources",
+    "track_cache",
+    "bounded_replay",
+    "live_sources",
+    "failure_codes",
+    "download_retry",
+    "download_resume",
+    "download_progress",
+    "track_metadata",
+    "silence_trimming"
+];
+
+static PROTOCOL_VERSION: AtomicU8 = AtomicU8::new(LEGACY_PROTOCOL);
+
+pub fn protocol_version() -> u8 {
+    PROTOCOL_VERSION.load(Ordering::Relaxed)
+}
+
+/// Selects the event shape; anything but a known version is ignored.
+pub fn set_protocol_version(version: u8) -> bool {
+    if !SUPPORTED_PROTOCOLS.contains(&version) {
+        return false;
+    }
+    PROTOCOL_VERSION.store(version, Ordering::Relaxed);
+    true
+}
 
 // Default for backward compatibility: LOAD without specific autoplay goes into autoplay
 fn default_autoplay() -> bool {
     true
 }
 
+// Default ramp for volume changes that do not specify one
+fn default_volume_ramp_ms() -> u64 {
+    50
+}
+
+// Overlay clips play at their own level unless Node.js asks otherwise
+fn default_overlay_volume() -> f32 {
+    1.0
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(tag = "op", rename_all = "snake_case")]
 pub enum InputCommand {
+    /// Handshake from Node.js: selects the protocol events are written in.
+    Hello {
+        protocol_version: u8
+    },
     Load {
         url: String,
         deck: String,
         #[serde(default = "default_autoplay")]
-        autoplay: bool
+        autoplay: bool,
+        /// A stream without an end (livestream, internet radio). yt-dlp
+        /// sources are also detected as live on their own.
+        #[serde(default)]
+        live: bool
     },
     /// Fades from the active deck to `to_deck` over `duration_ms`.
     Crossfade {
@@ -28,7 +130,7 @@ pub enum InputCommand {
     StopDeck {
         deck: String
     },
-    /// Loop mode: when the deck finishes, restart it from full_samples
+    /// Loop mode: when the deck finishes, restart it from its replay buffer
     SetLoop {
         enabled: bool
     },
@@ -40,19 +142,305 @@ pub enum InputCommand {
     RestartDeck {
         deck: String
     },
+    /// Sets the equalizer of one deck, or of the whole output when `deck` is
+    /// omitted. `bands` (dB per band, low to high) wins over `preset`; with
+    /// neither the equalizer goes flat.
+    SetEq {
+        #[serde(default)]
+        deck: Option<String>,
+        #[serde(default)]
+        preset: Option<String>,
+        #[serde(default)]
+        bands: Option<Vec<f32>>
+    },
+    /// Plays `deck` (both decks when omitted) faster or slower. Without
+    /// `preserve_pitch` the pitch follows the speed, as on a turntable.
+    SetPlaybackRate {
+        #[serde(default)]
+        deck: Option<String>,
+        rate: f32,
+        #[serde(default)]
+        preserve_pitch: bool
+    },
+    /// Jumps to `position_ms` inside the track loaded on `deck`.
+    Seek {
+        deck: String,
+        position_ms: u64
+    },
     /// Halts the output without discarding any deck state.
     PauseAll,
     /// Resumes the output exactly where `PauseAll` left it.
     ResumeAll,
+    /// Changes the gain of one deck, or of the whole output when `deck` is
+    /// omitted, ramping to `level` over `ramp_ms`.
+    SetVolume {
+        #[serde(default)]
+        deck: Option<String>,
+        level: f32,
+        #[serde(default = "default_volume_ramp_ms")]
+        ramp_ms: u64
+    },
+    /// Cues the deck auto-gapless moves to when the active one ends; `None`
+    /// goes back to alternating between "A" and "B".
+    SetNext {
+        deck: Option<String>
+    },
+    /// Closes a deck opened by `load`; "A" and "B" always stay.
+    RemoveDeck {
+        deck: String
+    },
+    /// Plays a short clip over the music. `duck` is the level the music is
+    /// brought down to while the clip sounds; without it the music is left alone.
+    /// Refused while playback is paused or stopped: no output runs to carry it.
+    PlayOverlay {
+        url: String,
+        #[serde(default = "default_overlay_volume")]
+        volume: f32,
+        #[serde(default)]
+        duck: Option<f32>
+    },
+    /// Stops one overlay clip, or all of them when `clip` is absent.
+    StopOverlay {
+        #[serde(default)]
+        clip: Option<u32>
+    },
+    /// Asks for a `state` event describing the whole mixer.
+    GetState,
     Stop
 }
 
+impl InputCommand {
+    /// The `op` this command was sent with, for reporting it back.
+    pub fn op(&self) -> &'static str {
+        match self {
+            Self::Hello { .. } => "hello",
+            Self::Load { .. } => "load",
+            Self::Crossfade { .. } => "crossfade",
+            Self::Play { .. } => "play",
+            Self::StopDeck { .. } => "stop_deck",
+            Self::SetLoop { .. } => "set_loop",
+            Self::SkipTo { .. } => "skip_to",
+            Self::RestartDeck { .. } => "restart_deck",
+            Self::SetEq { .. } => "set_eq",
+            Self::SetPlaybackRate { .. } => "set_playback_rate",
+            Self::Seek { .. } => "seek",
+            Self::PauseAll => "pause_all",
+            Self::ResumeAll => "resume_all",
+            Self::SetVolume { .. } => "set_volume",
+            Self::SetNext { .. } => "set_next",
+            Self::RemoveDeck { .. } => "remove_deck",
+            Self::PlayOverlay { .. } => "play_overlay",
+            Self::StopOverlay { .. } => "stop_overlay",
+            Self::GetState => "get_state",
+            Self::Stop => "stop"
+        }
+    }
+}
+
+/// A command read from stdin, with the correlation id Node.js may attach to
+/// any command. Commands sent with an id are answered with an `ack`.
+#[derive(Debug)]
+pub struct Request {
+    pub id: Option<Value>,
+    pub command: InputCommand
+}
+
+/// A stdin line that is not a command the engine understands.
+#[derive(Debug)]
+pub struct MalformedRequest {
+    pub id: Option<Value>,
+    pub op: Option<String>,
+    pub detail: String
+}
+
+/// Parses one stdin line. Whatever can be salvaged from a bad line (its `op`
+/// and `id`) is kept so the rejection can still be matched to the request.
+pub fn parse_request(line: &[u8]) -> Result<Request, MalformedRequest> {
+    let value: Value = serde_json::from_slice(line).map_err(|e| MalformedRequest {
+        id: None,
+        op: None,
+        detail: e.to_string()
+    })?;
+    let id = value.get("id").cloned();
+    match InputCommand::deserialize(&value) {
+        Ok(command) => Ok(Request { id, command }),
+        Err(e) => Err(MalformedRequest {
+            id,
+            op: value.get("op").and_then(Value::as_str).map(str::to_string),
+            detail: e.to_string()
+        })
+    }
+}
+
+/// Why a command was refused.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    /// Not valid JSON, or not a command this engine knows.
+    ParseError,
+    UnknownDeck,
+    /// Valid, but not applicable right now (e.g. a crossfade already running).
+    InvalidState,
+    /// A field is out of range or not a known name.
+    InvalidValue
+}
+
+/// What became of a command carrying an id.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AckStatus {
+    /// Carried out immediately.
+    Accepted,
+    /// Accepted, but waiting on a download (pending skip/crossfade, seek past
+    /// the buffered audio); later events report when it actually happens.
+    Deferred,
+    Rejected
+}
+
+impl AckStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Accepted => "accepted",
+            Self::Deferred => "deferred",
+            Self::Rejected => "rejected"
+        }
+    }
+}
+
+/// The playback state right after a command, carried by its `ack`.
+#[derive(Serialize, Debug)]
+pub struct MixerSummary {
+    pub active_deck: &'static str,
+    pub is_playing: bool,
+    pub loop_mode: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crossfade_to: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_to: Option<&'static str>
+}
+
+/// Reply to `get_state`: everything Node.js needs to rebuild its view of the
+/// engine after missing events.
+#[derive(Serialize, Debug)]
+pub struct MixerSnapshot {
+    pub active_deck: &'static str,
+    pub is_playing: bool,
+    pub loop_mode: bool,
+    pub crossfade: Option<CrossfadeSnapshot>,
+    pub pending: Option<PendingSnapshot>,
+    pub stall: Option<StallSnapshot>,
+    pub master_volume: f32,
+    /// Deck set by `set_next`, if any.
+    pub cued: Option<&'static str>,
+    pub max_decks: usize,
+    /// Overlay clips playing or downloading.
+    pub overlay_clips: Vec<u32>,
+    pub decks: BTreeMap<&'static str, DeckSnapshot>
+}
+
+#[derive(Serialize, Debug)]
+pub struct CrossfadeSnapshot {
+    pub target: &'static str,
+    /// 0.0 at the start of the fade, 1.0 when the target has taken over.
+    pub progress: f32,
+    pub duration_ms: u64,
+    /// How long the fade has been waiting on a target with no audio yet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stalled_ms: Option<u64>
+}
+
+#[derive(Serialize, Debug)]
+pub struct PendingSnapshot {
+    pub target: &'static str,
+    pub is_crossfade: bool,
+    pub duration_ms: u64,
+    pub waiting_ms: u64
+}
+
+#[derive(Serialize, Debug)]
+pub struct StallSnapshot {
+    pub target: &'static str,
+    pub waiting_ms: u64
+}
+
+#[derive(Serialize, Debug)]
+pub struct DeckSnapshot {
+    /// Samples queued for playback.
+    pub buffered_samples: usize,
+    /// Position inside the track, in samples.
+    pub played_samples: usize,
+    /// Samples kept for replay: everything downloaded so far, or 0 once the
+    /// track outgrew the replay buffer.
+    pub cached_samples: usize,
+    /// False once restart, seek and loop are no longer possible on this track.
+    pub replay_available: bool,
+    pub live: bool,
+    pub played_ms: u64,
+    pub buffered_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    pub downloading: bool,
+    pub download_failed: bool,
+    /// Why, when the download failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<FailureCode>,
+    pub has_ended: bool,
+    pub volume: f32,
+    pub playback_rate: f32,
+    pub preserve_pitch: bool
+}
+
+/// What the source knows about a track, carried by `track_metadata`. Also
+/// kept next to the track in the on-disk cache.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct TrackInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uploader: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// URL of the thumbnail image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
+    /// Empty when the track has none.
+    #[serde(default)]
+    pub chapters: Vec<Chapter>
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Chapter {
+    pub title: String,
+    pub start_ms: u64,
+    pub end_ms: u64
+}
+
+impl RejectReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::ParseError => "parse_error",
+            Self::UnknownDeck => "unknown_deck",
+            Self::InvalidState => "invalid_state",
+            Self::InvalidValue => "invalid_value"
+        }
+    }
+}
+
 #[derive(Serialize)]
 struct LogMessage {
     event: String,
     data: String
 }
 
+/// The first `max_chars` characters of `text`, for logging long URLs. Cuts
+/// between characters: a file path or link may carry non-ASCII names.
+pub fn shortened(text: &str, max_chars: usize) -> &str {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => &text[..end],
+        None => text
+    }
+}
+
 pub fn send_log(event: &str, data: &str) {
     let msg = LogMessage {
         event: event.to_string(),
@@ -62,3 +450,901 @@ pub fn send_log(event: &str, data: &str) {
         eprintln!("{}", json);
     }
 }
+
+/// What made the mixer change the deck being heard.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeckChangeTrigger {
+    PlayCommand,
+    SkipCommand,
+    PendingSkip,
+    CrossfadeSnap,
+    CrossfadeCompletion,
+    AutoGapless,
+    AutoGaplessStall,
+    MidChunkAutoGapless
+}
+
+/// Failure of the in-process decoder.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(not(feature = "native-decoder"), allow(dead_code))]
+#[serde(rename_all = "snake_case")]
+pub enum DecodeErrorKind {
+    UnrecognizedFormat,
+    UnsupportedCodec,
+    CorruptStream,
+    Io
+}
+
+impl DecodeErrorKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::UnrecognizedFormat => "unrecognized_format",
+            Self::UnsupportedCodec => "unsupported_codec",
+            Self::CorruptStream => "corrupt_stream",
+            Self::Io => "io"
+        }
+    }
+}
+
+/// Why a deck's download produced no audio, as far as the engine can tell.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureCode {
+    AgeRestricted,
+    GeoBlocked,
+    /// Private, removed, or otherwise gone for good.
+    Unavailable,
+    SignInRequired,
+    RateLimited,
+    ProxyUnreachable,
+    /// Connection refused, reset or timed out, outside the proxy.
+    Network,
+    DecodeFailed,
+    /// The download watchdog killed a source that sent nothing.
+    WatchdogTimeout,
+    /// yt-dlp or ffmpeg could not be started.
+    SpawnFailed,
+    /// Still downloading, but too slow for the transition waiting on it.
+    Stalled,
+    Unknown
+}
+
+impl FailureCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::AgeRestricted => "age_restricted",
+            Self::GeoBlocked => "geo_blocked",
+            Self::Unavailable => "unavailable",
+            Self::SignInRequired => "sign_in_required",
+            Self::RateLimited => "rate_limited",
+            Self::ProxyUnreachable => "proxy_unreachable",
+            Self::Network => "network",
+            Self::DecodeFailed => "decode_failed",
+            Self::WatchdogTimeout => "watchdog_timeout",
+            Self::SpawnFailed => "spawn_failed",
+            Self::Stalled => "stalled",
+            Self::Unknown => "unknown"
+        }
+    }
+}
+
+/// Why an overlay clip stopped sounding.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlayEndReason {
+    Finished,
+    Stopped,
+    Failed
+}
+
+/// Which end of a track was trimmed.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrimEdge {
+    Start,
+    End
+}
+
+impl TrimEdge {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Start => "start",
+            Self::End => "end"
+        }
+    }
+}
+
+impl OverlayEndReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Finished => "finished",
+            Self::Stopped => "stopped",
+            Self::Failed => "failed"
+        }
+    }
+}
+
+impl DeckChangeTrigger {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::PlayCommand => "play_command",
+            Self::SkipCommand => "skip_command",
+            Self::PendingSkip => "pending_skip",
+            Self::CrossfadeSnap => "crossfade_snap",
+            Self::CrossfadeCompletion => "crossfade_completion",
+            Self::AutoGapless => "auto_gapless",
+            Self::AutoGaplessStall => "auto_gapless_stall",
+            Self::MidChunkAutoGapless => "mid_chunk_auto_gapless"
+        }
+    }
+}
+
+/// State changes reported to Node.js.
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum OutputEvent {
+    /// Sent once at startup: what this build of the engine can do.
+    Hello {
+        protocol_version: u8,
+        protocols: Vec<u8>,
+        engine_version: &'static str,
+        commands: Vec<&'static str>,
+        features: Vec<&'static str>
+    },
+    /// Answer to the `Hello` command. When the requested version is not
+    /// supported, `protocol_version` is the one still in use.
+    ProtocolSelected {
+        protocol_version: u8,
+        accepted: bool
+    },
+    /// A command was not carried out; `op` is absent when the line could not
+    /// be parsed far enough to tell.
+    CommandRejected {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<Value>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        op: Option<String>,
+        reason: RejectReason,
+        detail: String
+    },
+    /// Reply to a command that carried an id, once it has been applied.
+    Ack {
+        id: Value,
+        op: String,
+        status: AckStatus,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<RejectReason>,
+        state: MixerSummary
+    },
+    DeckChanged {
+        deck: &'static str,
+        trigger: DeckChangeTrigger
+    },
+    /// `blocking` is true when playback is stuck on the deck rather than a
+    /// preload having gone to waste.
+    DeckFailed {
+        deck: &'static str,
+        blocking: bool,
+        reason: FailureCode
+    },
+    CrossfadeStarted {
+        from: &'static str,
+        to: &'static str
+    },
+    BufferReady {
+        deck: &'static str
+    },
+    PlaybackConfirmed {
+        deck: &'static str
+    },
+    ApproachingEnd {
+        deck: &'static str
+    },
+    End {
+        deck: &'static str
+    },
+    AutoEndSwitch {
+        deck: &'static str
+    },
+    AutoLoopRestart {
+        deck: &'static str
+    },
+    DeckRestarted {
+        deck: &'static str
+    },
+    DeckSeeked {
+        deck: &'static str,
+        position_ms: u64
+    },
+    /// The track outgrew the replay buffer after `limit_ms`: restart, seek and
+    /// loop are refused on this deck until its next load.
+    ReplayUnavailable {
+        deck: &'static str,
+        limit_ms: u64
+    },
+    StreamOpened {
+        deck: &'static str,
+        url: String
+    },
+    /// The deck plays a live stream: no replay, no approaching end, and
+    /// dropped connections are reopened.
+    LiveStream {
+        deck: &'static str
+    },
+    /// A live stream dropped; the next connection attempt is `delay_ms` away.
+    StreamReconnecting {
+        deck: &'static str,
+        attempt: u32,
+        delay_ms: u64
+    },
+    /// A download failed early and is tried again with another strategy in
+    /// `delay_ms`; `reason` is why the previous attempt failed.
+    DownloadRetry {
+        deck: &'static str,
+        attempt: u32,
+        strategy: &'static str,
+        reason: FailureCode,
+        delay_ms: u64
+    },
+    /// The download broke mid-song and is picked up again at `position_ms`.
+    DownloadResumed {
+        deck: &'static str,
+        attempt: u32,
+        position_ms: u64
+    },
+    /// How a deck's download is getting on: `decoded_ms` of the track are
+    /// decoded, at `realtime_factor` times playback speed. `bytes_received`
+    /// is only known when the engine reads the encoded audio itself, and
+    /// `duration_ms` once the source or the decoder tells. The last event of
+    /// a download, whatever its outcome, is `finished`.
+    DownloadProgress {
+        deck: &'static str,
+        decoded_ms: u64,
+        realtime_factor: f32,
+        finished: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bytes_received: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        duration_ms: Option<u64>
+    },
+    /// What the source found out about the track loaded on `deck`, once per
+    /// load.
+    TrackMetadata {
+        deck: &'static str,
+        #[serde(flatten)]
+        info: TrackInfo
+    },
+    /// `trimmed_ms` of silence were cut from one end of the track on `deck`.
+    /// `position` and the deck snapshot count the trimmed track, while
+    /// `download_progress` and `track_metadata` keep counting the source's.
+    SilenceTrimmed {
+        deck: &'static str,
+        edge: TrimEdge,
+        trimmed_ms: u64
+    },
+    /// "Now playing" of a radio stream (ICY metadata).
+    StreamMetadata {
+        deck: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        station: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        title: Option<String>
+    },
+    Position {
+        deck: &'static str,
+        played_ms: u64,
+        buffered_ms: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        duration_ms: Option<u64>
+    },
+    VolumeChanged {
+        master: f32,
+        decks: BTreeMap<&'static str, f32>
+    },
+    EqChanged {
+        /// "master" or a deck name
+        target: &'static str,
+        gains_db: Vec<f32>
+    },
+    PlaybackRateChanged {
+        deck: &'static str,
+        rate: f32,
+        preserve_pitch: bool
+    },
+    LoudnessMeasured {
+        deck: &'static str,
+        integrated_lufs: f64,
+        gain_db: f64
+    },
+    OverlayStarted {
+        clip: u32,
+        url: String
+    },
+    /// The in-process decoder gave up on a deck's stream.
+    #[cfg_attr(not(feature = "native-decoder"), allow(dead_code))]
+    DecoderError {
+        deck: &'static str,
+        kind: DecodeErrorKind,
+        detail: String
+    },
+    OverlayEnded {
+        clip: u32,
+        reason: OverlayEndReason
+    },
+    /// Reply to `get_state`.
+    State(Box<MixerSnapshot>)
+}
+
+impl OutputEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Hello { .. } => "hello",
+            Self::ProtocolSelected { .. } => "protocol_selected",
+            Self::CommandRejected { .. } => "command_rejected",
+            Self::Ack { .. } => "ack",
+            Self::DeckChanged { .. } => "deck_changed",
+            Self::DeckFailed { .. } => "deck_failed",
+            Self::CrossfadeStarted { .. } => "crossfade_started",
+            Self::BufferReady { .. } => "buffer_ready",
+            Self::PlaybackConfirmed { .. } => "playback_confirmed",
+            Self::ApproachingEnd { .. } => "approaching_end",
+            Self::End { .. } => "end",
+            Self::AutoEndSwitch { .. } => "auto_end_switch",
+            Self::AutoLoopRestart { .. } => "auto_loop_restart",
+            Self::DeckRestarted { .. } => "deck_restarted",
+            Self::DeckSeeked { .. } => "deck_seeked",
+            Self::ReplayUnavailable { .. } => "replay_unavailable",
+            Self::StreamOpened { .. } => "stream_opened",
+            Self::LiveStream { .. } => "live_stream",
+            Self::StreamReconnecting { .. } => "stream_reconnecting",
+            Self::DownloadRetry { .. } => "download_retry",
+            Self::DownloadResumed { .. } => "download_resumed",
+            Self::DownloadProgress { .. } => "download_progress",
+            Self::TrackMetadata { .. } => "track_metadata",
+            Self::SilenceTrimmed { .. } => "silence_trimmed",
+            Self::StreamMetadata { .. } => "stream_metadata",
+            Self::Position { .. } => "position",
+            Self::VolumeChanged { .. } => "volume_changed",
+            Self::EqChanged { .. } => "eq_changed",
+            Self::PlaybackRateChanged { .. } => "playback_rate_changed",
+            Self::LoudnessMeasured { .. } => "loudness_measured",
+            Self::OverlayStarted { .. } => "overlay_started",
+            Self::DecoderError { .. } => "decoder_error",
+            Self::OverlayEnded { .. } => "overlay_ended",
+            Self::State(_) => "state"
+        }
+    }
+
+    /// The `data` string of the legacy protocol, exactly as older clients
+    /// parse it.
+    fn legacy_data(&self) -> String {
+        match self {
+            // Lists are joined with '/': a client checks its commands and
+            // features under either protocol
+            Self::Hello {
+                protocol_version,
+                protocols,
+                engine_version,
+                commands,
+                features
+            } => format!(
+                "protocol_version={}, engine_version={}, protocols={}, commands={}, features={}",
+                protocol_version,
+                engine_version,
+                protocols
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+                commands.join("/"),
+                features.join("/")
+            ),
+            Self::ProtocolSelected {
+                protocol_version,
+                accepted
+            } => format!(
+                "protocol_version={}, accepted={}",
+                protocol_version, accepted
+            ),
+            Self::CommandRejected {
+                id,
+                op,
+                reason,
+                detail
+            } => {
+                let mut data = format!(
+                    "op={}, reason={}, detail={}",
+                    op.as_deref().unwrap_or("unknown"),
+                    reason.as_str(),
+                    detail
+                );
+                if let Some(id) = id {
+                    data.push_str(&format!(", id={}", id));
+                }
+                data
+            }
+            Self::Ack {
+                id,
+                op,
+                status,
+                reason,
+                state
+            } => {
+                let mut data = format!(
+                    "id={}, op={}, status={}, active={}, playing={}",
+                    id,
+                    op,
+                    status.as_str(),
+                    state.active_deck,
+                    state.is_playing
+                );
+                if let Some(reason) = reason {
+                    data.push_str(&format!(", reason={}", reason.as_str()));
+                }
+                data
+            }
+            Self::DeckChanged { deck, trigger } => {
+                format!("deck={}, triggered_by={}", deck, trigger.as_str())
+            }
+            Self::DeckFailed { deck, blocking, reason } => {
+                format!("deck={}, blocking={}, reason={}", deck, blocking, reason.as_str())
+            }
+            Self::CrossfadeStarted { from, to } => format!("from={}, to={}", from, to),
+            Self::BufferReady { deck }
+            | Self::PlaybackConfirmed { deck }
+            | Self::ApproachingEnd { deck }
+            | Self::End { deck }
+            | Self::AutoEndSwitch { deck }
+            | Self::AutoLoopRestart { deck } => deck.to_string(),
+            Self::DeckRestarted { deck } => format!("deck={}", deck),
+            Self::DeckSeeked { deck, position_ms } => {
+                format!("deck={}, position_ms={}", deck, position_ms)
+            }
+            Self::ReplayUnavailable { deck, limit_ms } => {
+                format!("deck={}, limit_ms={}", deck, limit_ms)
+            }
+            Self::StreamOpened { deck, url } => {
+                format!("[Deck {}] Streaming: {}", deck, shortened(url, 60))
+            }
+            Self::LiveStream { deck } => format!("deck={}", deck),
+            Self::StreamReconnecting {
+                deck,
+                attempt,
+                delay_ms
+            } => format!("deck={}, attempt={}, delay_ms={}", deck, attempt, delay_ms),
+            Self::DownloadRetry {
+                deck,
+                attempt,
+                strategy,
+                reason,
+                delay_ms
+            } => format!(
+                "deck={}, attempt={}, strategy={}, reason={}, delay_ms={}",
+                deck,
+                attempt,
+                strategy,
+                reason.as_str(),
+                delay_ms
+            ),
+            Self::DownloadResumed {
+                deck,
+                attempt,
+                position_ms
+            } => format!("deck={}, attempt={}, position_ms={}", deck, attempt, position_ms),
+            Self::DownloadProgress {
+                deck,
+                decoded_ms,
+                realtime_factor,
+                finished,
+                bytes_received,
+                duration_ms
+            } => {
+                let bytes = bytes_received
+                    .map(|bytes| format!(", bytes_received={}", bytes))
+                    .unwrap_or_default();
+                let duration = duration_ms
+                    .map(|ms| format!(", duration_ms={}", ms))
+                    .unwrap_or_default();
+                format!(
+                    "deck={}, decoded_ms={}, realtime_factor={:.2}, finished={}{}{}",
+                    deck, decoded_ms, realtime_factor, finished, bytes, duration
+                )
+            }
+            Self::TrackMetadata { deck, info } => {
+                let duration = info
+                    .duration_ms
+                    .map(|ms| format!(", duration_ms={}", ms))
+                    .unwrap_or_default();
+                format!(
+                    "deck={}, title={}, uploader={}{}, chapters={}",
+                    deck,
+                    info.title.as_deref().unwrap_or(""),
+                    info.uploader.as_deref().unwrap_or(""),
+                    duration,
+                    info.chapters.len()
+                )
+            }
+            Self::SilenceTrimmed {
+                deck,
+                edge,
+                trimmed_ms
+            } => format!("deck={}, edge={}, trimmed_ms={}", deck, edge.as_str(), trimmed_ms),
+            Self::StreamMetadata {
+                deck,
+                station,
+                title
+            } => format!(
+                "deck={}, station={}, title={}",
+                deck,
+                station.as_deref().unwrap_or(""),
+                title.as_deref().unwrap_or("")
+            ),
+            Self::Position {
+                deck,
+                played_ms,
+                buffered_ms,
+                duration_ms
+            } => {
+                let duration = duration_ms
+                    .map(|ms| format!(", duration_ms={}", ms))
+                    .unwrap_or_default();
+                format!(
+                    "deck={}, played_ms={}, buffered_ms={}{}",
+                    deck, played_ms, buffered_ms, duration
+                )
+            }
+            Self::VolumeChanged { master, decks } => {
+                let mut data = format!("master={:.2}", master);
+                for (deck, level) in decks {
+                    data.push_str(&format!(", {}={:.2}", deck, level));
+                }
+                data
+            }
+            Self::EqChanged { target, gains_db } => format!(
+                "target={}, gains_db={}",
+                target,
+                gains_db
+                    .iter()
+                    .map(|g| format!("{:.1}", g))
+                    .collect::<Vec<_>>()
+                    .join("/")
+            ),
+            Self::PlaybackRateChanged {
+                deck,
+                rate,
+                preserve_pitch
+            } => format!(
+                "deck={}, rate={:.2}, preserve_pitch={}",
+                deck, rate, preserve_pitch
+            ),
+            Self::LoudnessMeasured {
+                deck,
+                integrated_lufs,
+                gain_db
+            } => format!(
+                "deck={}, integrated_lufs={:.1}, gain_db={:.1}",
+                deck, integrated_lufs, gain_db
+            ),
+            Self::OverlayStarted { clip, url } => format!("clip={}, url={}", clip, url),
+            Self::DecoderError { deck, kind, detail } => {
+                format!("deck={}, kind={}, detail={}", deck, kind.as_str(), detail)
+            }
+            Self::OverlayEnded { clip, reason } => {
+                format!("clip={}, reason={}", clip, reason.as_str())
+            }
+            // Too nested for key=value pairs: legacy clients get the JSON.
+            Self::State(snapshot) => serde_json::to_string(snapshot).unwrap_or_default()
+        }
+    }
+
+    /// The line written to stderr under the given protocol version.
+    fn to_line(&self, version: u8) -> Option<String> {
+        if version == LEGACY_PROTOCOL {
+            let msg = LogMessage {
+                event: self.name().to_string(),
+                data: self.legacy_data()
+            };
+            return serde_json::to_string(&msg).ok();
+        }
+
+        #[derive(Serialize)]
+        struct Structured<'a> {
+            #[serde(flatten)]
+            event: &'a OutputEvent,
+            ts_ms: u64
+        }
+        let ts_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        serde_json::to_string(&Structured { event: self, ts_ms }).ok()
+    }
+}
+
+/// The startup announcement, written in the protocol currently selected.
+pub fn hello_event() -> OutputEvent {
+    let mut features = FEATURES.to_vec();
+    if use_native_decoder() {
+        features.push("native_decoder");
+    }
+    OutputEvent::Hello {
+        protocol_version: protocol_version(),
+        protocols: SUPPORTED_PROTOCOLS.to_vec(),
+        engine_version: env!("CARGO_PKG_VERSION"),
+        commands: SUPPORTED_COMMANDS.to_vec(),
+        features
+    }
+}
+
+/// Reports a state change to Node.js in the negotiated protocol shape.
+pub fn emit(event: OutputEvent) {
+    if let Some(line) = event.to_line(protocol_version()) {
+        eprintln!("{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_lines_keep_the_packed_data_string() {
+        let event = OutputEvent::DeckChanged {
+            deck: "B",
+            trigger: DeckChangeTrigger::PendingSkip
+        };
+        let line = event.to_line(LEGACY_PROTOCOL).unwrap();
+        assert_eq!(
+            line,
+            r#"{"event":"deck_changed","data":"deck=B, triggered_by=pending_skip"}"#
+        );
+    }
+
+    #[test]
+    fn structured_lines_carry_typed_fields() {
+        let event = OutputEvent::DeckFailed {
+            deck: "A",
+            blocking: true,
+            reason: FailureCode::AgeRestricted
+        };
+        let line = event.to_line(STRUCTURED_PROTOCOL).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["event"], "deck_failed");
+        assert_eq!(value["deck"], "A");
+        assert_eq!(value["blocking"], true);
+        assert_eq!(value["reason"], "age_restricted");
+        assert!(value["ts_ms"].as_u64().is_some());
+    }
+
+    #[test]
+    fn handshake_lists_every_command_the_engine_parses() {
+        // Each announced op must deserialize into a command (possibly failing
+        // on missing fields, but never on an unknown variant).
+        for op in SUPPORTED_COMMANDS {
+            let err = serde_json::from_str::<InputCommand>(&format!(r#"{{"op":"{}"}}"#, op))
+                .err()
+                .map(|e| e.to_string())
+                .unwrap_or_default();
+            assert!(!err.contains("unknown variant"), "{}: {}", op, err);
+        }
+    }
+
+    #[test]
+    fn bad_lines_keep_what_can_be_salvaged() {
+        let err = parse_request(br#"{"op":"crossfade","id":7,"to_deck":"B"}"#).unwrap_err();
+        assert_eq!(err.op.as_deref(), Some("crossfade"));
+        assert_eq!(err.id, Some(Value::from(7)));
+        assert!(err.detail.contains("duration_ms"));
+
+        let err = parse_request(b"{not json").unwrap_err();
+        assert!(err.op.is_none() && err.id.is_none());
+    }
+
+    #[test]
+    fn requests_carry_their_correlation_id() {
+        let request = parse_request(br#"{"op":"play","deck":"A","id":"abc"}"#).unwrap();
+        assert_eq!(request.id, Some(Value::from("abc")));
+        assert_eq!(request.command.op(), "play");
+    }
+
+    /// One value of every `OutputEvent` variant.
+    fn every_event() -> Vec<OutputEvent> {
+        let summary = || MixerSummary {
+            active_deck: "A",
+            is_playing: true,
+            loop_mode: false,
+            crossfade_to: None,
+            pending_to: None
+        };
+        vec![
+            hello_event(),
+            OutputEvent::ProtocolSelected { protocol_version: 2, accepted: true },
+            OutputEvent::CommandRejected {
+                id: None,
+                op: None,
+                reason: RejectReason::ParseError,
+                detail: String::new()
+            },
+            OutputEvent::Ack {
+                id: Value::from(1),
+                op: "play".to_string(),
+                status: AckStatus::Accepted,
+                reason: None,
+                state: summary()
+            },
+            OutputEvent::DeckChanged { deck: "A", trigger: DeckChangeTrigger::PlayCommand },
+            OutputEvent::DeckFailed { deck: "A", blocking: true, reason: FailureCode::Unknown },
+            OutputEvent::CrossfadeStarted { from: "A", to: "B" },
+            OutputEvent::BufferReady { deck: "A" },
+            OutputEvent::PlaybackConfirmed { deck: "A" },
+            OutputEvent::ApproachingEnd { deck: "A" },
+            OutputEvent::End { deck: "A" },
+            OutputEvent::AutoEndSwitch { deck: "A" },
+            OutputEvent::AutoLoopRestart { deck: "A" },
+            OutputEvent::DeckRestarted { deck: "A" },
+            OutputEvent::DeckSeeked { deck: "A", position_ms: 1 },
+            OutputEvent::ReplayUnavailable { deck: "A", limit_ms: 1 },
+            OutputEvent::StreamOpened { deck: "A", url: String::new() },
+            OutputEvent::LiveStream { deck: "A" },
+            OutputEvent::StreamReconnecting { deck: "A", attempt: 1, delay_ms: 1 },
+            OutputEvent::DownloadRetry {
+                deck: "A",
+                attempt: 1,
+                strategy: "",
+                reason: FailureCode::Network,
+                delay_ms: 1
+            },
+            OutputEvent::DownloadResumed { deck: "A", attempt: 1, position_ms: 1 },
+            OutputEvent::DownloadProgress {
+                deck: "A",
+                decoded_ms: 1,
+                realtime_factor: 1.0,
+                finished: false,
+                bytes_received: None,
+                duration_ms: None
+            },
+            OutputEvent::TrackMetadata { deck: "A", info: TrackInfo::default() },
+            OutputEvent::SilenceTrimmed { deck: "A", edge: TrimEdge::Start, trimmed_ms: 1 },
+            OutputEvent::StreamMetadata { deck: "A", station: None, title: None },
+            OutputEvent::Position { deck: "A", played_ms: 1, buffered_ms: 2, duration_ms: None },
+            OutputEvent::VolumeChanged { master: 1.0, decks: BTreeMap::new() },
+            OutputEvent::EqChanged { target: "master", gains_db: Vec::new() },
+            OutputEvent::PlaybackRateChanged { deck: "A", rate: 1.0, preserve_pitch: false },
+            OutputEvent::LoudnessMeasured { deck: "A", integrated_lufs: -14.0, gain_db: 0.0 },
+            OutputEvent::OverlayStarted { clip: 1, url: String::new() },
+            OutputEvent::DecoderError {
+                deck: "A",
+                kind: DecodeErrorKind::Io,
+                detail: String::new()
+            },
+            OutputEvent::OverlayEnded { clip: 1, reason: OverlayEndReason::Finished },
+            OutputEvent::State(Box::new(crate::state::MixerState::new().snapshot()))
+        ]
+    }
+
+    #[test]
+    fn the_legacy_hello_lists_commands_and_features() {
+        let line = hello_event().to_line(LEGACY_PROTOCOL).unwrap();
+        let value: Value = serde_json::from_str(&line).unwrap();
+        let data = value["data"].as_str().unwrap();
+        let field = |key: &str| {
+            data.split(", ")
+                .find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))
+                .unwrap()
+        };
+        assert_eq!(field("protocols"), "1/2");
+        let commands: Vec<&str> = field("commands").split('/').collect();
+        assert_eq!(commands, SUPPORTED_COMMANDS.to_vec());
+        assert!(data.contains("features="));
+    }
+
+    #[test]
+    fn every_event_name_matches_its_serialized_tag() {
+        let events = every_event();
+        for event in &events {
+            // No wildcard: a new variant does not compile here until it is
+            // added to `every_event`
+            match event {
+                OutputEvent::Hello { .. }
+                | OutputEvent::ProtocolSelected { .. }
+                | OutputEvent::CommandRejected { .. }
+                | OutputEvent::Ack { .. }
+                | OutputEvent::DeckChanged { .. }
+                | OutputEvent::DeckFailed { .. }
+                | OutputEvent::CrossfadeStarted { .. }
+                | OutputEvent::BufferReady { .. }
+                | OutputEvent::PlaybackConfirmed { .. }
+                | OutputEvent::ApproachingEnd { .. }
+                | OutputEvent::End { .. }
+                | OutputEvent::AutoEndSwitch { .. }
+                | OutputEvent::AutoLoopRestart { .. }
+                | OutputEvent::DeckRestarted { .. }
+                | OutputEvent::DeckSeeked { .. }
+                | OutputEvent::ReplayUnavailable { .. }
+                | OutputEvent::StreamOpened { .. }
+                | OutputEvent::LiveStream { .. }
+                | OutputEvent::StreamReconnecting { .. }
+                | OutputEvent::DownloadRetry { .. }
+                | OutputEvent::DownloadResumed { .. }
+                | OutputEvent::DownloadProgress { .. }
+                | OutputEvent::TrackMetadata { .. }
+                | OutputEvent::SilenceTrimmed { .. }
+                | OutputEvent::StreamMetadata { .. }
+                | OutputEvent::Position { .. }
+                | OutputEvent::VolumeChanged { .. }
+                | OutputEvent::EqChanged { .. }
+                | OutputEvent::PlaybackRateChanged { .. }
+                | OutputEvent::LoudnessMeasured { .. }
+                | OutputEvent::OverlayStarted { .. }
+                | OutputEvent::DecoderError { .. }
+                | OutputEvent::OverlayEnded { .. }
+                | OutputEvent::State(_) => {}
+            }
+            for version in SUPPORTED_PROTOCOLS {
+                let value: serde_json::Value =
+                    serde_json::from_str(&event.to_line(version).unwrap()).unwrap();
+                assert_eq!(value["event"], event.name(), "protocol {}", version);
+            }
+        }
+        let names: std::collections::BTreeSet<_> = events.iter().map(OutputEvent::name).collect();
+        assert_eq!(names.len(), events.len(), "two events share a name");
+    }
+
+    #[test]
+    fn urls_are_shortened_between_characters() {
+        let url = format!("file:///{}", "é".repeat(60));
+        assert_eq!(shortened(&url, 60).chars().count(), 60);
+        assert_eq!(shortened("file:///a.wav", 60), "file:///a.wav");
+    }
+
+    #[test]
+    fn absent_optional_fields_are_left_out() {
+        let event = OutputEvent::Position {
+            deck: "A",
+            played_ms: 1,
+            buffered_ms: 2,
+            duration_ms: None
+        };
+        let value: serde_json::Value =
+            serde_json::from_str(&event.to_line(STRUCTURED_PROTOCOL).unwrap()).unwrap();
+        assert!(value.get("duration_ms").is_none());
+    }
+
+    #[test]
+    fn state_snapshot_is_written_flat_under_the_state_event() {
+        let state = crate::state::MixerState::new();
+        let line = OutputEvent::State(Box::new(state.snapshot()))
+            .to_line(STRUCTURED_PROTOCOL)
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["event"], "state");
+        assert_eq!(value["active_deck"], "A");
+        assert!(value["crossfade"].is_null());
+        assert_eq!(value["decks"]["B"]["downloading"], false);
+    }
+
+    #[test]
+    fn track_metadata_is_written_flat_next_to_the_deck() {
+        let event = OutputEvent::TrackMetadata {
+            deck: "B",
+            info: TrackInfo {
+                title: Some("Song".to_string()),
+                duration_ms: Some(180_000),
+                chapters: vec![Chapter {
+                    title: "Intro".to_string(),
+                    start_ms: 0,
+                    end_ms: 30_000
+                }],
+                ..TrackInfo::default()
+            }
+        };
+        let value: serde_json::Value =
+            serde_json::from_str(&event.to_line(STRUCTURED_PROTOCOL).unwrap()).unwrap();
+        assert_eq!(value["event"], "track_metadata");
+        assert_eq!(value["deck"], "B");
+        assert_eq!(value["title"], "Song");
+        assert!(value.get("uploader").is_none());
+        assert_eq!(value["chapters"][0]["end_ms"], 30_000);
+    }
+}
@@ -0,0 +1,400 @@
+//! Playback rate effects: varispeed (tempo and pitch move together, as in
+//! nightcore/vaporwave) and time-stretching (tempo only, pitch preserved).
+//!
+//! The processor sits between a deck's buffers and its output, pulling source
+//! samples through a callback. Source and output therefore run at different
+//! speeds; the deck converts between the two where real time matters.
+
+use std::collections::VecDeque;
+
+use crate::config::{CHANNELS, SAMPLE_RATE};
+
+pub const MIN_RATE: f32 = 0.5;
+pub const MAX_RATE: f32 = 2.0;
+
+/// Length of a time-stretch grain (40 ms): long enough to hold a bass period,
+/// short enough not to smear transients.
+const GRAIN_FRAMES: usize = SAMPLE_RATE / 25;
+/// Grains overlap by half, so each one produces this many output frames.
+const HOP_FRAMES: usize = GRAIN_FRAMES / 2;
+/// How far a grain may be moved to line up with the previous one (5 ms).
+const SEEK_TOLERANCE: usize = SAMPLE_RATE / 200;
+/// Only every n-th frame is compared when lining grains up: plenty for
+/// picking the best alignment at a fraction of the cost.
+const CORRELATION_STRIDE: usize = 4;
+
+type Frame = [f32; CHANNELS];
+
+/// Pulls one frame from an interleaved sample source.
+fn read_frame(source: &mut dyn FnMut() -> Option<f32>) -> Option<Frame> {
+    let mut frame = [0.0; CHANNELS];
+    for sample in frame.iter_mut() {
+        *sample = source()?;
+    }
+    Some(frame)
+}
+
+/// Periodic Hann window: two copies offset by half a grain sum to one.
+fn hann(i: usize) -> f32 {
+    let x = i as f32 / GRAIN_FRAMES as f32;
+    0.5 - 0.5 * (std::f32::consts::TAU * x).cos()
+}
+
+/// Linear-interpolation resampler: reads the source at `rate` frames per frame.
+struct Varispeed {
+    previous: Frame,
+    next: Frame,
+    /// Position between `previous` and `next`; 2.0 primes both on first use.
+    fraction: f64,
+    /// `next` holds a frame of the source, not played yet.
+    primed: bool
+}
+
+impl Varispeed {
+    fn new() -> Self {
+        Self {
+            previous: [0.0; CHANNELS],
+            next: [0.0; CHANNELS],
+            fraction: 2.0,
+            primed: false
+        }
+    }
+
+    fn next_frame(&mut self, rate: f32, source: &mut dyn FnMut() -> Option<f32>) -> Option<Frame> {
+        while self.fraction >= 1.0 {
+            let frame = read_frame(source)?;
+            self.previous = self.next;
+            self.next = frame;
+            self.fraction -= 1.0;
+            self.primed = true;
+        }
+        let t = self.fraction as f32;
+        self.fraction += rate as f64;
+        Some(std::array::from_fn(|ch| {
+            self.previous[ch] + (self.next[ch] - self.previous[ch]) * t
+        }))
+    }
+
+    /// The frame read ahead: output only ever interpolates towards it.
+    fn flush(&mut self) -> Vec<Frame> {
+        let unplayed = if self.primed { vec![self.next] } else { Vec::new() };
+        *self = Self::new();
+        unplayed
+    }
+}
+
+/// WSOLA time-stretch: output grains advance by a fixed hop while the source
+/// advances by `rate` hops, each grain nudged to line up with the previous one
+/// so the overlap adds up without phase cancellation.
+struct Stretch {
+    input: VecDeque<Frame>,
+    /// Absolute source index of `input[0]`.
+    origin: usize,
+    /// Nominal start of the next grain, in source frames.
+    position: f64,
+    /// Where the source naturally continues after the last grain, which the
+    /// next grain is matched against. None before the first grain.
+    continuation: Option<usize>,
+    /// Windowed second half of the last grain, waiting to be overlapped.
+    tail: Vec<Frame>,
+    output: VecDeque<Frame>
+}
+
+impl Stretch {
+    fn new() -> Self {
+        Self {
+            input: VecDeque::new(),
+            origin: 0,
+            position: 0.0,
+            continuation: None,
+            tail: vec![[0.0; CHANNELS]; HOP_FRAMES],
+            output: VecDeque::with_capacity(HOP_FRAMES)
+        }
+    }
+
+    fn frame(&self, index: usize) -> Frame {
+        self.input[index - self.origin]
+    }
+
+    /// Mono correlation between the source at `a` and at `b` over one hop.
+    fn correlation(&self, a: usize, b: usize) -> f32 {
+        (0..HOP_FRAMES)
+            .step_by(CORRELATION_STRIDE)
+            .map(|i| {
+                let x = self.frame(a + i);
+                let y = self.frame(b + i);
+                x.iter().sum::<f32>() * y.iter().sum::<f32>()
+            })
+            .sum()
+    }
+
+    fn next_frame(&mut self, rate: f32, source: &mut dyn FnMut() -> Option<f32>) -> Option<Frame> {
+        if self.output.is_empty() {
+            self.produce_hop(rate, source)?;
+        }
+        self.output.pop_front()
+    }
+
+    fn produce_hop(&mut self, rate: f32, source: &mut dyn FnMut() -> Option<f32>) -> Option<()> {
+        let nominal = self.position as usize;
+        let needed = nominal + SEEK_TOLERANCE + GRAIN_FRAMES;
+        while self.origin + self.input.len() < needed {
+            self.input.push_back(read_frame(source)?);
+        }
+
+        let start = match self.continuation {
+            Some(continuation) => (nominal.saturating_sub(SEEK_TOLERANCE).max(self.origin)
+                ..=nominal + SEEK_TOLERANCE)
+                .map(|candidate| (self.correlation(candidate, continuation), candidate))
+                .max_by(|a, b| a.0.total_cmp(&b.0))
+                .map_or(nominal, |(_, candidate)| candidate),
+            None => nominal
+        };
+
+        for i in 0..HOP_FRAMES {
+            let head = self.frame(start + i).map(|s| s * hann(i));
+            let tail = self.tail[i];
+            self.output
+                .push_back(std::array::from_fn(|ch| tail[ch] + head[ch]));
+        }
+        for i in 0..HOP_FRAMES {
+            self.tail[i] = self
+                .frame(start + HOP_FRAMES + i)
+                .map(|s| s * hann(HOP_FRAMES + i));
+        }
+
+        self.continuation = Some(start + HOP_FRAMES);
+        self.position += HOP_FRAMES as f64 * rate as f64;
+
+        // Keep only what the next search can still reach
+        let keep_from = (self.position as usize)
+            .saturating_sub(SEEK_TOLERANCE)
+            .min(start + HOP_FRAMES);
+        while self.origin < keep_from {
+            self.input.pop_front();
+            self.origin += 1;
+        }
+        Some(())
+    }
+
+    /// Everything read from the source and not played yet, unstretched. The
+    /// pending tail is the fade-out of the source where the last grain
+    /// continues, so playing the source from there completes it seamlessly.
+    fn flush(&mut self) -> Vec<Frame> {
+        let from = self.continuation.unwrap_or(self.origin);
+        let mut unplayed: Vec<Frame> = self.output.drain(..).collect();
+        unplayed.extend((from..self.origin + self.input.len()).map(|index| self.frame(index)));
+        *self = Self::new();
+        unplayed
+    }
+}
+
+enum Mode {
+    Varispeed(Varispeed),
+    Stretch(Box<Stretch>)
+}
+
+impl Mode {
+    fn new(preserve_pitch: bool) -> Self {
+        if preserve_pitch {
+            Mode::Stretch(Box::new(Stretch::new()))
+        } else {
+            Mode::Varispeed(Varispeed::new())
+        }
+    }
+
+    fn flush(&mut self) -> Vec<Frame> {
+        match self {
+            Mode::Varispeed(varispeed) => varispeed.flush(),
+            Mode::Stretch(stretch) => stretch.flush()
+        }
+    }
+}
+
+pub struct RateProcessor {
+    rate: f32,
+    preserve_pitch: bool,
+    mode: Mode,
+    /// Second half of the last frame, served on the next call.
+    pending: Option<f32>,
+    /// Source frames a mode had read but not played when it was flushed,
+    /// played as they are before anything else.
+    unplayed: VecDeque<Frame>
+}
+
+impl RateProcessor {
+    pub fn new() -> Self {
+        Self {
+            rate: 1.0,
+            preserve_pitch: false,
+            mode: Mode::Varispeed(Varispeed::new()),
+            pending: None,
+            unplayed: VecDeque::new()
+        }
+    }
+
+    pub fn rate(&self) -> f32 {
+        self.rate
+    }
+
+    pub fn preserve_pitch(&self) -> bool {
+        self.preserve_pitch
+    }
+
+    /// At normal speed the processor is skipped and the deck reads its buffers
+    /// directly, sample for sample, once what it had read is played.
+    pub fn is_bypassed(&self) -> bool {
+        self.rate == 1.0 && self.pending.is_none() && self.unplayed.is_empty()
+    }
+
+    /// Changes the rate without losing audio: a new ratio applies to what the
+    /// mode already buffered, and a new mode (or normal speed) takes over
+    /// after the old one's buffers are played.
+    pub fn set(&mut self, rate: f32, preserve_pitch: bool) {
+        let rate = rate.clamp(MIN_RATE, MAX_RATE);
+        if preserve_pitch != self.preserve_pitch || rate == 1.0 {
+            self.unplayed.extend(self.mode.flush());
+            self.mode = Mode::new(preserve_pitch);
+        }
+        self.rate = rate;
+        self.preserve_pitch = preserve_pitch;
+    }
+
+    /// Drops anything buffered from the source, e.g. after a seek.
+    pub fn reset(&mut self) {
+        self.pending = None;
+        self.unplayed.clear();
+        self.mode = Mode::new(self.preserve_pitch);
+    }
+
+    /// Produces the next interleaved output sample, or None once the source
+    /// has run out.
+    pub fn next_sample(&mut self, source: &mut dyn FnMut() -> Option<f32>) -> Option<f32> {
+        if let Some(sample) = self.pending.take() {
+            return Some(sample);
+        }
+        let frame = match self.unplayed.pop_front() {
+            Some(frame) => frame,
+            None if self.rate == 1.0 => read_frame(source)?,
+            None => {
+                let frame = match &mut self.mode {
+                    Mode::Varispeed(varispeed) => varispeed.next_frame(self.rate, source),
+                    Mode::Stretch(stretch) => stretch.next_frame(self.rate, source)
+                };
+                match frame {
+                    Some(frame) => frame,
+                    // The source ran out: what the mode still holds is the
+                    // end of the audio, not something to throw away
+                    None => {
+                        self.unplayed.extend(self.mode.flush());
+                        self.unplayed.pop_front()?
+                    }
+                }
+            }
+        };
+        self.pending = Some(frame[1]);
+        Some(frame[0])
+    }
+}
+
+impl Default for RateProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `seconds` of a ramp source through the processor and returns how
+    /// many output samples it produced before the source ran out.
+    fn output_length(rate: f32, preserve_pitch: bool, seconds: usize) -> usize {
+        let mut processor = RateProcessor::new();
+        processor.set(rate, preserve_pitch);
+        let total = SAMPLE_RATE * CHANNELS * seconds;
+        let mut read = 0;
+        let mut source = || {
+            if read == total {
+                return None;
+            }
+            read += 1;
+            Some((read as f32 * 0.001).sin())
+        };
+        let mut produced = 0;
+        while processor.next_sample(&mut source).is_some() {
+            produced += 1;
+        }
+        produced
+    }
+
+    #[test]
+    fn varispeed_changes_the_duration_by_the_rate() {
+        let expected = SAMPLE_RATE * CHANNELS * 2;
+        let fast = output_length(2.0, false, 4);
+        assert!(fast.abs_diff(expected) < 10, "{}", fast);
+        let slow = output_length(0.5, false, 1);
+        assert!(slow.abs_diff(expected) < 10, "{}", slow);
+    }
+
+    #[test]
+    fn time_stretch_changes_the_duration_by_the_rate() {
+        let expected = SAMPLE_RATE * CHANNELS * 2;
+        let produced = output_length(1.5, true, 3);
+        // Within a couple of grains of the exact length
+        assert!(produced.abs_diff(expected) < GRAIN_FRAMES * CHANNELS * 3, "{}", produced);
+    }
+
+    #[test]
+    fn varispeed_interpolates_between_frames() {
+        let mut processor = RateProcessor::new();
+        processor.set(0.5, false);
+        let mut samples = [0.0, 0.0, 1.0, 1.0].into_iter();
+        let mut source = || samples.next();
+        assert_eq!(processor.next_sample(&mut source), Some(0.0));
+        assert_eq!(processor.next_sample(&mut source), Some(0.0));
+        assert_eq!(processor.next_sample(&mut source), Some(0.5));
+    }
+
+    #[test]
+    fn changing_the_rate_does_not_skip_audio() {
+        let mut processor = RateProcessor::new();
+        processor.set(0.5, false);
+        // Both channels carry the frame index
+        let mut read = 0;
+        let mut source = || {
+            read += 1;
+            Some(((read - 1) / CHANNELS) as f32)
+        };
+        let mut last = 0.0;
+        for rate in [0.5, 0.8, 1.0] {
+            processor.set(rate, false);
+            for _ in 0..200 {
+                let sample = processor.next_sample(&mut source).unwrap();
+                assert!(sample - last <= 1.0, "jumped from {} to {}", last, sample);
+                last = sample;
+            }
+        }
+        assert!(processor.is_bypassed());
+    }
+
+    #[test]
+    fn time_stretch_plays_its_tail_at_the_end() {
+        let total = SAMPLE_RATE * CHANNELS;
+        let mut processor = RateProcessor::new();
+        processor.set(1.5, true);
+        let mut read = 0;
+        let mut source = || {
+            if read == total {
+                return None;
+            }
+            read += 1;
+            Some(read as f32)
+        };
+        let mut last = None;
+        while let Some(sample) = processor.next_sample(&mut source) {
+            last = Some(sample);
+        }
+        assert_eq!(last, Some(total as f32));
+    }
+}
@@ -0,0 +1,135 @@
+//! Copy of a deck's track kept for restart, seek and loop without downloading
+//! it again.
+//!
+//! Samples are stored as i16: half the memory of the decoder's f32, and
+//! lossless for ffmpeg's s16 output. The copy is bounded: a track longer than
+//! the cap (a three-hour mix, a livestream) gives up its copy instead of the
+//! process's memory, and the deck goes on playing from the stream alone.
+
+use crate::config::{get_replay_max_minutes, CHANNELS, SAMPLE_RATE};
+
+pub struct ReplayBuffer {
+    samples: Vec<i16>,
+    /// Every sample of the track received so far, stored or not: positions
+    /// and the duration are counted on it.
+    received: usize,
+    max_samples: usize,
+    dropped: bool
+}
+
+impl ReplayBuffer {
+    pub fn new() -> Self {
+        Self::with_limit(get_replay_max_minutes() as usize * 60 * SAMPLE_RATE * CHANNELS)
+    }
+
+    pub fn with_limit(max_samples: usize) -> Self {
+        Self {
+            samples: Vec::new(),
+            received: 0,
+            max_samples,
+            dropped: false
+        }
+    }
+
+    /// Forgets the track, ready for the next one.
+    pub fn clear(&mut self) {
+        self.samples = Vec::new();
+        self.received = 0;
+        self.dropped = false;
+    }
+
+    /// Whether `extra` more samples still fit under the cap.
+    pub fn fits(&self, extra: usize) -> bool {
+        !self.dropped && self.received + extra <= self.max_samples
+    }
+
+    /// Appends a chunk of the track. Returns true when this chunk took the
+    /// track past the cap, i.e. when replay has just become unavailable.
+    pub fn extend(&mut self, chunk: &[f32]) -> bool {
+        let fits = self.fits(chunk.len());
+        self.received += chunk.len();
+        if self.dropped {
+            return false;
+        }
+        if !fits {
+            self.dropped = true;
+            self.samples = Vec::new();
+            return true;
+        }
+        self.samples.extend(chunk.iter().map(|&sample| {
+            (sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16
+        }));
+        false
+    }
+
+    /// Stops keeping the copy (a live stream). Returns true when there was
+    /// one to give up.
+    pub fn stop_storing(&mut self) -> bool {
+        if self.dropped {
+            return false;
+        }
+        self.dropped = true;
+        self.samples = Vec::new();
+        true
+    }
+
+    /// Samples of the track received so far.
+    pub fn len(&self) -> usize {
+        self.received
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.received == 0
+    }
+
+    /// False once the track outgrew the cap.
+    pub fn is_available(&self) -> bool {
+        !self.dropped
+    }
+
+    pub fn get(&self, offset: usize) -> Option<f32> {
+        self.samples
+            .get(offset)
+            .map(|&sample| sample as f32 / 32768.0)
+    }
+
+    /// The stored samples from `offset` on.
+    pub fn tail(&self, offset: usize) -> impl Iterator<Item = f32> + '_ {
+        self.samples
+            .iter()
+            .skip(offset)
+            .map(|&sample| sample as f32 / 32768.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_read_back_at_sixteen_bit_precision() {
+        let mut replay = ReplayBuffer::with_limit(16);
+        assert!(!replay.extend(&[0.5, -1.0, 0.1]));
+        assert_eq!(replay.get(0), Some(0.5));
+        assert_eq!(replay.get(1), Some(-1.0));
+        assert!((replay.get(2).unwrap() - 0.1).abs() < 1.0 / 32768.0);
+        assert_eq!(replay.get(3), None);
+    }
+
+    #[test]
+    fn passing_the_cap_drops_the_copy_but_keeps_counting() {
+        let mut replay = ReplayBuffer::with_limit(4);
+        assert!(!replay.extend(&[0.1; 4]));
+        assert!(replay.extend(&[0.1; 2]));
+        assert!(!replay.is_available());
+        assert_eq!(replay.get(0), None);
+
+        // Reported once; the length still follows the stream
+        assert!(!replay.extend(&[0.1; 2]));
+        assert_eq!(replay.len(), 8);
+
+        replay.clear();
+        assert!(replay.is_available());
+        assert!(replay.is_empty());
+    }
+}
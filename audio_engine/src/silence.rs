@@ -0,0 +1,193 @@
+//! Silence trimming: the digital silence at the start and at the end of a
+//! track is cut while its download streams in, so a gapless switch really has
+//! no gap and a crossfade does not fade into seconds of nothing.
+//!
+//! The start is simple: quiet frames are dropped until the first one with
+//! sound. The end is only known once the track is over, so quiet frames are
+//! held back instead and let through as soon as sound comes back (a pause
+//! mid-song); whatever is still held when the download ends was the tail.
+//! Holding audio back delays it for the deck, so the download decides how
+//! much may be held at any moment (see `Download::silence_hold_limit`). Both
+//! ends are capped: a track quiet for longer than the cap loses only the cap.
+
+use std::collections::VecDeque;
+
+use crate::config::{get_silence_max_trim_ms, get_silence_threshold_db, CHANNELS, SAMPLE_RATE};
+
+pub struct SilenceTrimmer {
+    /// Amplitude a frame must exceed on some channel to count as sound.
+    threshold: f32,
+    /// Most samples trimmed at each end.
+    max_samples: usize,
+    /// The frame being put together: samples arrive one at a time.
+    frame: Vec<f32>,
+    /// No sound yet: the frames held are the leading silence.
+    leading: bool,
+    /// Quiet frames not let through yet.
+    held: VecDeque<f32>,
+    /// The track had sound somewhere.
+    heard: bool
+}
+
+impl SilenceTrimmer {
+    /// None when trimming is turned off.
+    pub fn from_config() -> Option<Self> {
+        let threshold_db = get_silence_threshold_db()?;
+        let max_ms = get_silence_max_trim_ms();
+        (max_ms > 0).then(|| Self::new(threshold_db, max_ms))
+    }
+
+    pub fn new(threshold_db: f32, max_ms: u64) -> Self {
+        Self {
+            threshold: 10f32.powf(threshold_db / 20.0),
+            max_samples: max_ms as usize * SAMPLE_RATE / 1000 * CHANNELS,
+            frame: Vec::with_capacity(CHANNELS),
+            leading: true,
+            held: VecDeque::new(),
+            heard: false
+        }
+    }
+
+    /// Most samples trimmed at each end.
+    pub fn cap(&self) -> usize {
+        self.max_samples
+    }
+
+    /// Takes the next sample of the track and appends to `out` what is sure
+    /// to be kept, or may not be held back any longer: past the leading
+    /// silence, at most `hold_limit` samples are held. Returns the samples
+    /// trimmed from the start, on the frame that ended the leading silence.
+    pub fn push(&mut self, sample: f32, out: &mut Vec<f32>, hold_limit: usize) -> Option<usize> {
+        self.frame.push(sample);
+        if self.frame.len() < CHANNELS {
+            return None;
+        }
+
+        let mut leading_trimmed = None;
+        if self.frame.iter().all(|sample| sample.abs() <= self.threshold) {
+            if self.leading && self.held.len() + CHANNELS > self.max_samples {
+                // Quiet for longer than the cap: the cap goes, the rest plays
+                leading_trimmed = Some(self.held.len());
+                self.held.clear();
+                self.leading = false;
+            }
+            self.held.extend(self.frame.drain(..));
+            if !self.leading {
+                // The oldest frames go first, whole
+                let keep = self.max_samples.min(hold_limit) / CHANNELS * CHANNELS;
+                let excess = self.held.len().saturating_sub(keep);
+                out.extend(self.held.drain(..excess));
+            }
+        } else {
+            if self.leading {
+                leading_trimmed = Some(self.held.len());
+                self.held.clear();
+                self.leading = false;
+            } else {
+                out.extend(self.held.drain(..));
+            }
+            self.heard = true;
+            out.append(&mut self.frame);
+        }
+        leading_trimmed.filter(|&trimmed| trimmed > 0)
+    }
+
+    /// The track is over: the silence still held was its tail and is
+    /// dropped. Returns how many samples that was. A track without a single
+    /// frame of sound is let through whole instead, or the deck would be left
+    /// with nothing to play.
+    pub fn finish(&mut self, out: &mut Vec<f32>) -> usize {
+        self.frame.clear();
+        if !self.heard {
+            out.extend(self.held.drain(..));
+            return 0;
+        }
+        let trimmed = self.held.len();
+        self.held.clear();
+        trimmed
+    }
+
+    /// Lets through everything held and stops trimming, for a stream that
+    /// turned out to be live.
+    pub fn release(self, out: &mut Vec<f32>) {
+        out.extend(self.held);
+        out.extend(self.frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pushes stereo `frames` through, then ends the track. Returns what came
+    /// out, the leading and the trailing samples trimmed.
+    fn trim(trimmer: &mut SilenceTrimmer, frames: &[f32]) -> (Vec<f32>, usize, usize) {
+        let mut out = Vec::new();
+        let mut leading = 0;
+        for &frame in frames {
+            for sample in [frame, frame] {
+                leading += trimmer.push(sample, &mut out, usize::MAX).unwrap_or(0);
+            }
+        }
+        let trailing = trimmer.finish(&mut out);
+        (out, leading, trailing)
+    }
+
+    /// A trimmer with a cap of `max_frames` stereo frames.
+    fn trimmer(max_frames: usize) -> SilenceTrimmer {
+        let mut trimmer = SilenceTrimmer::new(-60.0, 1000);
+        trimmer.max_samples = max_frames * CHANNELS;
+        trimmer
+    }
+
+    #[test]
+    fn both_ends_are_trimmed_and_pauses_kept() {
+        let track = [0.0, 0.0, 0.5, 0.0, 0.0, 0.5, 0.0005, 0.0, 0.0];
+        let (out, leading, trailing) = trim(&mut trimmer(10), &track);
+        assert_eq!(out, vec![0.5, 0.5, 0.0, 0.0, 0.0, 0.0, 0.5, 0.5]);
+        assert_eq!(leading, 4);
+        assert_eq!(trailing, 6);
+    }
+
+    #[test]
+    fn only_the_cap_is_trimmed() {
+        let track = [0.0, 0.0, 0.0, 0.0, 0.5, 0.0, 0.0, 0.0];
+        let (out, leading, trailing) = trim(&mut trimmer(2), &track);
+        assert_eq!(out, vec![0.0, 0.0, 0.0, 0.0, 0.5, 0.5, 0.0, 0.0]);
+        assert_eq!(leading, 4);
+        assert_eq!(trailing, 4);
+    }
+
+    #[test]
+    fn a_silent_track_is_kept_whole() {
+        let (out, leading, trailing) = trim(&mut trimmer(10), &[0.0; 3]);
+        assert_eq!(out, vec![0.0; 6]);
+        assert_eq!((leading, trailing), (0, 0));
+    }
+
+    #[test]
+    fn sound_on_one_channel_is_sound() {
+        let mut trimmer = trimmer(10);
+        let mut out = Vec::new();
+        for sample in [0.0, 0.0, 0.0, 0.5] {
+            trimmer.push(sample, &mut out, usize::MAX);
+        }
+        // The quiet left sample belongs to the frame with sound
+        assert_eq!(out, vec![0.0, 0.5]);
+    }
+
+    #[test]
+    fn pauses_are_held_only_as_far_as_allowed() {
+        let mut trimmer = trimmer(10);
+        let mut out = Vec::new();
+        for sample in [0.5, 0.5, 0.0, 0.0, 0.0, 0.0] {
+            trimmer.push(sample, &mut out, CHANNELS);
+        }
+        // One quiet frame may wait, the one before it may not
+        assert_eq!(out, vec![0.5, 0.5, 0.0, 0.0]);
+        trimmer.push(0.0, &mut out, 0);
+        trimmer.push(0.0, &mut out, 0);
+        assert_eq!(out.len(), 8);
+        assert_eq!(trimmer.finish(&mut out), 0);
+    }
+}
@@ -0,0 +1,708 @@
+//! Where a deck's audio comes from, ahead of the decoder.
+//!
+//! Every source ends up as an input ffmpeg can decode: the stdout of a yt-dlp
+//! process for streaming sites, or a location ffmpeg opens itself for local
+//! files and direct media links, or a byte stream read in-process for radio
+//! (`icy.rs`). The source is picked from the URL in `source_for`, so Node.js
+//! keeps sending plain URLs.
+
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdout, Command as ProcessCommand, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{
+    default_ytdlp_cookie_browser, default_ytdlp_proxy_url, env_opt, get_base_path,
+    use_native_decoder
+};
+use crate::failure::{classify_ytdlp, weight};
+use crate::icy::IcySource;
+use crate::protocol::{send_log, Chapter, FailureCode, TrackInfo};
+
+/// Extensions of links that are fed straight to ffmpeg instead of yt-dlp.
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "mp3", "ogg", "oga", "opus", "flac", "wav", "m4a", "aac", "webm", "mka", "mp4"
+];
+
+/// yt-dlp stderr lines kept for the report of a download that produced nothing.
+const STDERR_HISTORY: usize = 40;
+
+/// Where ffmpeg reads the encoded audio from.
+pub enum DecoderInput {
+    /// Bytes written by an upstream process to its stdout.
+    Pipe(ChildStdout),
+    /// A path or URL ffmpeg opens itself.
+    Location(String),
+    /// Bytes read by the engine itself and fed to the decoder.
+    Stream(Box<dyn Read + Send + Sync>)
+}
+
+/// A source that has started producing audio.
+pub struct OpenedSource {
+    pub input: DecoderInput,
+    /// ffmpeg options that apply to this input, placed before `-i`.
+    pub input_args: Vec<String>,
+    /// Process feeding `input`, killed together with the decoder.
+    pub upstream: Option<Child>
+}
+
+pub trait Source: Send {
+    /// Short name used in logs.
+    fn kind(&self) -> &'static str;
+
+    /// Starts fetching the audio. `cancel` is raised when the deck no longer
+    /// wants it.
+    fn open(&mut self, cancel: &Arc<AtomicBool>) -> Result<OpenedSource>;
+
+    /// Whether finished downloads are worth keeping in the track cache. Local
+    /// files are already on disk.
+    fn cacheable(&self) -> bool {
+        true
+    }
+
+    /// Whether the source found out, once streaming, that it is live.
+    fn detected_live(&self) -> bool {
+        false
+    }
+
+    /// Length of the track, once the source knows it.
+    fn duration_ms(&self) -> Option<u64> {
+        None
+    }
+
+    /// Title, uploader and the like, once the source knows them.
+    fn metadata(&self) -> Option<TrackInfo> {
+        None
+    }
+
+    /// Makes the next `open` start `position_ms` into the track, to pick up
+    /// a stream that broke mid-song. Returns false if the source can only
+    /// start from the top.
+    fn resume_from(&mut self, position_ms: u64) -> bool {
+        position_ms == 0
+    }
+
+    /// Switches to the next way of fetching the audio after a failed attempt.
+    /// Returns its description, or None when there is nothing else to try.
+    fn next_strategy(&mut self) -> Option<&'static str> {
+        None
+    }
+
+    /// Why the source failed, if it could tell.
+    fn failure(&self) -> Option<FailureCode> {
+        None
+    }
+
+    /// What to check when the source ended without a single sample.
+    fn failure_hints(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// Picks the source for a URL sent by Node.js: `file://` paths are read from
+/// disk, links to a media file are decoded directly, a plain-http link loaded
+/// as live is read as a radio, and everything else (YouTube and the other
+/// sites yt-dlp knows) goes through yt-dlp. `deck` is the deck the source
+/// reports its metadata for.
+pub fn source_for(url: &str, live: bool, deck: &'static str) -> Box<dyn Source> {
+    if let Some(path) = url.strip_prefix("file://") {
+        return Box::new(FileSource {
+            path: percent_decode(path)
+        });
+    }
+    if live && url.to_ascii_lowercase().starts_with("http://") {
+        return Box::new(IcySource::new(url, deck));
+    }
+    if is_direct_media_url(url) {
+        return Box::new(HttpSource {
+            url: url.to_string(),
+            start_ms: 0
+        });
+    }
+    Box::new(YtDlpSource::new(url))
+}
+
+fn is_direct_media_url(url: &str) -> bool {
+    let lower = url.to_ascii_lowercase();
+    let Some(rest) = lower
+        .strip_prefix("https://")
+        .or_else(|| lower.strip_prefix("http://"))
+    else {
+        return false;
+    };
+    // Signed CDN links (Discord attachments) carry their token in the query
+    let path = rest.split(['?', '#']).next().unwrap_or("");
+    let Some((_, file)) = path.rsplit_once('/') else {
+        return false;
+    };
+    file.rsplit_once('.')
+        .is_some_and(|(_, ext)| MEDIA_EXTENSIONS.contains(&ext))
+}
+
+/// Decodes the `%XX` escapes of a `file://` URL; malformed escapes are kept
+/// as they are.
+fn percent_decode(raw: &str) -> String {
+    let bytes = raw.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| raw.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// yt-dlp format choice. The in-process decoder reads AAC but not Opus, so it
+/// gets the M4A audio YouTube offers next to its default Opus/WebM; ffmpeg
+/// only decodes what is left without one.
+fn format_selector() -> &'static str {
+    if use_native_decoder() {
+        "ba[ext=m4a]/ba[acodec^=mp4a]/ba/b/bestaudio/best"
+    } else {
+        "ba/b/bestaudio/best"
+    }
+}
+
+/// A position as ffmpeg and yt-dlp take it: seconds, to the millisecond.
+fn seconds_arg(position_ms: u64) -> String {
+    format!("{}.{:03}", position_ms / 1000, position_ms % 1000)
+}
+
+/// Proxy yt-dlp goes through unless told otherwise.
+fn configured_proxy() -> Option<String> {
+    env_opt("YTDLP_PROXY_URL").or_else(|| {
+        if env::var("YTDLP_PROXY_URL").is_ok() {
+            None
+        } else {
+            Some(default_ytdlp_proxy_url())
+        }
+    })
+}
+
+/// An audio file on the host, e.g. an attachment Node.js saved to disk.
+pub struct FileSource {
+    path: String
+}
+
+impl Source for FileSource {
+    fn kind(&self) -> &'static str {
+        "file"
+    }
+
+    fn open(&mut self, _cancel: &Arc<AtomicBool>) -> Result<OpenedSource> {
+        if !std::path::Path::new(&self.path).is_file() {
+            return Err(anyhow!("File not found: {}", self.path));
+        }
+        Ok(OpenedSource {
+            input: DecoderInput::Location(self.path.clone()),
+            input_args: Vec::new(),
+            upstream: None
+        })
+    }
+
+    fn cacheable(&self) -> bool {
+        false
+    }
+
+    fn failure_hints(&self) -> Vec<String> {
+        vec![format!("Check: {} is an audio file ffmpeg can decode", self.path)]
+    }
+}
+
+/// A plain link to a media file, downloaded by ffmpeg itself.
+pub struct HttpSource {
+    url: String,
+    /// Where ffmpeg starts reading, with a range request.
+    start_ms: u64
+}
+
+impl Source for HttpSource {
+    fn kind(&self) -> &'static str {
+        "http"
+    }
+
+    fn open(&mut self, _cancel: &Arc<AtomicBool>) -> Result<OpenedSource> {
+        let input_args = [
+            "-reconnect", "1",
+            "-reconnect_streamed", "1",
+            "-reconnect_delay_max", "5",
+            "-rw_timeout", "30000000"
+        ];
+        let mut input_args: Vec<String> = input_args.iter().map(|arg| arg.to_string()).collect();
+        if self.start_ms > 0 {
+            input_args.push("-ss".to_string());
+            input_args.push(seconds_arg(self.start_ms));
+        }
+        Ok(OpenedSource {
+            input: DecoderInput::Location(self.url.clone()),
+            input_args,
+            upstream: None
+        })
+    }
+
+    fn resume_from(&mut self, position_ms: u64) -> bool {
+        self.start_ms = position_ms;
+        true
+    }
+
+    fn failure_hints(&self) -> Vec<String> {
+        vec!["Check: the link is reachable and points to an audio file".to_string()]
+    }
+}
+
+/// How yt-dlp is asked for the audio. YouTube breaks one client or format
+/// at a time, and a proxy can be the problem rather than the cure, so a
+/// failed download is retried changing one of them.
+#[derive(Clone, Copy, PartialEq)]
+enum Strategy {
+    Default,
+    OtherClients,
+    NoProxy,
+    MuxedFormat
+}
+
+impl Strategy {
+    const FALLBACKS: [Strategy; 3] = [Self::OtherClients, Self::NoProxy, Self::MuxedFormat];
+
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::OtherClients => "player_client=tv,web_safari,android_vr",
+            Self::NoProxy => "no proxy",
+            Self::MuxedFormat => "format 18 (muxed mp4)"
+        }
+    }
+}
+
+/// Anything yt-dlp can stream, through the bot's own yt-dlp binary.
+pub struct YtDlpSource {
+    url: String,
+    /// Proxy in use, reported when the download fails.
+    proxy_url: Option<String>,
+    /// Latest stderr lines of yt-dlp.
+    stderr_lines: Arc<Mutex<Vec<String>>>,
+    /// Where yt-dlp writes what it knows of the video (`is_live`, duration,
+    /// title, …): its stdout carries the audio.
+    info_file: PathBuf,
+    spawn_failed: bool,
+    strategy: Strategy,
+    /// Where the download starts: yt-dlp cuts the stream itself (through
+    /// ffmpeg) when this is not the top.
+    start_ms: u64
+}
+
+impl YtDlpSource {
+    pub fn new(url: &str) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or(0);
+        Self {
+            url: url.to_string(),
+            proxy_url: None,
+            stderr_lines: Arc::new(Mutex::new(Vec::new())),
+            info_file: env::temp_dir().join(format!(
+                "mixer-ytdlp-{}-{}.txt",
+                std::process::id(),
+                nanos
+            )),
+            spawn_failed: false,
+            strategy: Strategy::Default,
+            start_ms: 0
+        }
+    }
+
+    /// A `key=value` line of the info file, if yt-dlp printed it already.
+    fn info_field(&self, key: &str) -> Option<String> {
+        let info = fs::read_to_string(&self.info_file).ok()?;
+        info.lines().find_map(|line| {
+            let (name, value) = line.split_once('=')?;
+            (name == key).then(|| value.trim().to_string())
+        })
+    }
+
+    /// An info file line printed as JSON (`%(field)j`); None when yt-dlp had
+    /// no value for it.
+    fn info_json<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let raw = self.info_field(key)?;
+        serde_json::from_str::<Option<T>>(&raw).ok().flatten()
+    }
+}
+
+/// An entry of yt-dlp's `chapters`, times in seconds.
+#[derive(Deserialize)]
+struct YtDlpChapter {
+    #[serde(default)]
+    title: String,
+    start_time: f64,
+    end_time: f64
+}
+
+impl Drop for YtDlpSource {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.info_file);
+    }
+}
+
+impl Source for YtDlpSource {
+    fn kind(&self) -> &'static str {
+        "yt-dlp"
+    }
+
+    fn open(&mut self, cancel: &Arc<AtomicBool>) -> Result<OpenedSource> {
+        // Uses yt-dlp binary from bot directory
+        let yt_dlp_binary = format!("{}/bin/yt-dlp", get_base_path());
+
+        let mut yt_dlp_cmd = ProcessCommand::new(yt_dlp_binary);
+        yt_dlp_cmd
+            .arg("--no-update")
+            .arg("-f").arg(match self.strategy {
+                // Audio-only formats are the ones YouTube throttles first
+                Strategy::MuxedFormat => "18/b*/ba*",
+                _ => format_selector()
+            })
+            .arg("--ignore-no-formats-error")
+            .arg("--force-ipv4")
+            .arg("--user-agent").arg("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36")
+            .arg("-o").arg("-")
+            .arg("-q")
+            .arg("--no-warnings")
+            .arg("--no-cache-dir")
+            .arg("--no-playlist")
+            .arg("--socket-timeout").arg("30")
+            .arg("--retries").arg("5")
+            .arg("--fragment-retries").arg("5")
+            .arg("--concurrent-fragments").arg("1")
+            .arg("--no-simulate")
+            .arg("--print-to-file").arg("is_live=%(is_live)s").arg(&self.info_file)
+            .arg("--print-to-file").arg("duration=%(duration)s").arg(&self.info_file)
+            .arg("--print-to-file").arg("title=%(title)j").arg(&self.info_file)
+            .arg("--print-to-file").arg("uploader=%(uploader,channel)j").arg(&self.info_file)
+            .arg("--print-to-file").arg("thumbnail=%(thumbnail)j").arg(&self.info_file)
+            .arg("--print-to-file").arg("chapters=%(chapters)j").arg(&self.info_file)
+            .arg("--js-runtimes").arg("node")
+            .arg("--impersonate").arg("chrome");
+
+        yt_dlp_cmd.env("PATH", env::var("PATH").unwrap_or_default());
+        // Appended to by every run: a reconnection starts from a clean file
+        let _ = fs::remove_file(&self.info_file);
+        // So that `failure` reads this attempt's errors only
+        if let Ok(mut lines) = self.stderr_lines.lock() {
+            lines.clear();
+        }
+
+        // Proxy: default socks5h://127.0.0.1:5040; YTDLP_PROXY_URL=none to disable
+        self.proxy_url = configured_proxy().filter(|_| self.strategy != Strategy::NoProxy);
+        if let Some(ref proxy) = self.proxy_url {
+            send_log("info", &format!("yt-dlp proxy active: {}", proxy));
+            yt_dlp_cmd.arg("--proxy").arg(proxy);
+        } else if self.strategy == Strategy::NoProxy {
+            send_log("info", "yt-dlp proxy skipped for this attempt");
+        } else {
+            send_log("info", "yt-dlp proxy disabled (YTDLP_PROXY_URL=none)");
+        }
+
+        // Cookie browser: disabled by default on Linux; YTDLP_COOKIE_BROWSER=chromium to force them
+        let cookie_browser = env_opt("YTDLP_COOKIE_BROWSER").or_else(|| {
+            if env::var("YTDLP_COOKIE_BROWSER").is_ok() {
+                None
+            } else {
+                default_ytdlp_cookie_browser()
+            }
+        });
+        if let Some(ref browser) = cookie_browser {
+            yt_dlp_cmd.arg("--cookies-from-browser").arg(browser);
+        } else {
+            send_log(
+                "info",
+                "yt-dlp cookies-from-browser disabled (YTDLP_COOKIE_BROWSER=none)"
+            );
+        }
+        yt_dlp_cmd.arg("--mark-watched");
+
+        // Fallback to cookies file if exists
+        let cookies_file = format!("{}/youtube-cookies.txt", get_base_path());
+        if std::path::Path::new(&cookies_file).exists() {
+            yt_dlp_cmd.arg("--cookies").arg(&cookies_file);
+        }
+
+        // Extractor args configurable via env
+        let extractor_args = match self.strategy {
+            Strategy::OtherClients => "youtube:player_client=tv,web_safari,android_vr".to_string(),
+            _ => env::var("YTDLP_EXTRACTOR_ARGS").unwrap_or_else(|_| {
+                "youtube:player_client=web,android,ios,mweb".to_string()
+            })
+        };
+        send_log(
+            "info",
+            &format!("yt-dlp extractor-args active: {}", extractor_args)
+        );
+        yt_dlp_cmd.arg("--extractor-args").arg(&extractor_args);
+
+        if self.start_ms > 0 {
+            yt_dlp_cmd
+                .arg("--download-sections")
+                .arg(format!("*{}-inf", seconds_arg(self.start_ms)));
+        }
+
+        let mut yt_dlp_child = yt_dlp_cmd
+            .arg(&self.url)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                self.spawn_failed = true;
+                anyhow!("Failed to spawn yt-dlp: {}", e)
+            })?;
+
+        let yt_dlp_stdout = yt_dlp_child
+            .stdout
+            .take()
+            .ok_or(anyhow!("Failed to open yt-dlp stdout"))?;
+        let yt_dlp_stderr = yt_dlp_child
+            .stderr
+            .take()
+            .ok_or(anyhow!("Failed to open yt-dlp stderr"))?;
+
+        let stderr_lines_cap = self.stderr_lines.clone();
+        let cancel_stderr_yt = cancel.clone();
+        thread::spawn(move || {
+            let reader = BufReader::new(yt_dlp_stderr);
+            for line in reader.lines() {
+                if cancel_stderr_yt.load(Ordering::Relaxed) {
+                    break;
+                }
+                if let Ok(l) = line {
+                    let trimmed = l.trim();
+                    if trimmed.is_empty() {
+                        continue;
+                    }
+                    if let Ok(mut buf) = stderr_lines_cap.lock() {
+                        if buf.len() >= STDERR_HISTORY {
+                            buf.remove(0);
+                        }
+                        buf.push(trimmed.to_string());
+                    }
+                    let lower = trimmed.to_lowercase();
+                    if lower.contains("error")
+                        || lower.contains("unable")
+                        || lower.contains("failed")
+                        || lower.contains("blocked")
+                        || lower.contains("sign in")
+                    {
+                        send_log("error", &format!("[yt-dlp] {}", trimmed));
+                    }
+                }
+            }
+        });
+
+        Ok(OpenedSource {
+            input: DecoderInput::Pipe(yt_dlp_stdout),
+            input_args: Vec::new(),
+            upstream: Some(yt_dlp_child)
+        })
+    }
+
+    /// yt-dlp prints `is_live` before it starts downloading, so it is known
+    /// by the time the first audio arrives.
+    fn detected_live(&self) -> bool {
+        self.info_field("is_live").as_deref() == Some("True")
+    }
+
+    /// "NA" for a live stream, and a fraction for some sites.
+    fn duration_ms(&self) -> Option<u64> {
+        self.info_field("duration")?
+            .parse::<f64>()
+            .ok()
+            .filter(|secs| secs.is_finite() && *secs > 0.0)
+            .map(|secs| (secs * 1000.0) as u64)
+    }
+
+    /// Printed with `is_live`: Node.js gets it without asking yt-dlp a
+    /// second time.
+    fn metadata(&self) -> Option<TrackInfo> {
+        let chapters: Vec<YtDlpChapter> = self.info_json("chapters").unwrap_or_default();
+        let info = TrackInfo {
+            title: self.info_json("title"),
+            uploader: self.info_json("uploader"),
+            duration_ms: self.duration_ms(),
+            thumbnail: self.info_json("thumbnail"),
+            chapters: chapters
+                .into_iter()
+                .map(|chapter| Chapter {
+                    title: chapter.title,
+                    start_ms: (chapter.start_time * 1000.0) as u64,
+                    end_ms: (chapter.end_time * 1000.0) as u64
+                })
+                .collect()
+        };
+        (info.title.is_some() || info.duration_ms.is_some()).then_some(info)
+    }
+
+    fn resume_from(&mut self, position_ms: u64) -> bool {
+        self.start_ms = position_ms;
+        true
+    }
+
+    fn next_strategy(&mut self) -> Option<&'static str> {
+        let has_proxy = configured_proxy().is_some();
+        let start = Strategy::FALLBACKS
+            .iter()
+            .position(|&strategy| strategy == self.strategy)
+            .map_or(0, |index| index + 1);
+        // Without a proxy there is none to turn off
+        let next = Strategy::FALLBACKS[start..]
+            .iter()
+            .copied()
+            .find(|&strategy| strategy != Strategy::NoProxy || has_proxy)?;
+        self.strategy = next;
+        Some(next.describe())
+    }
+
+    fn failure(&self) -> Option<FailureCode> {
+        if self.spawn_failed {
+            return Some(FailureCode::SpawnFailed);
+        }
+        let lines = self.stderr_lines.lock().ok()?;
+        lines
+            .iter()
+            .filter_map(|line| classify_ytdlp(line))
+            .max_by_key(|&code| weight(code))
+    }
+
+    fn failure_hints(&self) -> Vec<String> {
+        let mut hints = vec![
+            "Check: (1) yt-dlp is installed correctly".to_string(),
+            "Check: (2) the YouTube URL is valid and reachable".to_string(),
+            format!(
+                "Check: (3) SOCKS proxy/tunnel active (proxy: {})",
+                self.proxy_url.as_deref().unwrap_or("none")
+            )
+        ];
+        if let Ok(buf) = self.stderr_lines.lock() {
+            if !buf.is_empty() {
+                hints.push("Last yt-dlp stderr messages:".to_string());
+                for line in buf.iter().rev().take(8).rev() {
+                    hints.push(format!("[yt-dlp] {}", line));
+                }
+            }
+        }
+        hints
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_are_picked_from_the_url() {
+        let kind = |url: &str, live: bool| source_for(url, live, "A").kind();
+        assert_eq!(kind("file:///music/My%20Song.flac", false), "file");
+        assert_eq!(kind("https://www.youtube.com/watch?v=abc", false), "yt-dlp");
+        assert_eq!(
+            kind("https://cdn.discordapp.com/attachments/1/2/clip.MP3?ex=abc&is=def", false),
+            "http"
+        );
+        // A site page, not a file, even if the query mentions one
+        assert_eq!(kind("https://example.com/play?file=a.mp3", false), "yt-dlp");
+        // Radio: plain http only, the ICY reader speaks no TLS
+        assert_eq!(kind("http://radio.example.com:8000/stream", true), "icy");
+        assert_eq!(kind("https://www.youtube.com/watch?v=abc", true), "yt-dlp");
+    }
+
+    #[test]
+    fn file_urls_are_unescaped() {
+        assert_eq!(percent_decode("/music/My%20Song%2Bmix.flac"), "/music/My Song+mix.flac");
+        assert_eq!(percent_decode("/odd%zz%2"), "/odd%zz%2");
+    }
+
+    #[test]
+    fn missing_files_fail_to_open() {
+        let mut source = source_for("file:///definitely/not/here.wav", false, "A");
+        assert!(source.open(&Arc::new(AtomicBool::new(false))).is_err());
+    }
+
+    #[test]
+    fn fallback_strategies_run_out() {
+        let mut source = YtDlpSource::new("https://www.youtube.com/watch?v=abc");
+        let mut tried = Vec::new();
+        while let Some(strategy) = source.next_strategy() {
+            tried.push(strategy);
+        }
+        let mut expected = vec![Strategy::OtherClients.describe()];
+        if configured_proxy().is_some() {
+            expected.push(Strategy::NoProxy.describe());
+        }
+        expected.push(Strategy::MuxedFormat.describe());
+        assert_eq!(tried, expected);
+
+        // Local files have nothing else to try
+        assert_eq!(source_for("file:///a.wav", false, "A").next_strategy(), None);
+    }
+
+    #[test]
+    fn ytdlp_info_is_read_back() {
+        let source = YtDlpSource::new("https://www.youtube.com/watch?v=abc");
+        assert!(!source.detected_live());
+        assert_eq!(source.duration_ms(), None);
+
+        fs::write(&source.info_file, "is_live=False\nduration=212.5\n").unwrap();
+        assert!(!source.detected_live());
+        assert_eq!(source.duration_ms(), Some(212_500));
+
+        fs::write(&source.info_file, "is_live=True\nduration=NA\n").unwrap();
+        assert!(source.detected_live());
+        assert_eq!(source.duration_ms(), None);
+    }
+
+    #[test]
+    fn ytdlp_metadata_is_read_back() {
+        let source = YtDlpSource::new("https://www.youtube.com/watch?v=abc");
+        assert_eq!(source.metadata(), None);
+
+        let info = [
+            "is_live=False",
+            "duration=95",
+            r#"title="A \"quoted\" = title""#,
+            "uploader=null",
+            r#"thumbnail="https://i.ytimg.com/vi/abc/hq.jpg""#,
+            r#"chapters=[{"start_time": 0.0, "end_time": 40.5, "title": "Intro"}]"#
+        ];
+        fs::write(&source.info_file, info.join("\n")).unwrap();
+        let info = source.metadata().unwrap();
+        assert_eq!(info.title.as_deref(), Some(r#"A "quoted" = title"#));
+        assert_eq!(info.uploader, None);
+        assert_eq!(info.duration_ms, Some(95_000));
+        assert_eq!(info.thumbnail.as_deref(), Some("https://i.ytimg.com/vi/abc/hq.jpg"));
+        assert_eq!(
+            info.chapters,
+            vec![Chapter {
+                title: "Intro".to_string(),
+                start_ms: 0,
+                end_ms: 40_500
+            }]
+        );
+    }
+}
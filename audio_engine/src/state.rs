@@ -1,23 +1,43 @@
-//! The mixer's mutable state, plus the deck lookup every stage is built on.
+//! The mixer's mutable state, plus the deck registry every stage is built on.
 //!
-//! The engine only ever has two decks, named "A" and "B". Keeping the names as
-//! `&'static str` means a deck reference is a `Copy` value: the loop can read
-//! `state.active_deck` and immediately borrow the matching deck mutably, which
-//! is what lets the transition and event stages live in their own modules.
+//! Decks "A" and "B" always exist, which is all the original two-deck client
+//! knows about; Node.js may open more (up to `get_max_decks`) to preload
+//! several tracks ahead. Deck names are kept as `&'static str` so that a deck
+//! reference is a `Copy` value: the loop can read `state.active_deck` and
+//! immediately borrow the matching deck mutably, which is what lets the
+//! transition and event stages live in their own modules.
 
 use std::time::Instant;
 
-use crate::config::{CHANNELS, SAMPLE_RATE};
+use crate::config::{get_max_decks, CHANNELS, SAMPLE_RATE};
 use crate::deck::Deck;
+use crate::eq::Equalizer;
+use crate::gain::GainRamp;
+use crate::limiter::Limiter;
+use crate::overlay::OverlayBus;
+use crate::protocol::{
+    CrossfadeSnapshot, MixerSnapshot, MixerSummary, PendingSnapshot, StallSnapshot
+};
 
-/// Resolves a deck name coming from Node.js. Anything the engine does not know
-/// about is rejected here, so no stage further down has to guard against it.
-pub fn deck_name(raw: &str) -> Option<&'static str> {
-    match raw {
-        "A" => Some("A"),
-        "B" => Some("B"),
-        _ => None
-    }
+/// Decks that exist from startup and can never be removed.
+pub const FIXED_DECKS: [&str; 2] = ["A", "B"];
+
+/// Longest deck name accepted from Node.js.
+const MAX_DECK_NAME_LEN: usize = 16;
+
+/// Why a deck could not be opened.
+#[derive(Debug, PartialEq)]
+pub enum OpenDeckError {
+    InvalidName,
+    LimitReached(usize)
+}
+
+fn is_valid_deck_name(raw: &str) -> bool {
+    !raw.is_empty()
+        && raw.len() <= MAX_DECK_NAME_LEN
+        && raw
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
 }
 
 /// A crossfade in flight from the active deck to `target`.
@@ -63,48 +83,121 @@ pub struct Stall {
 }
 
 pub struct MixerState {
-    pub deck_a: Deck,
-    pub deck_b: Deck,
+    /// Every open deck, "A" and "B" first.
+    decks: Vec<Deck>,
+    /// Every deck name ever opened. Names are leaked once to become
+    /// `&'static str` and reused when a removed deck is opened again, so the
+    /// leak is bounded by the distinct names Node.js uses, not by loads.
+    names: Vec<&'static str>,
+    max_decks: usize,
     pub active_deck: &'static str,
+    /// Deck auto-gapless moves to when the active one ends, set by `set_next`.
+    /// Without one, "A" and "B" hand over to each other.
+    pub cued: Option<&'static str>,
     /// False while paused or after an unplayable track stopped the output.
     pub is_playing: bool,
     /// When set, a finished deck restarts from its cache instead of advancing.
     pub loop_mode: bool,
     pub crossfade: Option<Crossfade>,
     pub pending: Option<PendingTransition>,
-    pub stall: Option<Stall>
+    pub stall: Option<Stall>,
+    /// Gain of the whole output, applied after the decks are mixed.
+    pub master_volume: GainRamp,
+    /// Equalizer of the whole output, ahead of the master gain.
+    pub master_eq: Equalizer,
+    /// Clips mixed over the decks, ahead of the master equalizer.
+    pub overlay: OverlayBus,
+    /// Last stage of the output, after the master gain.
+    pub limiter: Limiter
 }
 
 impl MixerState {
     pub fn new() -> Self {
         Self {
-            deck_a: Deck::new("A"),
-            deck_b: Deck::new("B"),
+            decks: FIXED_DECKS.iter().map(|&name| Deck::new(name)).collect(),
+            names: FIXED_DECKS.to_vec(),
+            max_decks: get_max_decks(),
             active_deck: "A",
+            cued: None,
             is_playing: false,
             loop_mode: false,
             crossfade: None,
             pending: None,
-            stall: None
+            stall: None,
+            master_volume: GainRamp::default(),
+            master_eq: Equalizer::default(),
+            overlay: OverlayBus::new(),
+            limiter: Limiter::new()
         }
     }
 
-    pub fn deck(&self, name: &str) -> &Deck {
-        if name == "A" {
-            &self.deck_a
-        } else {
-            &self.deck_b
+    /// Resolves a deck name coming from Node.js. Anything the engine does not
+    /// know about is rejected here, so no stage further down has to guard
+    /// against it.
+    pub fn deck_name(&self, raw: &str) -> Option<&'static str> {
+        self.decks
+            .iter()
+            .map(|deck| deck.name())
+            .find(|&name| name == raw)
+    }
+
+    /// Resolves a deck name, opening the deck if it does not exist yet.
+    pub fn open_deck(&mut self, raw: &str) -> Result<&'static str, OpenDeckError> {
+        if let Some(name) = self.deck_name(raw) {
+            return Ok(name);
+        }
+        if !is_valid_deck_name(raw) {
+            return Err(OpenDeckError::InvalidName);
+        }
+        if self.decks.len() >= self.max_decks {
+            return Err(OpenDeckError::LimitReached(self.max_decks));
         }
+        let name = match self.names.iter().find(|&&name| name == raw) {
+            Some(&name) => name,
+            None => {
+                let name: &'static str = Box::leak(raw.to_string().into_boxed_str());
+                self.names.push(name);
+                name
+            }
+        };
+        self.decks.push(Deck::new(name));
+        Ok(name)
     }
 
-    pub fn deck_mut(&mut self, name: &str) -> &mut Deck {
-        if name == "A" {
-            &mut self.deck_a
-        } else {
-            &mut self.deck_b
+    /// Closes a deck opened by Node.js, cancelling its download. Callers make
+    /// sure nothing still refers to it.
+    pub fn remove_deck(&mut self, name: &'static str) {
+        self.decks.retain(|deck| deck.name() != name);
+        if self.cued == Some(name) {
+            self.cued = None;
         }
     }
 
+    /// Names of every open deck, "A" and "B" first.
+    pub fn deck_names(&self) -> Vec<&'static str> {
+        self.decks.iter().map(|deck| deck.name()).collect()
+    }
+
+    pub fn decks_mut(&mut self) -> impl Iterator<Item = &mut Deck> {
+        self.decks.iter_mut()
+    }
+
+    /// Panics on a name that was never opened: names are resolved through
+    /// `deck_name`/`open_deck` when they enter the engine.
+    pub fn deck(&self, name: &str) -> &Deck {
+        self.decks
+            .iter()
+            .find(|deck| deck.name() == name)
+            .expect("deck names are resolved on entry")
+    }
+
+    pub fn deck_mut(&mut self, name: &str) -> &mut Deck {
+        self.decks
+            .iter_mut()
+            .find(|deck| deck.name() == name)
+            .expect("deck names are resolved on entry")
+    }
+
     pub fn active(&self) -> &Deck {
         self.deck(self.active_deck)
     }
@@ -113,25 +206,42 @@ impl MixerState {
         self.deck_mut(self.active_deck)
     }
 
-    /// The deck that is not currently playing: the one preload writes to.
-    pub fn idle_deck(&self) -> &'static str {
-        if self.active_deck == "A" {
-            "B"
-        } else {
-            "A"
+    /// The deck auto-gapless moves to when the active one ends: the cued deck
+    /// if there is one, otherwise the other half of the A/B pair.
+    pub fn next_deck(&self) -> Option<&'static str> {
+        if let Some(cued) = self.cued.filter(|&cued| cued != self.active_deck) {
+            return Some(cued);
+        }
+        match self.active_deck {
+            "A" => Some("B"),
+            "B" => Some("A"),
+            _ => None
         }
     }
 
     /// Discards a deck entirely, which also cancels its download thread through
     /// `Deck::drop`. Used whenever a deck's audio has been consumed for good.
+    /// The deck's volume, equalizer and playback rate belong to the slot
+    /// rather than the track, so they stay.
     pub fn reset_deck(&mut self, name: &'static str) {
-        *self.deck_mut(name) = Deck::new(name);
+        let volume = std::mem::take(&mut self.deck_mut(name).volume);
+        let eq = std::mem::take(&mut self.deck_mut(name).eq);
+        let mut rate = std::mem::take(&mut self.deck_mut(name).rate);
+        rate.reset();
+        let deck = self.deck_mut(name);
+        *deck = Deck::new(name);
+        deck.volume = volume;
+        deck.eq = eq;
+        deck.rate = rate;
     }
 
     /// Makes `name` the deck being heard and restarts its played-sample count,
     /// which gates both the playback confirmation and the end-of-track check.
     pub fn switch_to(&mut self, name: &'static str) {
         self.active_deck = name;
+        if self.cued == Some(name) {
+            self.cued = None;
+        }
         self.deck_mut(name).samples_played = 0;
     }
 
@@ -147,6 +257,52 @@ impl MixerState {
         deck.receiver.is_none() && deck.has_samples()
     }
 
+    /// The state reported back with each acknowledged command.
+    pub fn summary(&self) -> MixerSummary {
+        MixerSummary {
+            active_deck: self.active_deck,
+            is_playing: self.is_playing,
+            loop_mode: self.loop_mode,
+            crossfade_to: self.crossfade.as_ref().map(|c| c.target),
+            pending_to: self.pending.as_ref().map(|p| p.target)
+        }
+    }
+
+    /// Everything reported by `get_state`.
+    pub fn snapshot(&self) -> MixerSnapshot {
+        let waited = |since: Instant| since.elapsed().as_millis() as u64;
+        MixerSnapshot {
+            active_deck: self.active_deck,
+            is_playing: self.is_playing,
+            loop_mode: self.loop_mode,
+            crossfade: self.crossfade.as_ref().map(|c| CrossfadeSnapshot {
+                target: c.target,
+                progress: 1.0 - c.left as f32 / c.total as f32,
+                duration_ms: (c.total / CHANNELS * 1000 / SAMPLE_RATE) as u64,
+                stalled_ms: c.stalled_since.map(waited)
+            }),
+            pending: self.pending.as_ref().map(|p| PendingSnapshot {
+                target: p.target,
+                is_crossfade: p.is_crossfade,
+                duration_ms: p.duration_ms,
+                waiting_ms: waited(p.since)
+            }),
+            stall: self.stall.as_ref().map(|s| StallSnapshot {
+                target: s.target,
+                waiting_ms: waited(s.since)
+            }),
+            master_volume: self.master_volume.level(),
+            cued: self.cued,
+            max_decks: self.max_decks,
+            overlay_clips: self.overlay.clip_ids(),
+            decks: self
+                .decks
+                .iter()
+                .map(|deck| (deck.name(), deck.snapshot()))
+                .collect()
+        }
+    }
+
     /// True when a deck is the target of work that playback is waiting on, so
     /// its failure leaves the output stuck rather than merely wasting a preload.
     pub fn is_awaited(&self, name: &str) -> bool {
@@ -155,3 +311,41 @@ impl MixerState {
             || self.crossfade.as_ref().is_some_and(|c| c.target == name)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extra_decks_are_opened_up_to_the_cap() {
+        let mut state = MixerState::new();
+        state.max_decks = 3;
+        assert_eq!(state.open_deck("A"), Ok("A"));
+        assert_eq!(state.open_deck("next"), Ok("next"));
+        assert_eq!(state.open_deck("later"), Err(OpenDeckError::LimitReached(3)));
+        assert_eq!(state.open_deck("bad name"), Err(OpenDeckError::InvalidName));
+        assert_eq!(state.deck_names(), vec!["A", "B", "next"]);
+    }
+
+    #[test]
+    fn reopened_decks_reuse_their_name() {
+        let mut state = MixerState::new();
+        let first = state.open_deck("C").unwrap();
+        state.remove_deck(first);
+        assert_eq!(state.deck_name("C"), None);
+        let second = state.open_deck("C").unwrap();
+        assert!(std::ptr::eq(first, second));
+    }
+
+    #[test]
+    fn cued_deck_overrides_the_ab_pair() {
+        let mut state = MixerState::new();
+        assert_eq!(state.next_deck(), Some("B"));
+        let c = state.open_deck("C").unwrap();
+        state.cued = Some(c);
+        assert_eq!(state.next_deck(), Some("C"));
+        state.switch_to(c);
+        assert_eq!(state.cued, None);
+        assert_eq!(state.next_deck(), None);
+    }
+}
@@ -4,7 +4,7 @@
 
 use std::time::{Duration, Instant};
 
-use crate::protocol::send_log;
+use crate::protocol::{emit, send_log, DeckChangeTrigger, FailureCode, OutputEvent};
 use crate::state::{Crossfade, MixerState, PendingTransition};
 
 /// How long a deferred skip/crossfade waits for its target before running anyway.
@@ -26,7 +26,7 @@ const DOWNLOAD_STUCK_SECS: u64 = 30;
 /// opposed to a preload that simply went to waste: only the former warrants
 /// warning the users and skipping the track.
 pub fn detect_failed_decks(state: &mut MixerState) {
-    for name in ["A", "B"] {
+    for name in state.deck_names() {
         let just_failed = {
             let deck = state.deck(name);
             deck.download_failed && !deck.fail_sent
@@ -41,10 +41,11 @@ pub fn detect_failed_decks(state: &mut MixerState) {
         let is_crossfade_target = state.crossfade.as_ref().is_some_and(|c| c.target == name);
         let blocking = is_active || state.is_awaited(name);
 
-        send_log(
-            "deck_failed",
-            &format!("deck={}, blocking={}", name, blocking)
-        );
+        emit(OutputEvent::DeckFailed {
+            deck: name,
+            blocking,
+            reason: state.deck(name).failure().unwrap_or(FailureCode::Unknown)
+        });
 
         if !blocking {
             continue;
@@ -99,11 +100,11 @@ pub fn poll_stall(state: &mut MixerState) {
         state.switch_to(target);
         state.stall = None;
 
-        send_log("auto_end_switch", target);
-        send_log(
-            "deck_changed",
-            &format!("deck={}, triggered_by=auto_gapless_stall", target)
-        );
+        emit(OutputEvent::AutoEndSwitch { deck: target });
+        emit(OutputEvent::DeckChanged {
+            deck: target,
+            trigger: DeckChangeTrigger::AutoGaplessStall
+        });
         return;
     }
 
@@ -115,7 +116,11 @@ pub fn poll_stall(state: &mut MixerState) {
                 AUTO_GAPLESS_STALL_TIMEOUT_SECS, target
             )
         );
-        send_log("deck_failed", &format!("deck={}, blocking=true", target));
+        emit(OutputEvent::DeckFailed {
+            deck: target,
+            blocking: true,
+            reason: state.deck(target).failure().unwrap_or(FailureCode::Stalled)
+        });
         // Already reported: keep `detect_failed_decks` from sending a second
         // report when the stuck download finally gives up with no samples.
         state.deck_mut(target).fail_sent = true;
@@ -161,10 +166,10 @@ pub fn poll_pending(state: &mut MixerState) {
     if is_crossfade {
         // Do NOT reset the previous deck: the fade still mixes its audio.
         state.is_playing = true;
-        send_log(
-            "crossfade_started",
-            &format!("from={}, to={}", state.active_deck, target)
-        );
+        emit(OutputEvent::CrossfadeStarted {
+            from: state.active_deck,
+            to: target
+        });
         state.crossfade = Some(Crossfade::new(target, duration_ms));
     } else {
         let previous = state.active_deck;
@@ -173,13 +178,13 @@ pub fn poll_pending(state: &mut MixerState) {
         state.is_playing = true;
         state.switch_to(target);
         send_log("info", &format!("⚡ Skip completed → deck {}", target));
-        send_log(
-            "deck_changed",
-            &format!("deck={}, triggered_by=pending_skip", target)
-        );
+        emit(OutputEvent::DeckChanged {
+            deck: target,
+            trigger: DeckChangeTrigger::PendingSkip
+        });
     }
 
-    send_log("buffer_ready", target);
+    emit(OutputEvent::BufferReady { deck: target });
 }
 
 /// Aborts a fade whose target deck never starts producing audio.
@@ -216,7 +221,11 @@ pub fn poll_crossfade_stall(state: &mut MixerState) {
             target, CROSSFADE_STALL_TIMEOUT_SECS
         )
     );
-    send_log("deck_failed", &format!("deck={}, blocking=true", target));
+    emit(OutputEvent::DeckFailed {
+        deck: target,
+        blocking: true,
+        reason: state.deck(target).failure().unwrap_or(FailureCode::Stalled)
+    });
     state.deck_mut(target).fail_sent = true;
     state.crossfade = None;
 
@@ -227,13 +236,13 @@ pub fn poll_crossfade_stall(state: &mut MixerState) {
     }
 }
 
-/// Tells Node.js when the idle deck becomes playable, on the rising edge only.
+/// Tells Node.js when an idle deck becomes playable, on the rising edge only.
 pub fn emit_buffer_ready_edges(state: &mut MixerState) {
-    for name in ["A", "B"] {
+    for name in state.deck_names() {
         let ready = state.is_ready(name);
-        // Only the idle deck is worth reporting: the active one is audibly ready.
+        // Only idle decks are worth reporting: the active one is audibly ready.
         if name != state.active_deck && ready && !state.deck(name).buffer_prev_ready {
-            send_log("buffer_ready", name);
+            emit(OutputEvent::BufferReady { deck: name });
         }
         state.deck_mut(name).buffer_prev_ready = ready;
     }
@@ -255,9 +264,11 @@ pub fn handle_track_end(state: &mut MixerState) {
     }
     state.active_mut().end_sent = true;
 
-    if state.loop_mode {
+    if state.loop_mode && state.active().can_replay() {
         state.active_mut().restart();
-        send_log("auto_loop_restart", state.active_deck);
+        emit(OutputEvent::AutoLoopRestart {
+            deck: state.active_deck
+        });
         send_log(
             "info",
             &format!("🔁 Auto-loop: deck {} restarted from cache", state.active_deck)
@@ -265,17 +276,31 @@ pub fn handle_track_end(state: &mut MixerState) {
         return;
     }
 
-    let other = state.idle_deck();
+    // A looped track too long to keep for replay: Node.js reloads it, so the
+    // next song waiting on the other deck must not take over
+    let next = if state.loop_mode { None } else { state.next_deck() };
+    let Some(other) = next else {
+        // Neither a cued deck nor an A/B partner: nowhere to go.
+        emit(OutputEvent::End {
+            deck: state.active_deck
+        });
+        send_log(
+            "debug",
+            &format!("Deck {} ended (no next deck)", state.active_deck)
+        );
+        state.is_playing = false;
+        return;
+    };
     let other_deck = state.deck(other);
     let other_samples = other_deck.available_samples();
     let other_downloading = other_deck.receiver.is_some();
-    let other_cached = other_deck.full_samples.len();
+    let other_cached = other_deck.replay.len();
     let load_started_at = other_deck.load_started_at;
 
     send_log(
         "info",
         &format!(
-            "🔍 Auto-gapless check: other deck {} → samples={}, downloading={}, has_ended={}, full_samples={}",
+            "🔍 Auto-gapless check: other deck {} → samples={}, downloading={}, has_ended={}, cached={}",
             other, other_samples, other_downloading, other_deck.has_ended, other_cached
         )
     );
@@ -285,11 +310,11 @@ pub fn handle_track_end(state: &mut MixerState) {
         let previous = state.active_deck;
         state.reset_deck(previous);
         state.switch_to(other);
-        send_log("auto_end_switch", other);
-        send_log(
-            "deck_changed",
-            &format!("deck={}, triggered_by=auto_gapless", other)
-        );
+        emit(OutputEvent::AutoEndSwitch { deck: other });
+        emit(OutputEvent::DeckChanged {
+            deck: other,
+            trigger: DeckChangeTrigger::AutoGapless
+        });
         send_log("info", &format!("⚡ Auto-gapless: instant switch → deck {}", other));
         return;
     }
@@ -305,7 +330,11 @@ pub fn handle_track_end(state: &mut MixerState) {
                     other, DOWNLOAD_STUCK_SECS
                 )
             );
-            send_log("deck_failed", &format!("deck={}, blocking=true", other));
+            emit(OutputEvent::DeckFailed {
+                deck: other,
+                blocking: true,
+                reason: state.deck(other).failure().unwrap_or(FailureCode::Stalled)
+            });
             // Already reported: don't report it again when the stuck download
             // finally gives up with no samples.
             state.deck_mut(other).fail_sent = true;
@@ -329,11 +358,13 @@ pub fn handle_track_end(state: &mut MixerState) {
 
     // Nothing queued on the other deck: the queue is over as far as the engine
     // is concerned, and Node.js decides what happens next.
-    send_log("end", state.active_deck);
+    emit(OutputEvent::End {
+        deck: state.active_deck
+    });
     send_log(
         "debug",
         &format!(
-            "Deck {} ended (no next song ready on deck {}, full_samples={})",
+            "Deck {} ended (no next song ready on deck {}, cached={})",
             state.active_deck, other, other_cached
         )
     );
@@ -22,13 +22,70 @@ import { getNextMixerGeneration } from '../state/globals.js';
 // Events printed to the console. Everything else still reaches the event
 // handler and the per-guild log file: console noise is a display concern and
 // must never decide whether an event is delivered.
-const CONSOLE_ERROR_EVENTS = new Set(['error', 'stream_error']);
-const CONSOLE_INFO_EVENTS = new Set(['info']);
+const CONSOLE_ERROR_EVENTS = new Set(['error', 'stream_error', 'decoder_error', 'command_rejected']);
+const CONSOLE_INFO_EVENTS = new Set(['info', 'download_retry', 'download_resumed']);
+
+// Event shape requested from the engine: state events arrive with typed fields
+// (deck, trigger, blocking, …) instead of a packed `data` string.
+const RUST_PROTOCOL_VERSION = 2;
+
+// Commands this controller sends. A binary that does not announce all of them
+// in its `hello` would silently drop some, so it is refused instead.
+const REQUIRED_COMMANDS = [
+  'hello', 'load', 'play', 'stop_deck', 'crossfade', 'skip_to', 'restart_deck',
+  'pause_all', 'resume_all', 'set_loop', 'set_volume', 'seek', 'set_eq', 'set_playback_rate',
+  'get_state', 'set_next', 'remove_deck', 'play_overlay', 'stop_overlay'
+];
+
+// The engine announces itself as soon as it starts: silence past this means a
+// binary that predates the handshake.
+const HELLO_TIMEOUT_MS = 5000;
+
+// Commands are applied within one 10 ms mixer tick: an ack this late means the
+// engine is gone or wedged, and the caller should fall back to later events.
+const ACK_TIMEOUT_MS = 2000;
+
+/**
+ * Text form of an event for the log file and console. Log lines carry `data`;
+ * structured events carry their own fields, written out as JSON.
+ * @param {object} log
+ * @returns {string}
+ */
+function describeEvent(log) {
+  if (log.data !== undefined) return String(log.data);
+  const fields = { ...log };
+  delete fields.event;
+  delete fields.ts_ms;
+  delete fields._mixerGeneration;
+  return JSON.stringify(fields);
+}
+
+/**
+ * Fields of a `hello` in the legacy shape, from an engine that did not take
+ * MIXER_PROTOCOL_VERSION: packed in `data` as key=value pairs, lists joined
+ * with '/'.
+ * @param {string} data
+ * @returns {{engine_version?: string, protocols: number[], commands: string[], features: string[]}}
+ */
+function legacyHelloFields(data) {
+  const fields = {};
+  for (const pair of data.split(', ')) {
+    const [key, value = ''] = pair.split('=');
+    fields[key] = value;
+  }
+  const list = value => (value ? value.split('/') : []);
+  return {
+    engine_version: fields.engine_version,
+    protocols: list(fields.protocols).map(Number),
+    commands: list(fields.commands),
+    features: list(fields.features)
+  };
+}
 
 class AudioMixerController {
   /**
    * @param {string} guildId
-   * @param {(log: {event: string, data?: string, _mixerGeneration: number}) => void} onLog - Receives every event
+   * @param {(log: {event: string, data?: string, _mixerGeneration: number}) => void} onLog - Receives every event (structured events carry their own fields instead of `data`)
    * @param {(reason: string) => void} [onCrash] - Called once when the process dies unexpectedly
    */
   constructor(guildId, onLog, onCrash = null) {
@@ -42,6 +99,14 @@ class AudioMixerController {
     this.hasCrashed = false;
     this.generation = getNextMixerGeneration(); // Unique ID for this mixer
     this.logStream = null;
+    this.engineInfo = null; // Payload of the engine's `hello`, once received
+    this.helloTimer = null;
+    this.nextRequestId = 1;
+    this.pendingAcks = new Map(); // request id -> { resolve, timer }
+    this.lastState = null; // Latest `state` event, the reply to get_state
+    this.replayDropped = new Set(); // Decks whose track outgrew the engine's replay buffer
+    this.downloadProgress = new Map(); // deck -> latest `download_progress` of its track
+    this.silenceTrimmed = new Map(); // deck -> { start, end } ms of silence the engine cut from its track
   }
 
   start() {
@@ -58,7 +123,8 @@ class AudioMixerController {
       DISCORD_BOT_PATH: ROOT_DIR,
       YTDLP_PROXY_URL: proxyUrl || 'none',
       YTDLP_COOKIE_BROWSER: cookieBrowser || 'none',
-      YTDLP_EXTRACTOR_ARGS: resolveYtDlpExtractorArgs()
+      YTDLP_EXTRACTOR_ARGS: resolveYtDlpExtractorArgs(),
+      MIXER_PROTOCOL_VERSION: String(RUST_PROTOCOL_VERSION)
     };
 
     try {
@@ -73,6 +139,10 @@ class AudioMixerController {
     this.stdoutClosed = false;
 
     this._openLogStream();
+    this.helloTimer = setTimeout(() => {
+      this.helloTimer = null;
+      if (!this.engineInfo) this._refuseEngine('no hello received (engine predates the handshake)');
+    }, HELLO_TIMEOUT_MS);
 
     const rl = readline.createInterface({ input: this.process.stderr });
     this.stderrReadline = rl;
@@ -128,8 +198,17 @@ class AudioMixerController {
     if (!log || !log.event) return;
 
     log._mixerGeneration = this.generation;
+    if (log.event === 'hello') this._handleHello(log);
+    if (log.event === 'state') this.lastState = log;
+    if (log.event === 'ack') this._settleAck(log.id, log);
+    if (log.event === 'replay_unavailable') this.replayDropped.add(log.deck);
+    if (log.event === 'download_progress') this.downloadProgress.set(log.deck, log);
+    if (log.event === 'silence_trimmed') {
+      const trimmed = { ...this.silenceTrimmedMs(log.deck), [log.edge]: log.trimmed_ms };
+      this.silenceTrimmed.set(log.deck, trimmed);
+    }
 
-    const data = log.data || '';
+    const data = describeEvent(log);
     try { this.logStream?.write(`${log.event} ${data}\n`); } catch { /* diagnostics only */ }
 
     if (CONSOLE_ERROR_EVENTS.has(log.event)) {
@@ -145,6 +224,55 @@ class AudioMixerController {
     }
   }
 
+  /**
+   * Checks the engine's capabilities and selects the protocol version.
+   * @param {{protocols?: number[], engine_version?: string, commands?: string[], data?: string}} hello
+   */
+  _handleHello(hello) {
+    if (this.helloTimer) { clearTimeout(this.helloTimer); this.helloTimer = null; }
+    if (typeof hello.data === 'string') hello = { ...hello, ...legacyHelloFields(hello.data) };
+    this.engineInfo = hello;
+
+    const protocols = Array.isArray(hello.protocols) ? hello.protocols : [];
+    const commands = new Set(Array.isArray(hello.commands) ? hello.commands : []);
+    const missing = REQUIRED_COMMANDS.filter(op => !commands.has(op));
+
+    if (!protocols.includes(RUST_PROTOCOL_VERSION)) {
+      this._refuseEngine(`protocol ${RUST_PROTOCOL_VERSION} not supported (engine offers ${protocols.join(', ') || 'none'})`);
+      return;
+    }
+    if (missing.length > 0) {
+      this._refuseEngine(`missing commands: ${missing.join(', ')}`);
+      return;
+    }
+
+    console.info(`🦀 [RUST] Engine ${hello.engine_version || '?'} ready (protocol ${RUST_PROTOCOL_VERSION})`);
+    this.send({ op: 'hello', protocol_version: RUST_PROTOCOL_VERSION });
+  }
+
+  /**
+   * Resolves the request waiting on `id`, if any.
+   * @param {number} id
+   * @param {object|null} ack - The `ack` event, or null when none will come
+   */
+  _settleAck(id, ack) {
+    const pending = this.pendingAcks.get(id);
+    if (!pending) return;
+    this.pendingAcks.delete(id);
+    clearTimeout(pending.timer);
+    pending.resolve(ack);
+  }
+
+  /**
+   * Shuts down a binary this controller cannot drive. Not reported as a
+   * crash: restarting the same binary would only fail the same way.
+   * @param {string} reason
+   */
+  _refuseEngine(reason) {
+    console.error(`❌ [RUST] Incompatible audio engine for ${this.guildId}: ${reason}`);
+    this.kill();
+  }
+
   _openLogStream() {
     this._closeLogStream();
     try {
@@ -205,19 +333,112 @@ class AudioMixerController {
     }
   }
 
-  load(url, deck, autoplay = true) { this.send({ op: 'load', url, deck, autoplay }); }
+  /**
+   * Sends a command tagged with an id and waits for the engine's `ack`.
+   * Resolves with the ack ({status: 'accepted'|'deferred'|'rejected', reason?, state}),
+   * or null when the command could not be sent or no ack arrived in time.
+   * @param {object} cmd
+   * @returns {Promise<object|null>}
+   */
+  request(cmd) {
+    const id = this.nextRequestId++;
+    if (!this.send({ ...cmd, id })) return Promise.resolve(null);
+    return new Promise(resolve => {
+      const timer = setTimeout(() => this._settleAck(id, null), ACK_TIMEOUT_MS);
+      this.pendingAcks.set(id, { resolve, timer });
+    });
+  }
+
+  /** `live` marks a stream without an end (a livestream, a radio): never cached, reconnected when it drops. */
+  load(url, deck, autoplay = true, live = false) {
+    this.replayDropped.delete(deck);
+    this.downloadProgress.delete(deck);
+    this.silenceTrimmed.delete(deck);
+    this.send({ op: 'load', url, deck, autoplay, live });
+  }
+  /** False once the track on `deck` is too long for restartDeck()/loop: reload it instead. */
+  canReplay(deck) { return !this.replayDropped.has(deck); }
+  /**
+   * Milliseconds until `deck` has `targetMs` of its track decoded, at the pace
+   * of its latest `download_progress`: 0 once it has (or its download is over,
+   * so waiting brings nothing more), null while the engine has not reported yet.
+   * `targetMs` is a playback position: the silence trimmed from the start was
+   * decoded but never plays.
+   * @param {string} deck
+   * @param {number} targetMs
+   * @returns {number|null}
+   */
+  downloadEtaMs(deck, targetMs) {
+    const progress = this.downloadProgress.get(deck);
+    if (!progress) return null;
+    const missingMs = targetMs + this.silenceTrimmedMs(deck).start - progress.decoded_ms;
+    if (missingMs <= 0 || progress.finished) return 0;
+    if (!(progress.realtime_factor > 0)) return Infinity;
+    return Math.round(missingMs / progress.realtime_factor);
+  }
+  /**
+   * Silence the engine cut from each end of the track on `deck`. Positions it
+   * reports count the trimmed track: add `start` to place them on the source's
+   * timeline (chapters, timestamps in links).
+   * @param {string} deck
+   * @returns {{start: number, end: number}}
+   */
+  silenceTrimmedMs(deck) { return this.silenceTrimmed.get(deck) || { start: 0, end: 0 }; }
+  /** True while `deck` decodes its track slower than it plays it: playback will catch up with the download. */
+  isBuffering(deck) {
+    const progress = this.downloadProgress.get(deck);
+    return !!progress && !progress.finished && progress.realtime_factor < 1;
+  }
   /** Starts `deck` from the top. To come back from a pause use resume(). */
   play(deck) { this.send({ op: 'play', deck }); }
   stopDeck(deck) { this.send({ op: 'stop_deck', deck }); }
+  /** Resolves with the ack: `deferred` means the target is still buffering. */
   crossfade(toDeck, durationMs = CROSSFADE_DURATION_MS) {
-    this.send({ op: 'crossfade', to_deck: toDeck, duration_ms: durationMs });
+    return this.request({ op: 'crossfade', to_deck: toDeck, duration_ms: durationMs });
   }
-  skipTo(targetDeck) { this.send({ op: 'skip_to', target_deck: targetDeck }); }
+  /** Resolves with the ack: `deferred` means the switch waits on the target's buffer. */
+  skipTo(targetDeck) { return this.request({ op: 'skip_to', target_deck: targetDeck }); }
   restartDeck(deck) { this.send({ op: 'restart_deck', deck }); }
+  seek(deck, positionMs) { this.send({ op: 'seek', deck, position_ms: positionMs }); }
   pause() { this.send({ op: 'pause_all' }); }
   /** Resumes exactly where pause() stopped, without restarting any deck. */
   resume() { this.send({ op: 'resume_all' }); }
   setLoop(enabled) { this.send({ op: 'set_loop', enabled }); }
+  /** Sets the gain of one deck, or of the whole output when `deck` is null. */
+  setVolume(level, deck = null, rampMs = 50) { this.send({ op: 'set_volume', deck, level, ramp_ms: rampMs }); }
+  /** Plays faster/slower; preservePitch switches from varispeed to time-stretch. Both decks when `deck` is null. */
+  setPlaybackRate(rate, preservePitch = false, deck = null) {
+    this.send({ op: 'set_playback_rate', deck, rate, preserve_pitch: preservePitch });
+  }
+  /**
+   * Cues the deck the engine switches to when the current track ends. Decks
+   * other than 'A'/'B' are opened by load(); null restores A/B alternation.
+   */
+  setNext(deck) { this.send({ op: 'set_next', deck }); }
+  /** Closes an extra deck opened by load(), freeing its slot and buffers. */
+  removeDeck(deck) { this.send({ op: 'remove_deck', deck }); }
+  /**
+   * Plays a short clip (ident, sound effect) over the music. `duck` (0-1) is the
+   * level the music dips to while the clip sounds; null leaves it untouched.
+   * The engine reports the clip id in `overlay_started` and `overlay_ended`, and
+   * refuses the clip (`command_rejected`) while playback is paused or idle.
+   */
+  playOverlay(url, { volume = 1, duck = null } = {}) { this.send({ op: 'play_overlay', url, volume, duck }); }
+  /** Stops one overlay clip, or every clip when `clip` is null. */
+  stopOverlay(clip = null) { this.send({ op: 'stop_overlay', clip }); }
+  /** Applies an EQ preset ('flat', 'bass_boost', 'vocal', 'treble_boost') or explicit band gains in dB. */
+  setEq({ preset = null, bands = null, deck = null } = {}) { this.send({ op: 'set_eq', deck, preset, bands }); }
+
+  /**
+   * Full engine snapshot (active deck, transitions, per-deck buffers and
+   * download status), or null if the engine did not answer. The `state` event
+   * is written just before the ack, so it is in place once the ack arrives.
+   * @returns {Promise<object|null>}
+   */
+  async getState() {
+    const ack = await this.request({ op: 'get_state' });
+    return ack?.status === 'accepted' ? this.lastState : null;
+  }
 
   getStdout() {
     if (!this.process || !this.isAlive) return null;
@@ -227,6 +448,8 @@ class AudioMixerController {
   kill() {
     // Prevent the close handler from invoking onCrash: kill() is always intentional
     this.hasCrashed = true;
+    if (this.helloTimer) { clearTimeout(this.helloTimer); this.helloTimer = null; }
+    for (const id of [...this.pendingAcks.keys()]) this._settleAck(id, null);
     // Close readline BEFORE killing the process
     this._closeReadline();
     this._closeLogStream('KILLED');
@@ -209,7 +209,7 @@ async function preloadNextSong(guildId) {
     const outcome = await commandQueue.run(
       guildId,
       'preload_load',
-      () => { sq.mixer.load(nextSong.url, nextDeck, false); },
+      () => { sq.mixer.load(nextSong.url, nextDeck, false, !!nextSong.isLive); },
       { timeout: 8000, retries: 1 }
     );
 
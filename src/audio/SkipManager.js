@@ -66,6 +66,20 @@ function skipFailed(reason, { retryable = false, retryAfterMs = 0 } = {}) {
 
 // ─── Helpers ────────────────────────────────────────────────
 
+/**
+ * How the engine took a fast-path switch, for the skip log. A rejection is not
+ * treated as a failure: it usually means Rust already switched on its own
+ * (auto-gapless) or a crossfade is already heading there.
+ * @param {{status: string, reason?: string}|null} ack
+ * @returns {string}
+ */
+function describeAck(ack) {
+  if (!ack) return 'preloaded';
+  if (ack.status === 'deferred') return 'preloaded, waiting for buffer';
+  if (ack.status === 'rejected') return `ignored by engine: ${ack.reason}`;
+  return 'preloaded';
+}
+
 function getOtherDeck(sq) {
   return (sq.currentDeck || 'A') === 'A' ? 'B' : 'A';
 }
@@ -160,7 +174,7 @@ async function performTransition(guildId, targetIndex, reason) {
         const outcome = await commandQueue.run(
           guildId,
           'crossfade',
-          () => { sq.mixer.crossfade(targetDeck, CROSSFADE_DURATION_MS); },
+          () => sq.mixer.crossfade(targetDeck, CROSSFADE_DURATION_MS),
           { timeout: 5000, priority: 'high' }
         );
         if (!outcome.success) {
@@ -170,7 +184,8 @@ async function performTransition(guildId, targetIndex, reason) {
           return skipFailed('command_failed');
         }
 
-        console.log(`🎚️  [SKIP] Crossfade → deck ${targetDeck} (${reason}, preloaded)`);
+        const when = describeAck(outcome.result);
+        console.log(`🎚️  [SKIP] Crossfade → deck ${targetDeck} (${reason}, ${when})`);
 
         // ⚠️  Do NOT clear the flag here with setTimeout
         // It is cleared by onSongStart(), which means the crossfade is
@@ -179,14 +194,15 @@ async function performTransition(guildId, targetIndex, reason) {
         const outcome = await commandQueue.run(
           guildId,
           'skipTo',
-          () => { sq.mixer.skipTo(targetDeck); },
+          () => sq.mixer.skipTo(targetDeck),
           { timeout: 5000, priority: 'high' }
         );
         if (!outcome.success) {
           console.error('❌ [SKIP] skipTo command failed:', outcome.error.message);
           return skipFailed('command_failed');
         }
-        console.log(`⚡ [SKIP] → deck ${targetDeck} (${reason}, preloaded)`);
+        const when = describeAck(outcome.result);
+        console.log(`⚡ [SKIP] → deck ${targetDeck} (${reason}, ${when})`);
       }
 
     } else {
@@ -206,7 +222,7 @@ async function performTransition(guildId, targetIndex, reason) {
       const outcome = await commandQueue.run(
         guildId,
         'load',
-        () => { sq.mixer.load(targetUrl, targetDeck, false); },  // autoplay: false, skipTo/crossfade activates it
+        () => { sq.mixer.load(targetUrl, targetDeck, false, !!targetSong.isLive); },  // autoplay: false, skipTo/crossfade activates it
         { timeout: 8000, priority: 'high' }
       );
       if (!outcome.success) {
@@ -128,9 +128,10 @@ async function restartCurrentSong(guildId) {
     serverQueue.currentDeck = 'A';
   }
 
-  // Download failed (no buffer_ready): replay must reload, not play silence
-  if (!serverQueue.bufferReady?.[currentDeck]) {
-    console.warn(`⚠️ [REPLAY] Deck ${currentDeck} has no buffered audio, reloading from URL`);
+  // Download failed (no buffer_ready), or the track was too long for the
+  // engine to keep: replay must reload, not play silence
+  if (!serverQueue.bufferReady?.[currentDeck] || !serverQueue.mixer.canReplay(currentDeck)) {
+    console.warn(`⚠️ [REPLAY] Deck ${currentDeck} has no replayable audio, reloading from URL`);
     serverQueue.currentDeckLoaded = null;
     await playSong(guildId);
     return true;
@@ -305,7 +306,7 @@ async function playSong(guildId, interaction = null) {
   serverQueue.bufferReady = serverQueue.bufferReady || {};
   serverQueue.bufferReady[deck] = false;
 
-  safeMixerInvoke(serverQueue, guildId, () => serverQueue.mixer.load(song.url, deck), 'load');
+  safeMixerInvoke(serverQueue, guildId, () => serverQueue.mixer.load(song.url, deck, true, !!song.isLive), 'load');
   // IMPORTANT: Delay to allow download thread to send first audio chunk
   // Without this delay, play command executes before data arrives, causing silence.
   // In replay (restartDeck) it's not needed because data is already buffered in full_samples.
@@ -16,8 +16,10 @@ import { stateVersionManager } from '../state/StateVersion.js';
 import { getNextSong, resolveDeckIndex, bindDeckSong } from '../queue/QueueManager.js';
 import { saveQueueState } from '../queue/persistence.js';
 import { sanitizeTitle } from '../utils/sanitize.js';
-import { MAX_CONSECUTIVE_PLAYBACK_FAILURES } from '../../config/index.js';
+import { FALLBACK_THUMBNAIL } from '../utils/youtube.js';
+import { CROSSFADE_DURATION_MS, MAX_CONSECUTIVE_PLAYBACK_FAILURES } from '../../config/index.js';
 import { createPlaybackErrorEmbed, refreshDashboard } from '../ui/index.js';
+import { UNKNOWN_TITLE } from '../ui/messages.js';
 import { recordSongStart, incrementSongsCompleted } from '../database/stats.js';
 
 // ─── Stream error tracking ─────────────────────────────────
@@ -29,6 +31,17 @@ import { recordSongStart, incrementSongsCompleted } from '../database/stats.js';
 const songErrors = new Map();
 const SONG_ERROR_TTL_MS = 60 * 60 * 1000; // 1 hour: transient errors are forgotten
 const STREAM_ERRORS_BEFORE_BLACKLIST = 3;
+
+// `deck_failed` reasons that will not change on a retry: the song is
+// blacklisted at once instead of after STREAM_ERRORS_BEFORE_BLACKLIST attempts.
+const PERMANENT_FAILURES = new Set(['age_restricted', 'geo_blocked', 'unavailable']);
+// Reasons that say nothing about the song itself (proxy down, YouTube asking
+// to sign in, rate limiting, a slow download): they never count towards the
+// blacklist, or one bad minute would blacklist the whole queue.
+const INFRASTRUCTURE_FAILURES = new Set([
+  'sign_in_required', 'rate_limited', 'proxy_unreachable', 'network',
+  'watchdog_timeout', 'spawn_failed', 'stalled'
+]);
 const ERROR_SWEEP_INTERVAL_MS = 30 * 60 * 1000;
 
 /**
@@ -65,9 +78,10 @@ function isFailedSong(guildId, url) {
  * Records a stream error for a song, blacklisting it after enough failures.
  * @param {string} guildId
  * @param {string} url
+ * @param {boolean} [permanent=false] - The song can never play: blacklist it now
  * @returns {boolean} true if the song has just been marked unplayable
  */
-function recordStreamError(guildId, url) {
+function recordStreamError(guildId, url, permanent = false) {
   if (!songErrors.has(guildId)) songErrors.set(guildId, new Map());
   const guildErrors = songErrors.get(guildId);
 
@@ -76,7 +90,8 @@ function recordStreamError(guildId, url) {
   entry.lastErrorAt = Date.now();
   guildErrors.set(url, entry);
 
-  if (entry.blacklistedAt !== null || entry.errors < STREAM_ERRORS_BEFORE_BLACKLIST) return false;
+  if (entry.blacklistedAt !== null) return false;
+  if (!permanent && entry.errors < STREAM_ERRORS_BEFORE_BLACKLIST) return false;
 
   entry.blacklistedAt = Date.now();
   console.error(`❌ [STREAM] Song marked as unplayable (${entry.errors} errors): ${url.substring(0, 60)}`);
@@ -135,44 +150,53 @@ async function handleBufferReady(guildId, deck) {
 // A deferred transition older than this is considered stuck, so the engine's own
 // end-of-track signals are allowed to take over again.
 const STUCK_TRANSITION_MS = 25000;
+// `approaching_end` comes this long before the end of the track.
+const APPROACHING_END_LEAD_MS = 3000;
 
 // Events the Rust engine emits and this module reacts to. Anything else it
 // sends (info, debug, stream_opened, deck_restarted, …) is logged by
 // AudioMixerController and needs no handling here.
+// Each handler receives the whole event: state events carry typed fields
+// (deck, blocking, trigger, …), log lines carry a `data` string.
 const RUST_EVENT_HANDLERS = {
-  buffer_ready: handleBufferReady,
-  stream_error: handleStreamError,
+  buffer_ready: (guildId, log) => handleBufferReady(guildId, log.deck),
+  stream_error: (guildId, log) => handleStreamError(guildId, log.data),
+  decoder_error: (guildId, log) => handleDecoderError(guildId, log.kind),
   crossfade_started: () => console.log('🎚️  [RUST] Crossfade started'),
   approaching_end: handleApproachingEnd,
   end: (guildId) => PlaybackEngine.handleTrackEnd(guildId).catch(e => {
     console.error('❌ [TRACK-END] Error in handleTrackEnd:', e);
   }),
-  auto_end_switch: (guildId, data) => handleAutoEndSwitch(guildId, data).catch(e => {
+  auto_end_switch: (guildId, log) => handleAutoEndSwitch(guildId, log.deck).catch(e => {
     console.error('❌ [AUTO-GAPLESS] Error in handleAutoEndSwitch:', e);
   }),
-  auto_loop_restart: (guildId, data) => handleAutoLoopRestart(guildId, data),
-  playback_confirmed: (guildId, data) => {
+  auto_loop_restart: (guildId, log) => handleAutoLoopRestart(guildId, log.deck),
+  playback_confirmed: (guildId, log) => {
     // The engine reported real audio: the statistics fallback it guards against
     // can no longer happen for this song, so it must not fire (and warn) later.
     PlaybackEngine.cancelPlaybackFallback(guildId);
-    return confirmPlayback(guildId, data).catch(e => {
+    return confirmPlayback(guildId, log.deck).catch(e => {
       console.error('❌ [PLAY-CONFIRM] Error in confirmPlayback:', e);
     });
   },
-  deck_failed: (guildId, data) => handleDeckFailed(guildId, data).catch(e => {
+  deck_failed: (guildId, log) => handleDeckFailed(guildId, log.deck, log.blocking === true, log.reason || 'unknown').catch(e => {
     console.error('❌ [DECK-FAILED] Error in handleDeckFailed:', e);
   }),
-  deck_changed: (guildId, data) => {
-    const match = String(data || '').match(/deck=([A-C])/);
-    PlaybackEngine.handleDeckChanged(guildId, match ? match[1] : String(data || ''));
-  },
-  error: (guildId, data) => console.error(`🦀 [RUST-${guildId}] ERROR`, data || '')
+  deck_changed: (guildId, log) => PlaybackEngine.handleDeckChanged(guildId, log.deck),
+  stream_metadata: handleStreamMetadata,
+  track_metadata: handleTrackMetadata,
+  download_progress: handleDownloadProgress,
+  silence_trimmed: handleSilenceTrimmed,
+  stream_reconnecting: (guildId, log) => console.warn(
+    `📡 [RUST-${guildId}] Deck ${log.deck}: live stream dropped, reconnecting in ${log.delay_ms}ms (attempt ${log.attempt})`
+  ),
+  error: (guildId, log) => console.error(`🦀 [RUST-${guildId}] ERROR`, log.data || '')
 };
 
 /**
  * Receives logs/events from the Rust process.
  * @param {string} guildId
- * @param {{event: string, data?: string, _mixerGeneration?: number}} log
+ * @param {{event: string, data?: string, deck?: string, _mixerGeneration?: number}} log
  */
 async function handleRustEvent(guildId, log) {
   try {
@@ -185,12 +209,98 @@ async function handleRustEvent(guildId, log) {
     }
 
     const handler = RUST_EVENT_HANDLERS[log.event];
-    if (handler) await handler(guildId, log.data);
+    if (handler) await handler(guildId, log);
   } catch (e) {
     console.error('❌ [RUST-EVENT] Error routing event:', e);
   }
 }
 
+/**
+ * A live stream announced what is on air: kept on the song for the embed.
+ * Reconnections announce the station again without a title, which keeps the last one.
+ * @param {string} guildId
+ * @param {{deck: string, station?: string|null, title?: string|null}} log
+ */
+function handleStreamMetadata(guildId, log) {
+  const sq = queue.get(guildId);
+  if (!sq || !log.title) return;
+  const index = resolveDeckIndex(sq, log.deck);
+  const song = index !== null ? sq.songs[index] : null;
+  if (!song) return;
+
+  const streamTitle = sanitizeTitle(log.title);
+  if (song.streamTitle === streamTitle) return;
+  song.streamTitle = streamTitle;
+  console.log(`📻 [RUST-${guildId}] Deck ${log.deck} on air: ${streamTitle}`);
+  if (log.deck === sq.currentDeck) refreshDashboard(sq);
+}
+
+/**
+ * The engine reported what yt-dlp knows about the track on a deck: fills in
+ * what the queue entry lacks (a flat playlist gives no duration, a bare link
+ * no title). Chapters and uploader are kept as the engine sent them.
+ * @param {string} guildId
+ * @param {{deck: string, title?: string, uploader?: string, duration_ms?: number, thumbnail?: string, chapters?: Array<{title: string, start_ms: number, end_ms: number}>}} log
+ */
+function handleTrackMetadata(guildId, log) {
+  const sq = queue.get(guildId);
+  if (!sq) return;
+  const index = resolveDeckIndex(sq, log.deck);
+  const song = index !== null ? sq.songs[index] : null;
+  if (!song) return;
+
+  if (log.duration_ms && !song.duration) song.duration = Math.round(log.duration_ms / 1000);
+  if (log.uploader) song.uploader = log.uploader;
+  if (Array.isArray(log.chapters) && log.chapters.length > 0) song.chapters = log.chapters;
+
+  let changed = false;
+  if (log.title && (!song.title || song.title === UNKNOWN_TITLE)) {
+    song.title = log.title;
+    changed = true;
+  }
+  if (log.thumbnail && (!song.thumbnail || song.thumbnail === FALLBACK_THUMBNAIL)) {
+    song.thumbnail = log.thumbnail;
+    changed = true;
+  }
+  if (changed && log.deck === sq.currentDeck) refreshDashboard(sq);
+}
+
+/**
+ * The engine cut silence from one end of the track on a deck: kept on the song,
+ * since its duration and chapters are those of the source, silence included.
+ * @param {string} guildId
+ * @param {{deck: string, edge: 'start'|'end', trimmed_ms: number}} log
+ */
+function handleSilenceTrimmed(guildId, log) {
+  const sq = queue.get(guildId);
+  if (!sq?.mixer) return;
+  const index = resolveDeckIndex(sq, log.deck);
+  const song = index !== null ? sq.songs[index] : null;
+  if (!song) return;
+
+  song.silenceTrimmedMs = sq.mixer.silenceTrimmedMs(log.deck);
+}
+
+/**
+ * The download of the current track started or stopped keeping up with
+ * playback: the dashboard footer follows. The embed reads the state from the
+ * mixer, so the dashboard is only refreshed when it changes.
+ * @param {string} guildId
+ * @param {{deck: string, realtime_factor: number}} log
+ */
+function handleDownloadProgress(guildId, log) {
+  const sq = queue.get(guildId);
+  if (!sq || !sq.mixer || log.deck !== sq.currentDeck) return;
+
+  const buffering = sq.mixer.isBuffering(log.deck);
+  if (buffering === !!sq.bufferingShown) return;
+  sq.bufferingShown = buffering;
+  if (buffering) {
+    console.warn(`🐢 [RUST-${guildId}] Deck ${log.deck} downloads slower than it plays (${log.realtime_factor.toFixed(2)}x)`);
+  }
+  refreshDashboard(sq);
+}
+
 /**
  * A deck reported a decoding error: blacklist the song after enough of them.
  * @param {string} guildId
@@ -199,7 +309,27 @@ async function handleRustEvent(guildId, log) {
 function handleStreamError(guildId, data) {
   const dataStr = String(data || '').toLowerCase();
   if (!dataStr.includes('opus') || !dataStr.includes('error')) return;
+  countStreamError(guildId);
+}
 
+/**
+ * The in-process decoder gave up on a stream. Its errors are typed, so only a
+ * corrupt stream counts towards the blacklist: an unsupported format is not
+ * the song's fault.
+ * @param {string} guildId
+ * @param {string} kind - 'unrecognized_format' | 'unsupported_codec' | 'corrupt_stream' | 'io'
+ */
+function handleDecoderError(guildId, kind) {
+  if (kind !== 'corrupt_stream') return;
+  countStreamError(guildId);
+}
+
+/**
+ * Records a decoding error against the loaded song and skips it once it has
+ * failed too often.
+ * @param {string} guildId
+ */
+function countStreamError(guildId) {
   const sq = queue.get(guildId);
   if (!sq || !sq.currentDeckLoaded) return;
 
@@ -230,7 +360,15 @@ function handleApproachingEnd(guildId) {
   const fadeEnabled = !!(sq.fadeEnabled && sq.mixer && sq.mixer.crossfade);
   const nextSong = getNextSong(sq);
 
-  if (fadeEnabled && nextSong) {
+  // A preload that will not have a fade's worth of audio by the end would stall
+  // the crossfade: the natural end switches once the deck is ready instead
+  const preloadEtaMs = fadeEnabled && sq.nextDeckTarget
+    ? sq.mixer.downloadEtaMs(sq.nextDeckTarget, CROSSFADE_DURATION_MS)
+    : null;
+
+  if (fadeEnabled && nextSong && preloadEtaMs !== null && preloadEtaMs > APPROACHING_END_LEAD_MS) {
+    console.log(`⏭️  [APPROACHING-END] Preload on deck ${sq.nextDeckTarget} not ready before the end (~${Math.round(preloadEtaMs / 1000)}s) – waiting for natural end`);
+  } else if (fadeEnabled && nextSong) {
     console.log('🎚️  [APPROACHING-END] 3s before the end – automatic crossfade');
     SkipManager.autoSkip(guildId).catch(e => {
       console.error('❌ [APPROACHING-END] autoSkip error:', e);
@@ -294,12 +432,13 @@ const MAX_SKIP_RETRIES = 3;
  * @param {object} sq - Server queue
  * @param {object|null} song - Song that failed to stream
  * @param {boolean} willSkip - true if the next song is being started
+ * @param {string} [reason] - Failure code from the engine, shown to the users when known
  */
-async function notifyPlaybackError(sq, song, willSkip) {
+async function notifyPlaybackError(sq, song, willSkip, reason) {
   try {
     const channel = sq.textChannel;
     if (!channel || !channel.send) return;
-    await channel.send({ embeds: [createPlaybackErrorEmbed(song, willSkip)] });
+    await channel.send({ embeds: [createPlaybackErrorEmbed(song, willSkip, reason)] });
   } catch (e) {
     console.warn('⚠️ [DECK-FAILED] Unable to notify the text channel:', e.message);
   }
@@ -334,29 +473,29 @@ async function skipPastFailure(guildId, targetIndex, attempt = 1) {
  * Blocking means playback is stuck on that deck: warn the users and move past
  * the song. Otherwise it is only a stale preload, silently discarded.
  * @param {string} guildId
- * @param {string} data - Event payload, "deck=<A|B>, blocking=<bool>"
+ * @param {string} deck - 'A' | 'B'
+ * @param {boolean} engineBlocking - Whether the engine reported playback stuck on it
+ * @param {string} reason - Failure code from the engine (age_restricted, network, …)
  */
-async function handleDeckFailed(guildId, data) {
+async function handleDeckFailed(guildId, deck, engineBlocking, reason) {
   const sq = queue.get(guildId);
-  if (!sq) return;
-
-  const payload = String(data || '');
-  const deck = (payload.match(/deck=([AB])/) || [])[1];
-  if (!deck) return;
+  if (!sq || !deck) return;
 
   // A deferred transition waiting on this deck is invisible to Rust, but it
   // means playback is stuck here too — including a replacement song that fails
   // right after the one it was meant to replace.
   const awaitingDeck = !!(sq.pendingTransition && sq.pendingTransition.targetDeck === deck);
-  const blocking = payload.includes('blocking=true') || awaitingDeck;
+  const blocking = engineBlocking || awaitingDeck;
 
   const failedIndex = resolveDeckIndex(sq, deck);
   const song = (failedIndex !== null && failedIndex !== undefined) ? sq.songs[failedIndex] : null;
   const title = song ? sanitizeTitle(song.title) : 'unknown song';
 
   if (!blocking) {
-    // Failed preload: drop it, the song is retried when its turn actually comes
-    console.warn(`⚠️  [DECK-FAILED] Preload on deck ${deck} received no audio ("${title}"), discarded`);
+    // Failed preload: drop it, the song is retried when its turn actually comes,
+    // unless it can never play
+    console.warn(`⚠️  [DECK-FAILED] Preload on deck ${deck} received no audio ("${title}", ${reason}), discarded`);
+    if (song && PERMANENT_FAILURES.has(reason)) recordStreamError(guildId, song.url, true);
     if (sq.nextDeckTarget === deck) {
       sq.nextDeckLoaded = null;
       sq.nextDeckTarget = null;
@@ -365,7 +504,7 @@ async function handleDeckFailed(guildId, data) {
     return;
   }
 
-  console.error(`❌ [DECK-FAILED] Deck ${deck} received no audio: "${title}"`);
+  console.error(`❌ [DECK-FAILED] Deck ${deck} received no audio: "${title}" (${reason})`);
 
   // A transition waiting on this deck will never complete: drop it
   if (sq.pendingTransition && sq.pendingTransition.targetDeck === deck) {
@@ -383,8 +522,10 @@ async function handleDeckFailed(guildId, data) {
   const giveUp = sq._consecutiveFailures >= MAX_CONSECUTIVE_PLAYBACK_FAILURES || nextIndex >= sq.songs.length;
 
   if (song) {
-    recordStreamError(guildId, song.url);
-    await notifyPlaybackError(sq, song, !giveUp);
+    if (!INFRASTRUCTURE_FAILURES.has(reason)) {
+      recordStreamError(guildId, song.url, PERMANENT_FAILURES.has(reason));
+    }
+    await notifyPlaybackError(sq, song, !giveUp, reason);
   }
 
   if (giveUp) {
@@ -7,9 +7,10 @@ import { EmbedBuilder } from 'discord.js';
 import { displayTitle } from '../utils/sanitize.js';
 import { getCurrentSong } from '../queue/QueueManager.js';
 import {
-  NO_SONGS, ADD_SONGS_TO_START, NOW_PLAYING, REQUESTED_BY,
+  NO_SONGS, ADD_SONGS_TO_START, NOW_PLAYING, REQUESTED_BY, ON_AIR, BUFFERING_FOOTER,
   QUEUE_FINISHED, QUEUE_FINISHED_HINT, LAST_PLAYED, ADD_SONGS_TO_RESTART,
-  UNKNOWN_SONG, PLAYBACK_ERROR_TITLE, PLAYBACK_ERROR_WILL_SKIP, PLAYBACK_ERROR_GAVE_UP
+  UNKNOWN_SONG, PLAYBACK_ERROR_TITLE, PLAYBACK_ERROR_WILL_SKIP, PLAYBACK_ERROR_GAVE_UP,
+  PLAYBACK_ERROR_REASON, PLAYBACK_ERROR_REASONS
 } from './messages.js';
 
 /**
@@ -47,9 +48,17 @@ function createCurrentSongEmbed(serverQueue) {
     .setThumbnail(song.thumbnail)
     .addFields({ name: REQUESTED_BY, value: `<@${song.requester}>`, inline: true });
 
+  // Now-playing title a radio station sends along with its stream
+  if (song.isLive && song.streamTitle) {
+    embed.addFields({ name: ON_AIR, value: song.streamTitle, inline: true });
+  }
+
   // Loading footer (set by SkipManager during loading)
   if (serverQueue && serverQueue.loadingFooter) {
     embed.setFooter({ text: serverQueue.loadingFooter });
+  } else if (!song.isLive && serverQueue?.mixer?.isBuffering(serverQueue.currentDeck)) {
+    // The download of the current track cannot keep up with playback
+    embed.setFooter({ text: BUFFERING_FOOTER });
   }
 
   return embed;
@@ -81,9 +90,10 @@ function createFinishedEmbed(lastSong) {
  * Creates the embed warning that a song could not be played
  * @param {Object|null} song - Song that failed to stream
  * @param {boolean} willSkip - true if the next song is being started
+ * @param {string|null} [reason] - Failure code reported by the engine
  * @returns {EmbedBuilder} Playback error embed
  */
-function createPlaybackErrorEmbed(song, willSkip = true) {
+function createPlaybackErrorEmbed(song, willSkip = true, reason = null) {
   const embed = new EmbedBuilder()
     .setColor(0xE74C3C)
     .setAuthor({ name: PLAYBACK_ERROR_TITLE })
@@ -97,6 +107,10 @@ function createPlaybackErrorEmbed(song, willSkip = true) {
     embed.setTitle(UNKNOWN_SONG);
   }
 
+  if (reason && PLAYBACK_ERROR_REASONS[reason]) {
+    embed.addFields({ name: PLAYBACK_ERROR_REASON, value: PLAYBACK_ERROR_REASONS[reason] });
+  }
+
   return embed;
 }
 
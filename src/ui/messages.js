@@ -40,6 +40,8 @@ export const NO_SONGS = '🚫 Nessuna canzone';
 export const ADD_SONGS_TO_START = 'Aggiungi una canzone per iniziare!';
 export const NOW_PLAYING = '🎶 In riproduzione';
 export const REQUESTED_BY = 'Richiesta da';
+export const ON_AIR = '📻 In onda';
+export const BUFFERING_FOOTER = '🐢 Buffering: il download è più lento della riproduzione';
 export const QUEUE_FINISHED = '🚫 Coda terminata';
 export const QUEUE_FINISHED_HINT = 'Premi 🔁 per riascoltare l\'ultima canzone';
 export const LAST_PLAYED = 'Ultima riprodotta:';
@@ -48,6 +50,21 @@ export const UNKNOWN_SONG = 'Canzone sconosciuta';
 export const PLAYBACK_ERROR_TITLE = '⚠️ Errore di riproduzione';
 export const PLAYBACK_ERROR_WILL_SKIP = 'Non è stato possibile ricevere audio per questa canzone. Passo alla successiva.';
 export const PLAYBACK_ERROR_GAVE_UP = 'Non è stato possibile ricevere audio. Troppi errori consecutivi, riproduzione interrotta.';
+export const PLAYBACK_ERROR_REASON = 'Motivo';
+// Keyed by the failure codes of the engine's `deck_failed`; unknown codes show no reason
+export const PLAYBACK_ERROR_REASONS = {
+  age_restricted: 'Video con limiti di età',
+  geo_blocked: 'Video non disponibile in questo paese',
+  unavailable: 'Video privato o rimosso',
+  sign_in_required: 'YouTube richiede l\'accesso',
+  rate_limited: 'Troppe richieste, YouTube ci sta limitando',
+  proxy_unreachable: 'Proxy non raggiungibile',
+  network: 'Errore di rete',
+  decode_failed: 'Formato audio non leggibile',
+  watchdog_timeout: 'Nessun dato ricevuto in tempo',
+  spawn_failed: 'Impossibile avviare il downloader',
+  stalled: 'Download troppo lento'
+};
 
 // ─── Lyrics ─────────────────────────────────────────────────
 export const NO_SONG_PLAYING = '❌ Nessuna canzone in riproduzione.';
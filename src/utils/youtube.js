@@ -5,18 +5,17 @@
 import { spawn } from 'child_process';
 import {
   LOCAL_TEMP_DIR,
-  VIDEO_DURATION_TIMEOUT_MS,
   VIDEO_INFO_TIMEOUT_MS,
   getYtDlpCommand
 } from '../../config/index.js';
 import { normalizeYoutubeUrl } from './sanitize.js';
 import { UNKNOWN_TITLE } from '../ui/messages.js';
 
-const DURATION_FETCH_CONCURRENCY = 3;  // Duration lookups started at the same time
 const MAX_YTDLP_CONCURRENT = 6;        // Max global yt-dlp processes (cross-guild)
 const MAX_OUTPUT_BYTES = 50 * 1024 * 1024;
 const MAX_STDERR_CHARS = 8 * 1024;     // Keep only the tail we would ever log
 
+// Also marks, for the `track_metadata` handler, a song with no real thumbnail yet
 const FALLBACK_THUMBNAIL = 'https://i.imgur.com/AfFp7pu.png';
 
 // ─── Global semaphore to limit concurrent yt-dlp processes ──────
@@ -106,65 +105,6 @@ function runYtDlp(args, timeoutMs, label) {
   });
 }
 
-/**
- * Extracts only the duration of a video (fast fallback function)
- * @param {string} videoUrl - YouTube video URL
- * @returns {Promise<number>} - Duration in seconds (0 if it fails)
- */
-async function getVideoDuration(videoUrl) {
-  if (typeof videoUrl !== 'string' || !videoUrl.trim()) return 0;
-  const url = videoUrl.startsWith('http') ? normalizeYoutubeUrl(videoUrl) : videoUrl;
-
-  return withSlot(async () => {
-    let result;
-    try {
-      result = await runYtDlp([
-        '--no-warnings',
-        '--no-cache-dir',
-        '--skip-download',
-        '--force-ipv4',
-        '--paths', `home:${LOCAL_TEMP_DIR}`,
-        '-J',
-        url
-      ], VIDEO_DURATION_TIMEOUT_MS, 'DURATION');
-    } catch {
-      return 0;
-    }
-
-    if (result.timedOut) {
-      console.warn(`⏱️ [DURATION] Timeout for ${url.substring(0, 50)}...`);
-      return 0;
-    }
-    if (result.code !== 0 && result.stderr) {
-      console.warn(`⚠️ [DURATION] yt-dlp exit code ${result.code}: ${result.stderr.substring(0, 200)}`);
-    }
-
-    try {
-      return JSON.parse(result.stdout).duration || 0;
-    } catch (e) {
-      console.warn(`⚠️ [DURATION] Parse error: ${e.message}`);
-      return 0;
-    }
-  });
-}
-
-/**
- * Fills in the duration of the songs missing one, a few at a time.
- * Runs OUTSIDE the caller's semaphore slot so the lookups cannot deadlock
- * against the request that produced them.
- * @param {Array<{url: string, duration: number}>} songs - Mutated in place
- */
-async function enrichMissingDurations(songs) {
-  const pending = songs.filter(s => !s.duration);
-  for (let i = 0; i < pending.length; i += DURATION_FETCH_CONCURRENCY) {
-    const batch = pending.slice(i, i + DURATION_FETCH_CONCURRENCY);
-    await Promise.all(batch.map(async (song) => {
-      const duration = await getVideoDuration(song.url);
-      if (duration > 0) song.duration = duration;
-    }));
-  }
-}
-
 /**
  * Maps a yt-dlp entry (playlist/search result) to a queue song.
  * @param {object} entry
@@ -193,7 +133,9 @@ async function getVideoInfo(query) {
   // This solves cases where yt-dlp with the ANDROID_MUSIC client on a single video returns "null".
   const target = query.startsWith('http') ? normalizeYoutubeUrl(query.trim()) : query.trim();
 
-  const songs = await withSlot(async () => {
+  // Flat playlist entries can lack a duration: the engine reports it with
+  // `track_metadata` once the song is loaded, instead of one yt-dlp run per song here
+  return withSlot(async () => {
     const result = await runYtDlp([
       '--flat-playlist',
       '-J',
@@ -249,10 +191,6 @@ async function getVideoInfo(query) {
     const song = toSong(info);
     return song.url ? [song] : [];
   });
-
-  await enrichMissingDurations(songs);
-  return songs;
 }
 
-// getVideoDuration only backfills durations for getVideoInfo: internal on purpose.
-export { getVideoInfo };
+export { getVideoInfo, FALLBACK_THUMBNAIL };